@@ -0,0 +1,98 @@
+// How long the engine takes to render with 8, 32 and 128 voices held down, as a percentage of how
+// long the audio lasts (so anything under 100% keeps up in real time). Run with `cargo bench`.
+//
+// There's no bench harness on stable Rust, so this just times a few seconds of audio for each
+// case and prints the result.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use gvl::audio_engine::AudioEngine;
+use gvl::midi_input_processor::{NoteEvent, TimedEvent};
+use gvl::mod_matrix::{ModDestination, ModSource};
+use gvl::parameters::Parameters;
+use gvl::transport::Transport;
+use gvl::ui_state::UiState;
+
+const SAMPLE_RATE: f32 = 48000.0;
+const BLOCK_SIZE: usize = 256;
+const SECONDS: f64 = 4.0;
+const VOICE_COUNTS: [usize; 3] = [8, 32, 128];
+
+// The default patch: one oscillator through the filter.
+fn plain(_: &Parameters, _: &UiState) {}
+
+// Four unison copies of all three oscillators plus the sub, hard synced and ring modulated, with
+// the matrix busy on every voice.
+fn unison(params: &Parameters, ui_state: &UiState) {
+    params.unison_voices.set(3.0 / 7.0);
+    params.unison_detune.set(0.4);
+    params.unison_width.set(0.7);
+    params.osc_sync.set(1.0);
+    params.ring_mod_level.set(0.3);
+    params.sub_level.set(0.4);
+    params.osc2_waveform.set(1.0 / 3.0);
+    params.osc3_level.set(0.5);
+    params.filter_env_amount.set(0.8);
+    params.filter_resonance.set(0.5);
+    let matrix = &ui_state.mod_matrix;
+    matrix.set_source(0, ModSource::Lfo1);
+    matrix.set_destination(0, ModDestination::FilterCutoff);
+    matrix.set_amount(0, 0.3);
+    matrix.set_source(1, ModSource::Lfo2);
+    matrix.set_destination(1, ModDestination::Pitch);
+    matrix.set_amount(1, 0.05);
+    matrix.set_source(2, ModSource::AmpEnvelope);
+    matrix.set_destination(2, ModDestination::UnisonDetune);
+    matrix.set_amount(2, 0.3);
+}
+
+// The FM operators instead of the oscillators, with the matrix on the FM index.
+fn fm(params: &Parameters, ui_state: &UiState) {
+    params.synth_mode.set(1.0);
+    let matrix = &ui_state.mod_matrix;
+    matrix.set_source(0, ModSource::Lfo1);
+    matrix.set_destination(0, ModDestination::FmIndex);
+    matrix.set_amount(0, 0.5);
+}
+
+// Returns the time taken as a percentage of the audio's length.
+fn bench(patch: fn(&Parameters, &UiState), voices: usize) -> f64 {
+    let params = Arc::new(Parameters::new());
+    let ui_state = Arc::new(UiState::new());
+    patch(&params, &ui_state);
+    let mut engine = AudioEngine::new(params, ui_state);
+    engine.set_sample_rate(SAMPLE_RATE);
+    engine.set_block_size(BLOCK_SIZE);
+    let transport = Transport::stopped();
+
+    // A different key for every voice, spread over the keyboard.
+    let notes: Vec<TimedEvent> = (0..voices)
+        .map(|voice| TimedEvent {
+            delta_frames: 0,
+            event: NoteEvent::NoteOn {
+                channel: 0,
+                note: (voice * 37 % 128) as u8,
+                velocity: 100,
+            },
+        })
+        .collect();
+    engine.render(BLOCK_SIZE, &notes, &transport);
+
+    let blocks = (SECONDS * SAMPLE_RATE as f64) as usize / BLOCK_SIZE;
+    let start = Instant::now();
+    for _ in 0..blocks {
+        engine.render(BLOCK_SIZE, &[], &transport);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    elapsed / (blocks * BLOCK_SIZE) as f64 * SAMPLE_RATE as f64 * 100.0
+}
+
+fn main() {
+    let patches: [(&str, fn(&Parameters, &UiState)); 3] = [("plain", plain), ("unison", unison), ("fm", fm)];
+    for &voices in &VOICE_COUNTS {
+        for &(name, patch) in &patches {
+            println!("{:3} voices, {:6}: {:6.1}% of real time", voices, name, bench(patch, voices));
+        }
+    }
+}
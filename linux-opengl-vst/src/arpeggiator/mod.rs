@@ -0,0 +1,425 @@
+use std::sync::Arc;
+
+mod pattern;
+use self::pattern::{ArpMode, HeldNote};
+
+use crate::midi_input_processor::{NoteEvent, TimedEvent};
+use crate::parameters::{choice_index, Parameters};
+use crate::transport::Transport;
+
+// Sits between the MIDI input and the audio engine. While it's on, it swallows the incoming note
+// events and plays the held notes back one at a time, in step with the host's tempo. Everything
+// else (pitch bend, pressure, all sound off...) goes straight through.
+//
+// It only depends on the events it's given and the transport, so it can be driven without a host.
+
+const MAX_OCTAVES: usize = 4;
+// Every note on every channel, since MPE controllers play each note on a channel of its own.
+const MAX_HELD: usize = 16 * 128;
+
+// Step lengths in quarter notes, in the order of the rate parameter's choices.
+pub const RATES: [f64; 6] = [1.0, 0.5, 1.0 / 3.0, 0.25, 1.0 / 6.0, 0.125];
+pub const RATE_NAMES: [&str; 6] = ["1/4", "1/8", "1/8T", "1/16", "1/16T", "1/32"];
+
+pub struct Arpeggiator {
+    params: Arc<Parameters>,
+    enabled: bool,
+    // Keys that are down right now, in the order they were pressed.
+    pressed: Vec<HeldNote>,
+    // What the arpeggio plays. The same as `pressed`, unless latch is holding on to notes.
+    held: Vec<HeldNote>,
+    pattern: Vec<HeldNote>,
+    step: usize,
+    playing: Option<HeldNote>,
+    note_off_beat: f64,
+    next_step_beat: f64,
+    // Our own clock, for when the host isn't playing. It carries on from wherever the host
+    // stopped.
+    position: f64,
+    random_state: u32,
+    sample_rate: f32,
+    // Set by `release_all()`, for the next block to pick up.
+    release_requested: bool,
+    events: Vec<TimedEvent>,
+}
+
+impl Arpeggiator {
+    pub fn new(params: Arc<Parameters>) -> Self {
+        Self {
+            params,
+            enabled: false,
+            pressed: Vec::with_capacity(MAX_HELD),
+            held: Vec::with_capacity(MAX_HELD),
+            pattern: Vec::with_capacity(MAX_HELD * MAX_OCTAVES * 2),
+            step: 0,
+            playing: None,
+            note_off_beat: 0.0,
+            next_step_beat: 0.0,
+            position: 0.0,
+            random_state: 0x2545_F491,
+            sample_rate: 44100.0,
+            release_requested: false,
+            events: Vec::with_capacity(1024),
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    // Events for the audio engine, for the block that was last processed.
+    pub fn events(&self) -> &[TimedEvent] {
+        &self.events
+    }
+
+    // Lets go of every note at the start of the next block, latched ones included. For when the
+    // host may have dropped some note-offs, like while the plugin was bypassed.
+    pub fn release_all(&mut self) {
+        self.release_requested = true;
+    }
+
+    pub fn process(&mut self, input: &[TimedEvent], num_samples: usize, transport: &Transport) {
+        self.events.clear();
+        if self.release_requested {
+            self.release_requested = false;
+            self.pressed.clear();
+            self.held.clear();
+            self.stop_note(0);
+        }
+
+        let enabled = choice_index(self.params.arp_enabled.get(), 2) == 1;
+        if enabled != self.enabled {
+            self.set_enabled(enabled);
+        }
+        if choice_index(self.params.arp_latch.get(), 2) == 0 {
+            let pressed = &self.pressed;
+            self.held.retain(|held_note| pressed.contains(held_note));
+        }
+
+        let beats_per_sample = transport.beats_per_sample(self.sample_rate);
+        let step_length = RATES[choice_index(self.params.arp_rate.get(), RATES.len())];
+        let start = match transport.position {
+            Some(position) => {
+                // The host jumped (looped, or the user moved the playhead), so line the steps
+                // back up with its grid.
+                if (position - self.position).abs() > beats_per_sample * 2.0 {
+                    self.next_step_beat = (position / step_length).ceil() * step_length;
+                }
+                position
+            }
+            None => self.position,
+        };
+        self.position = start + num_samples as f64 * beats_per_sample;
+
+        let mut input = input.iter().peekable();
+        for sample in 0..num_samples {
+            while let Some(event) = input.peek() {
+                if event.delta_frames > sample {
+                    break;
+                }
+                self.handle_event(event);
+                input.next();
+            }
+
+            if !self.enabled {
+                continue;
+            }
+
+            let beat = start + sample as f64 * beats_per_sample;
+            if self.playing.is_some() && beat >= self.note_off_beat {
+                self.stop_note(sample);
+            }
+            if !self.held.is_empty() && beat >= self.next_step_beat {
+                self.play_step(sample, beat, step_length);
+            }
+        }
+
+        // Anything the host put past the end of the block still counts.
+        for event in input {
+            self.handle_event(event);
+        }
+    }
+
+    fn handle_event(&mut self, event: &TimedEvent) {
+        match event.event {
+            NoteEvent::NoteOn { channel, note, velocity } => {
+                let latch = choice_index(self.params.arp_latch.get(), 2) == 1;
+                // With latch on, the first key after letting go of everything starts a new chord.
+                if latch && self.pressed.is_empty() {
+                    self.held.clear();
+                }
+                if self.held.is_empty() {
+                    self.step = 0;
+                }
+
+                let held_note = HeldNote { channel, note, velocity };
+                self.pressed.retain(|pressed| !pressed.is(channel, note));
+                self.pressed.push(held_note);
+                self.held.retain(|held| !held.is(channel, note));
+                self.held.push(held_note);
+            }
+            NoteEvent::NoteOff { channel, note } => {
+                let latch = choice_index(self.params.arp_latch.get(), 2) == 1;
+                self.pressed.retain(|pressed| !pressed.is(channel, note));
+                if !latch {
+                    self.held.retain(|held| !held.is(channel, note));
+                }
+                if self.enabled && self.held.is_empty() {
+                    self.stop_note(event.delta_frames);
+                }
+            }
+            NoteEvent::AllSoundOff => {
+                self.pressed.clear();
+                self.held.clear();
+                self.playing = None;
+            }
+            _ => (),
+        }
+
+        // Note events are ours while we're on; everything else goes through as it is.
+        let is_note = matches!(event.event, NoteEvent::NoteOn { .. } | NoteEvent::NoteOff { .. });
+        if !self.enabled || !is_note {
+            self.events.push(*event);
+        }
+    }
+
+    // The keys that were held before the switch are handed over cleanly either way, so nothing
+    // gets stuck or goes quiet.
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if enabled {
+            for &HeldNote { channel, note, .. } in &self.pressed {
+                let event = NoteEvent::NoteOff { channel, note };
+                self.events.push(TimedEvent { delta_frames: 0, event });
+            }
+            self.held.clear();
+            self.held.extend_from_slice(&self.pressed);
+            self.step = 0;
+        } else {
+            self.stop_note(0);
+            for &HeldNote { channel, note, velocity } in &self.pressed {
+                let event = NoteEvent::NoteOn { channel, note, velocity };
+                self.events.push(TimedEvent { delta_frames: 0, event });
+            }
+        }
+    }
+
+    fn play_step(&mut self, sample: usize, beat: f64, step_length: f64) {
+        let mode = ArpMode::from_parameter(self.params.arp_mode.get());
+        let octaves = choice_index(self.params.arp_octaves.get(), MAX_OCTAVES) + 1;
+        pattern::build(&self.held, mode, octaves, &mut self.pattern);
+
+        self.next_step_beat = ((beat / step_length).floor() + 1.0) * step_length;
+        if self.pattern.is_empty() {
+            return;
+        }
+
+        let index = match mode {
+            ArpMode::Random => self.next_random() as usize % self.pattern.len(),
+            _ => self.step % self.pattern.len(),
+        };
+        self.step += 1;
+
+        self.stop_note(sample);
+        let next = self.pattern[index];
+        self.push_event(sample, NoteEvent::NoteOn {
+            channel: next.channel,
+            note: next.note,
+            velocity: next.velocity,
+        });
+        self.playing = Some(next);
+        self.note_off_beat = beat + step_length * arp_gate(self.params.arp_gate.get());
+    }
+
+    fn stop_note(&mut self, sample: usize) {
+        if let Some(HeldNote { channel, note, .. }) = self.playing.take() {
+            self.push_event(sample, NoteEvent::NoteOff { channel, note });
+        }
+    }
+
+    fn push_event(&mut self, delta_frames: usize, event: NoteEvent) {
+        self.events.push(TimedEvent { delta_frames, event });
+    }
+
+    // xorshift32: plenty random enough for picking notes, and the same every time for the same
+    // starting state.
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.random_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_state = x;
+        x
+    }
+}
+
+// How much of each step the note is held for. A full step runs each note into the next.
+pub fn arp_gate(value: f32) -> f64 {
+    const SHORTEST: f64 = 0.05;
+
+    SHORTEST + value as f64 * (1.0 - SHORTEST)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    // A 1/16 step at 120 BPM.
+    const STEP: usize = 6000;
+
+    fn arpeggiator(mode: f32, octaves: f32, latch: bool) -> Arpeggiator {
+        let params = Arc::new(Parameters::new());
+        params.arp_enabled.set(1.0);
+        params.arp_mode.set(mode);
+        params.arp_octaves.set(octaves);
+        params.arp_rate.set(0.6);
+        params.arp_gate.set(0.5);
+        params.arp_latch.set(if latch { 1.0 } else { 0.0 });
+        let mut arpeggiator = Arpeggiator::new(params);
+        arpeggiator.set_sample_rate(SAMPLE_RATE);
+        arpeggiator
+    }
+
+    fn note_on(delta_frames: usize, channel: u8, note: u8) -> TimedEvent {
+        TimedEvent {
+            delta_frames,
+            event: NoteEvent::NoteOn { channel, note, velocity: 100 },
+        }
+    }
+
+    fn note_off(delta_frames: usize, channel: u8, note: u8) -> TimedEvent {
+        TimedEvent {
+            delta_frames,
+            event: NoteEvent::NoteOff { channel, note },
+        }
+    }
+
+    fn playing(position: f64) -> Transport {
+        Transport {
+            tempo: 120.0,
+            position: Some(position),
+        }
+    }
+
+    fn note_ons(arpeggiator: &Arpeggiator) -> Vec<(usize, u8)> {
+        arpeggiator
+            .events()
+            .iter()
+            .filter_map(|event| match event.event {
+                NoteEvent::NoteOn { note, .. } => Some((event.delta_frames, note)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn assert_on_steps(played: &[(usize, u8)], notes: &[u8]) {
+        assert_eq!(played.iter().map(|&(_, note)| note).collect::<Vec<_>>(), notes);
+        for (step, &(delta_frames, _)) in played.iter().enumerate() {
+            // Beat positions don't land exactly on samples, so allow for rounding.
+            assert!((delta_frames as i64 - (step * STEP) as i64).abs() <= 1, "{:?}", played);
+        }
+    }
+
+    #[test]
+    fn plays_up_through_the_octaves_in_time() {
+        let mut arpeggiator = arpeggiator(0.0, 1.0 / 3.0, false);
+        let input = [note_on(0, 0, 64), note_on(0, 0, 60), note_on(0, 0, 67)];
+        arpeggiator.process(&input, 6 * STEP, &playing(0.0));
+
+        assert_on_steps(&note_ons(&arpeggiator), &[60, 64, 67, 72, 76, 79]);
+        let first_note_off = arpeggiator
+            .events()
+            .iter()
+            .find(|event| matches!(event.event, NoteEvent::NoteOff { .. }))
+            .unwrap();
+        let gate_length = (STEP as f64 * arp_gate(0.5)) as i64;
+        assert!((first_note_off.delta_frames as i64 - gate_length).abs() <= 1);
+    }
+
+    #[test]
+    fn as_played_keeps_the_order_keys_went_down() {
+        let mut arpeggiator = arpeggiator(1.0, 0.0, false);
+        let input = [note_on(0, 0, 64), note_on(0, 0, 60), note_on(0, 0, 67)];
+        arpeggiator.process(&input, 4 * STEP, &playing(0.0));
+
+        assert_on_steps(&note_ons(&arpeggiator), &[64, 60, 67, 64]);
+    }
+
+    #[test]
+    fn steps_follow_the_host_across_blocks() {
+        let mut arpeggiator = arpeggiator(0.0, 0.0, false);
+        arpeggiator.process(&[note_on(0, 0, 60), note_on(0, 0, 62)], STEP / 2, &playing(0.0));
+        assert_eq!(note_ons(&arpeggiator), vec![(0, 60)]);
+        arpeggiator.process(&[], STEP, &playing(0.125));
+        let played = note_ons(&arpeggiator);
+        assert_eq!(played.len(), 1);
+        assert_eq!(played[0].1, 62);
+        assert!((played[0].0 as i64 - (STEP / 2) as i64).abs() <= 1);
+    }
+
+    #[test]
+    fn letting_go_stops_the_arpeggio_without_latch() {
+        let mut arpeggiator = arpeggiator(0.0, 0.0, false);
+        let input = [note_on(0, 0, 60), note_off(STEP / 4, 0, 60)];
+        arpeggiator.process(&input, 2 * STEP, &playing(0.0));
+
+        assert_eq!(note_ons(&arpeggiator), vec![(0, 60)]);
+        assert_eq!(arpeggiator.events().last().unwrap().event, NoteEvent::NoteOff { channel: 0, note: 60 });
+    }
+
+    #[test]
+    fn latch_holds_the_chord_until_the_next_one() {
+        let mut arpeggiator = arpeggiator(0.0, 0.0, true);
+        let input = [note_on(0, 0, 60), note_on(0, 0, 64), note_off(10, 0, 60), note_off(10, 0, 64)];
+        arpeggiator.process(&input, 3 * STEP, &playing(0.0));
+        assert_on_steps(&note_ons(&arpeggiator), &[60, 64, 60]);
+
+        arpeggiator.process(&[note_on(0, 0, 67)], 2 * STEP, &playing(0.75));
+        assert_on_steps(&note_ons(&arpeggiator), &[67, 67]);
+    }
+
+    #[test]
+    fn release_all_lets_go_of_a_latched_chord() {
+        let mut arpeggiator = arpeggiator(0.0, 0.0, true);
+        let input = [note_on(0, 0, 60), note_on(0, 0, 64), note_off(10, 0, 60), note_off(10, 0, 64)];
+        arpeggiator.process(&input, 2 * STEP + STEP / 4, &playing(0.0));
+        assert_on_steps(&note_ons(&arpeggiator), &[60, 64, 60]);
+
+        arpeggiator.release_all();
+        arpeggiator.process(&[], 2 * STEP, &playing(0.5625));
+        let events: Vec<(usize, NoteEvent)> =
+            arpeggiator.events().iter().map(|event| (event.delta_frames, event.event)).collect();
+        assert_eq!(events, vec![(0, NoteEvent::NoteOff { channel: 0, note: 60 })]);
+    }
+
+    #[test]
+    fn same_note_on_two_channels_is_held_separately() {
+        let mut arpeggiator = arpeggiator(1.0, 0.0, false);
+        let input = [note_on(0, 1, 60), note_on(0, 2, 60), note_off(10, 2, 60)];
+        arpeggiator.process(&input, 2 * STEP, &playing(0.0));
+
+        let channels: Vec<u8> = arpeggiator
+            .events()
+            .iter()
+            .filter_map(|event| match event.event {
+                NoteEvent::NoteOn { channel, .. } => Some(channel),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(channels, vec![1, 1]);
+    }
+
+    #[test]
+    fn holding_every_key_on_every_channel_never_allocates() {
+        let mut arpeggiator = arpeggiator(0.0, 1.0, false);
+        let capacities = (arpeggiator.held.capacity(), arpeggiator.pattern.capacity());
+        let input: Vec<TimedEvent> = (0..16)
+            .flat_map(|channel| (0..128).map(move |note| note_on(0, channel, note)))
+            .collect();
+        arpeggiator.process(&input, 1, &playing(0.0));
+
+        assert_eq!(arpeggiator.held.len(), MAX_HELD);
+        assert_eq!((arpeggiator.held.capacity(), arpeggiator.pattern.capacity()), capacities);
+    }
+}
@@ -0,0 +1,217 @@
+// A plain ADSR envelope. Attack is linear; decay and release are exponential, which sounds a lot
+// more natural for amplitude.
+
+use super::voice::VOICE_LANES;
+
+// Below this, a releasing envelope counts as finished.
+const SILENCE: f64 = 0.0001;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+pub struct Envelope {
+    stage: Stage,
+    level: f64,
+    attack: f64,  // seconds
+    decay: f64,   // seconds
+    sustain: f64, // level, 0.0 - 1.0
+    release: f64, // seconds
+}
+
+impl Envelope {
+    pub fn new(attack: f64, decay: f64, sustain: f64, release: f64) -> Self {
+        Self {
+            stage: Stage::Idle,
+            level: 0.0,
+            attack,
+            decay,
+            sustain,
+            release,
+        }
+    }
+
+    // For envelopes whose times come from parameters. Takes effect straight away, even partway
+    // through a stage.
+    pub fn set_adsr(&mut self, attack: f64, decay: f64, sustain: f64, release: f64) {
+        self.attack = attack;
+        self.decay = decay;
+        self.sustain = sustain;
+        self.release = release;
+    }
+
+    // Starts the attack from wherever the level currently is, so retriggering a sounding voice
+    // doesn't click.
+    pub fn trigger(&mut self) {
+        self.stage = Stage::Attack;
+    }
+
+    pub fn release(&mut self) {
+        if self.stage != Stage::Idle {
+            self.stage = Stage::Release;
+        }
+    }
+
+    // Straight to silence, no release.
+    pub fn kill(&mut self) {
+        self.stage = Stage::Idle;
+        self.level = 0.0;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.stage != Stage::Idle
+    }
+
+    // True from the trigger until the release starts, i.e. while the "key" is down.
+    pub fn is_gate_open(&self) -> bool {
+        match self.stage {
+            Stage::Attack | Stage::Decay | Stage::Sustain => true,
+            _ => false,
+        }
+    }
+
+    pub fn next_sample(&mut self, sample_rate: f32) -> f64 {
+        let sample_rate = sample_rate as f64;
+        let rate = match self.stage {
+            Stage::Attack => attack_step(self.attack, sample_rate),
+            Stage::Decay => decay_coefficient(self.decay, sample_rate),
+            Stage::Release => decay_coefficient(self.release, sample_rate),
+            Stage::Idle | Stage::Sustain => 0.0,
+        };
+        advance(&mut self.stage, &mut self.level, self.sustain, rate);
+        self.level
+    }
+}
+
+// One envelope per voice lane, all with the same settings.
+pub struct EnvelopeLanes {
+    stages: [Stage; VOICE_LANES],
+    levels: [f64; VOICE_LANES],
+    attack: f64,
+    decay: f64,
+    sustain: f64,
+    release: f64,
+}
+
+impl EnvelopeLanes {
+    pub fn new(attack: f64, decay: f64, sustain: f64, release: f64) -> Self {
+        Self {
+            stages: [Stage::Idle; VOICE_LANES],
+            levels: [0.0; VOICE_LANES],
+            attack,
+            decay,
+            sustain,
+            release,
+        }
+    }
+
+    pub fn set_adsr(&mut self, attack: f64, decay: f64, sustain: f64, release: f64) {
+        self.attack = attack;
+        self.decay = decay;
+        self.sustain = sustain;
+        self.release = release;
+    }
+
+    pub fn trigger(&mut self, lane: usize) {
+        self.stages[lane] = Stage::Attack;
+    }
+
+    pub fn release(&mut self, lane: usize) {
+        if self.stages[lane] != Stage::Idle {
+            self.stages[lane] = Stage::Release;
+        }
+    }
+
+    pub fn kill(&mut self, lane: usize) {
+        self.stages[lane] = Stage::Idle;
+        self.levels[lane] = 0.0;
+    }
+
+    pub fn is_active(&self, lane: usize) -> bool {
+        self.stages[lane] != Stage::Idle
+    }
+
+    pub fn is_gate_open(&self, lane: usize) -> bool {
+        self.stages[lane] != Stage::Idle && self.stages[lane] != Stage::Release
+    }
+
+    // Fills `outputs` with every lane's level, sample by sample. Each lane only moves for its first
+    // `lengths[lane]` samples, and stops for good once it's idle. Returns how many samples each
+    // lane was active for.
+    pub fn render(
+        &mut self,
+        lengths: [usize; VOICE_LANES],
+        sample_rate: f32,
+        outputs: &mut [[f64; VOICE_LANES]],
+    ) -> [usize; VOICE_LANES] {
+        // Indexed by stage. The settings are the same all block, so these only need working out
+        // once.
+        let sample_rate = sample_rate as f64;
+        let mut rates = [0.0; 5];
+        rates[Stage::Attack as usize] = attack_step(self.attack, sample_rate);
+        rates[Stage::Decay as usize] = decay_coefficient(self.decay, sample_rate);
+        rates[Stage::Release as usize] = decay_coefficient(self.release, sample_rate);
+
+        let mut active = [0; VOICE_LANES];
+        for (index, output) in outputs.iter_mut().enumerate() {
+            for lane in 0..VOICE_LANES {
+                let stage = &mut self.stages[lane];
+                if index < lengths[lane] && *stage != Stage::Idle {
+                    let rate = rates[*stage as usize];
+                    advance(stage, &mut self.levels[lane], self.sustain, rate);
+                    active[lane] = index + 1;
+                }
+                output[lane] = self.levels[lane];
+            }
+        }
+        active
+    }
+}
+
+// One sample of an envelope. `rate` is what the stage it's in moves by: the step for the attack,
+// or the coefficient for the decay and release.
+fn advance(stage: &mut Stage, level: &mut f64, sustain: f64, rate: f64) {
+    match *stage {
+        Stage::Idle => {}
+        Stage::Attack => {
+            *level += rate;
+            if *level >= 1.0 {
+                *level = 1.0;
+                *stage = Stage::Decay;
+            }
+        }
+        Stage::Decay => {
+            *level = sustain + (*level - sustain) * rate;
+            if (*level - sustain).abs() < SILENCE {
+                *level = sustain;
+                *stage = Stage::Sustain;
+            }
+        }
+        Stage::Sustain => {
+            *level = sustain;
+        }
+        Stage::Release => {
+            *level *= rate;
+            if *level < SILENCE {
+                *stage = Stage::Idle;
+                *level = 0.0;
+            }
+        }
+    }
+}
+
+fn attack_step(time: f64, sample_rate: f64) -> f64 {
+    1.0 / (time * sample_rate).max(1.0)
+}
+
+// Per-sample multiplier that gets an exponential segment to within -80 dB of its target in
+// `time` seconds.
+fn decay_coefficient(time: f64, sample_rate: f64) -> f64 {
+    let samples = (time * sample_rate).max(1.0);
+    (SILENCE.ln() / samples).exp()
+}
@@ -0,0 +1,319 @@
+use std::f64::consts::PI;
+
+use super::voice::VOICE_LANES;
+use crate::parameters::choice_index;
+
+// A state-variable filter, in the trapezoidal ("zero delay feedback") form from Andrew Simper's
+// "Linear Trap Integrated SVF" paper. Unlike the classic Chamberlin SVF it stays stable and in tune
+// all the way up to Nyquist, and it can be swept every sample without zipper noise or blowing up.
+
+// Lowest and highest cutoff the parameter reaches, before key tracking and the envelope.
+const MIN_CUTOFF: f64 = 20.0;
+const MAX_CUTOFF: f64 = 20000.0;
+// Keep the cutoff a little way under Nyquist, where tan() heads off to infinity.
+const MAX_CUTOFF_RATIO: f64 = 0.49;
+// Resonance goes from Q = 0.5 (no peak) to about Q = 50 (close to self-oscillation).
+const MIN_DAMPING: f64 = 0.02;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterMode {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+impl FilterMode {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 4) {
+            0 => FilterMode::LowPass,
+            1 => FilterMode::HighPass,
+            2 => FilterMode::BandPass,
+            _ => FilterMode::Notch,
+        }
+    }
+}
+
+// One filter per voice lane. The lanes are kept side by side, like the oscillators' unison
+// copies, and run together one sample at a time.
+pub struct StateVariableFilter {
+    // The two integrators' states.
+    ic1eq: [f64; VOICE_LANES],
+    ic2eq: [f64; VOICE_LANES],
+}
+
+impl StateVariableFilter {
+    pub fn new() -> Self {
+        Self {
+            ic1eq: [0.0; VOICE_LANES],
+            ic2eq: [0.0; VOICE_LANES],
+        }
+    }
+
+    pub fn reset(&mut self, lane: usize) {
+        self.ic1eq[lane] = 0.0;
+        self.ic2eq[lane] = 0.0;
+    }
+
+    // One sample for every lane, each with its own coefficients. Lanes that aren't `active` still
+    // work out an output, but keep their state as it was.
+    pub fn process(
+        &mut self,
+        inputs: &[f64; VOICE_LANES],
+        mode: FilterMode,
+        coefficients: &FilterCoefficients,
+        active: &[bool; VOICE_LANES],
+    ) -> [f64; VOICE_LANES] {
+        let mut outputs = [0.0; VOICE_LANES];
+        for lane in 0..VOICE_LANES {
+            let (k, a1, a2, a3) = (
+                coefficients.k[lane],
+                coefficients.a1[lane],
+                coefficients.a2[lane],
+                coefficients.a3[lane],
+            );
+            let (ic1eq, ic2eq) = (self.ic1eq[lane], self.ic2eq[lane]);
+            let input = inputs[lane];
+
+            let v3 = input - ic2eq;
+            let v1 = a1 * ic1eq + a2 * v3;
+            let v2 = ic2eq + a2 * ic1eq + a3 * v3;
+            if active[lane] {
+                self.ic1eq[lane] = 2.0 * v1 - ic1eq;
+                self.ic2eq[lane] = 2.0 * v2 - ic2eq;
+            }
+
+            let low = v2;
+            let band = v1;
+            let high = input - k * band - low;
+            outputs[lane] = match mode {
+                FilterMode::LowPass => low,
+                FilterMode::HighPass => high,
+                FilterMode::BandPass => band,
+                FilterMode::Notch => low + high,
+            };
+        }
+        outputs
+    }
+}
+
+// A cutoff and resonance for each lane, worked out into what the filter uses. Both sides of a
+// voice share them.
+pub struct FilterCoefficients {
+    k: [f64; VOICE_LANES],
+    a1: [f64; VOICE_LANES],
+    a2: [f64; VOICE_LANES],
+    a3: [f64; VOICE_LANES],
+}
+
+impl FilterCoefficients {
+    pub fn new() -> Self {
+        Self {
+            k: [0.0; VOICE_LANES],
+            a1: [0.0; VOICE_LANES],
+            a2: [0.0; VOICE_LANES],
+            a3: [0.0; VOICE_LANES],
+        }
+    }
+
+    // `resonance` is 0.0 - 1.0.
+    pub fn set(&mut self, lane: usize, cutoff: f64, resonance: f64, sample_rate: f32) {
+        let sample_rate = sample_rate as f64;
+        let cutoff = cutoff.max(MIN_CUTOFF).min(sample_rate * MAX_CUTOFF_RATIO);
+        let g = (PI * cutoff / sample_rate).tan();
+        let k = 2.0 - (2.0 - MIN_DAMPING) * resonance.max(0.0).min(1.0);
+
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+        self.k[lane] = k;
+        self.a1[lane] = a1;
+        self.a2[lane] = a2;
+        self.a3[lane] = a3;
+    }
+}
+
+// Exponential, so each octave gets the same amount of the slider.
+pub fn filter_cutoff_hz(value: f32) -> f64 {
+    MIN_CUTOFF * (MAX_CUTOFF / MIN_CUTOFF).powf(value as f64)
+}
+
+// How far the envelope at full level moves the cutoff: up to 6 octaves, either way.
+pub fn filter_env_octaves(value: f32) -> f64 {
+    const MAX_OCTAVES: f64 = 6.0;
+
+    (value as f64 - 0.5) * 2.0 * MAX_OCTAVES
+}
+
+// For the filter envelope's attack, decay and release.
+pub fn envelope_time_seconds(value: f32) -> f64 {
+    const MAX_TIME: f64 = 10.0;
+
+    value as f64 * value as f64 * MAX_TIME
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATES: [f32; 3] = [44100.0, 48000.0, 96000.0];
+    const CUTOFF: f64 = 1000.0;
+
+    // Runs every lane on the same input and settings, and returns the first.
+    fn process(
+        filter: &mut StateVariableFilter,
+        input: f64,
+        mode: FilterMode,
+        cutoff: f64,
+        resonance: f64,
+        sample_rate: f32,
+    ) -> f64 {
+        let mut coefficients = FilterCoefficients::new();
+        for lane in 0..VOICE_LANES {
+            coefficients.set(lane, cutoff, resonance, sample_rate);
+        }
+        filter.process(&[input; VOICE_LANES], mode, &coefficients, &[true; VOICE_LANES])[0]
+    }
+
+    // Steady-state gain for a sine at `frequency`, measured by correlating the output with sine
+    // and cosine once the filter has settled.
+    fn gain(mode: FilterMode, frequency: f64, resonance: f64, sample_rate: f32) -> f64 {
+        let mut filter = StateVariableFilter::new();
+        let settle = sample_rate as usize;
+        let measure = sample_rate as usize / 2;
+        let (mut sine_sum, mut cosine_sum) = (0.0, 0.0);
+        for index in 0..settle + measure {
+            let phase = 2.0 * PI * frequency * index as f64 / sample_rate as f64;
+            let output = process(&mut filter, phase.sin(), mode, CUTOFF, resonance, sample_rate);
+            if index >= settle {
+                sine_sum += output * phase.sin();
+                cosine_sum += output * phase.cos();
+            }
+        }
+        2.0 * (sine_sum * sine_sum + cosine_sum * cosine_sum).sqrt() / measure as f64
+    }
+
+    fn db(gain: f64) -> f64 {
+        20.0 * gain.log10()
+    }
+
+    // The resonance that gives a Q of 1/sqrt(2): flat as it gets, and 3 dB down at the cutoff.
+    fn butterworth_resonance() -> f64 {
+        (2.0 - 2.0f64.sqrt()) / (2.0 - MIN_DAMPING)
+    }
+
+    #[test]
+    fn butterworth_is_3_db_down_at_the_cutoff() {
+        for &sample_rate in &SAMPLE_RATES {
+            for &mode in &[FilterMode::LowPass, FilterMode::HighPass] {
+                let at_cutoff = db(gain(mode, CUTOFF, butterworth_resonance(), sample_rate));
+                assert!((at_cutoff + 3.01).abs() < 0.05, "{:?} at {}: {} dB", mode, sample_rate, at_cutoff);
+            }
+        }
+    }
+
+    #[test]
+    fn resonance_sets_the_gain_at_the_cutoff() {
+        for &sample_rate in &SAMPLE_RATES {
+            for &resonance in &[0.0, 0.5, 0.9] {
+                // Q, which is 1/k.
+                let expected = 1.0 / (2.0 - (2.0 - MIN_DAMPING) * resonance);
+                for &mode in &[FilterMode::LowPass, FilterMode::HighPass, FilterMode::BandPass] {
+                    let at_cutoff = gain(mode, CUTOFF, resonance, sample_rate);
+                    assert!(
+                        (db(at_cutoff) - db(expected)).abs() < 0.05,
+                        "{:?} at {} with resonance {}: {} instead of {}",
+                        mode,
+                        sample_rate,
+                        resonance,
+                        at_cutoff,
+                        expected
+                    );
+                }
+                assert!(db(gain(FilterMode::Notch, CUTOFF, resonance, sample_rate)) < -40.0);
+            }
+        }
+    }
+
+    #[test]
+    fn passband_and_stopband() {
+        let resonance = butterworth_resonance();
+        for &sample_rate in &SAMPLE_RATES {
+            let low = CUTOFF / 10.0;
+            let high = CUTOFF * 10.0;
+            assert!(db(gain(FilterMode::LowPass, low, resonance, sample_rate)).abs() < 0.1);
+            assert!(db(gain(FilterMode::LowPass, high, resonance, sample_rate)) < -40.0);
+            assert!(db(gain(FilterMode::HighPass, high, resonance, sample_rate)).abs() < 0.1);
+            assert!(db(gain(FilterMode::HighPass, low, resonance, sample_rate)) < -40.0);
+            assert!(db(gain(FilterMode::BandPass, low, resonance, sample_rate)) < -15.0);
+            assert!(db(gain(FilterMode::BandPass, high, resonance, sample_rate)) < -15.0);
+            assert!(db(gain(FilterMode::Notch, low, resonance, sample_rate)).abs() < 0.1);
+            assert!(db(gain(FilterMode::Notch, high, resonance, sample_rate)).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn stays_stable_at_high_cutoff_and_resonance() {
+        for &sample_rate in &SAMPLE_RATES {
+            for &mode in &[FilterMode::LowPass, FilterMode::HighPass, FilterMode::BandPass, FilterMode::Notch] {
+                let mut filter = StateVariableFilter::new();
+                let mut noise = 0x1234_5678u32;
+                for index in 0..sample_rate as usize * 2 {
+                    noise ^= noise << 13;
+                    noise ^= noise >> 17;
+                    noise ^= noise << 5;
+                    let input = noise as f64 / u32::MAX as f64 * 2.0 - 1.0;
+                    // Past Nyquist for the first second, then swept hard across the whole range.
+                    let cutoff = if index < sample_rate as usize {
+                        MAX_CUTOFF * 2.0
+                    } else {
+                        MIN_CUTOFF + (index % 64) as f64 / 63.0 * MAX_CUTOFF
+                    };
+                    let output = process(&mut filter, input, mode, cutoff, 1.0, sample_rate);
+                    assert!(output.is_finite() && output.abs() < 200.0, "{:?} at {}: {}", mode, sample_rate, output);
+                }
+
+                // And it rings down once the input stops.
+                let mut output = 0.0;
+                for _ in 0..sample_rate as usize {
+                    output = process(&mut filter, 0.0, mode, MAX_CUTOFF, 1.0, sample_rate);
+                }
+                assert!(output.abs() < 1e-6, "{:?} at {}: {}", mode, sample_rate, output);
+            }
+        }
+    }
+
+    #[test]
+    fn lanes_are_independent() {
+        let sample_rate = 48000.0;
+        let mode = FilterMode::LowPass;
+        let mut coefficients = FilterCoefficients::new();
+        for lane in 0..VOICE_LANES {
+            coefficients.set(lane, CUTOFF, 0.5, sample_rate);
+        }
+        coefficients.set(1, CUTOFF * 4.0, 0.5, sample_rate);
+        let mut active = [true; VOICE_LANES];
+        active[2] = false;
+        let mut inputs = [1.0; VOICE_LANES];
+        inputs[3] = 0.0;
+
+        let mut filter = StateVariableFilter::new();
+        let first = filter.process(&inputs, mode, &coefficients, &active);
+        let second = filter.process(&inputs, mode, &coefficients, &active);
+        // Lane 0 comes out the same as it does with every lane doing the same thing.
+        let mut alone = StateVariableFilter::new();
+        process(&mut alone, 1.0, mode, CUTOFF, 0.5, sample_rate);
+        assert_eq!(second[0], process(&mut alone, 1.0, mode, CUTOFF, 0.5, sample_rate));
+        // A higher cutoff lets the step through faster.
+        assert!(second[1] > second[0]);
+        // A lane that isn't active doesn't move on, so it gives the same output again.
+        assert_eq!(first[2], second[2]);
+        assert_eq!(second[3], 0.0);
+
+        // Resetting one lane leaves the rest alone.
+        filter.reset(0);
+        let third = filter.process(&inputs, mode, &coefficients, &active);
+        assert_eq!(third[0], first[0]);
+        assert!(third[4] > second[4]);
+    }
+}
@@ -0,0 +1,119 @@
+use super::voice::{Lanes, VOICE_LANES};
+use crate::parameters::choice_index;
+
+// Portamento. Pitch moves in a straight line in log-frequency space, so a glide sounds the same
+// speed all the way, whatever the interval.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlideCurve {
+    // Every glide takes the glide time, however far it has to go.
+    ConstantTime,
+    // The glide time is per octave, so bigger jumps take longer.
+    ConstantRate,
+}
+
+impl GlideCurve {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 2) {
+            0 => GlideCurve::ConstantTime,
+            _ => GlideCurve::ConstantRate,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlideMode {
+    Always,
+    // Only glide when the new note overlaps one that's still held.
+    LegatoOnly,
+}
+
+impl GlideMode {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 2) {
+            0 => GlideMode::Always,
+            _ => GlideMode::LegatoOnly,
+        }
+    }
+}
+
+// Where a glide starts from and how it gets to its note.
+#[derive(Clone, Copy, Debug)]
+pub struct Portamento {
+    pub from: f64, // Hz
+    pub time: f64, // seconds
+    pub curve: GlideCurve,
+}
+
+// A glide for every voice in a group, a lane each.
+pub struct GlideLanes {
+    // All in log2(Hz)
+    current: Lanes,
+    target: Lanes,
+    steps: Lanes, // per sample
+    // `current` in Hz, which only needs working out again when it moves.
+    frequencies: Lanes,
+}
+
+impl GlideLanes {
+    pub fn new() -> Self {
+        Self {
+            current: [0.0; VOICE_LANES],
+            target: [0.0; VOICE_LANES],
+            steps: [0.0; VOICE_LANES],
+            frequencies: [1.0; VOICE_LANES],
+        }
+    }
+
+    pub fn jump_to(&mut self, lane: usize, frequency: f64) {
+        self.current[lane] = frequency.log2();
+        self.target[lane] = self.current[lane];
+        self.steps[lane] = 0.0;
+        self.frequencies[lane] = self.current[lane].exp2();
+    }
+
+    pub fn glide_to(&mut self, lane: usize, frequency: f64, portamento: Portamento, sample_rate: f32) {
+        self.current[lane] = portamento.from.log2();
+        self.target[lane] = frequency.log2();
+
+        let octaves = (self.target[lane] - self.current[lane]).abs();
+        let seconds = match portamento.curve {
+            GlideCurve::ConstantTime => portamento.time,
+            GlideCurve::ConstantRate => portamento.time * octaves,
+        };
+        let samples = seconds * sample_rate as f64;
+
+        if samples < 1.0 {
+            self.current[lane] = self.target[lane];
+            self.steps[lane] = 0.0;
+        } else {
+            self.steps[lane] = (self.target[lane] - self.current[lane]) / samples;
+        }
+        self.frequencies[lane] = self.current[lane].exp2();
+    }
+
+    // Where the pitch is right now, which might be partway through a glide.
+    pub fn frequency(&self, lane: usize) -> f64 {
+        self.frequencies[lane]
+    }
+
+    // Moves the lanes in `active` on by a sample. The others stay where they are.
+    pub fn next_frequencies(&mut self, active: &[bool; VOICE_LANES]) -> Lanes {
+        for (lane, active) in active.iter().enumerate() {
+            let step = self.steps[lane];
+            if !active || step == 0.0 {
+                continue;
+            }
+            let current = self.current[lane] + step;
+            let target = self.target[lane];
+            if (step > 0.0 && current >= target) || (step < 0.0 && current <= target) {
+                self.current[lane] = target;
+                self.steps[lane] = 0.0;
+            } else {
+                self.current[lane] = current;
+            }
+            self.frequencies[lane] = self.current[lane].exp2();
+        }
+        self.frequencies
+    }
+}
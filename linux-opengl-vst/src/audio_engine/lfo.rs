@@ -0,0 +1,199 @@
+use std::f64::consts::PI;
+
+use crate::parameters::choice_index;
+
+// Low-frequency oscillators for the modulation matrix. The engine runs one for everything (LFO 1)
+// and each voice has its own (LFO 2). Output is -1.0 - 1.0.
+
+const MIN_RATE: f64 = 0.02; // Hz
+const MAX_RATE: f64 = 50.0;
+
+// Cycle lengths in quarter notes, in the order of the rate parameter's choices when synced.
+const SYNC_RATES: [f64; 11] = [32.0, 16.0, 8.0, 4.0, 2.0, 1.0, 0.5, 1.0 / 3.0, 0.25, 1.0 / 6.0, 0.125];
+const SYNC_RATE_NAMES: [&str; 11] = [
+    "8 bars", "4 bars", "2 bars", "1 bar", "1/2", "1/4", "1/8", "1/8T", "1/16", "1/16T", "1/32",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    SawUp,
+    SawDown,
+    Square,
+    SampleAndHold,
+}
+
+impl LfoShape {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 6) {
+            0 => LfoShape::Sine,
+            1 => LfoShape::Triangle,
+            2 => LfoShape::SawUp,
+            3 => LfoShape::SawDown,
+            4 => LfoShape::Square,
+            _ => LfoShape::SampleAndHold,
+        }
+    }
+}
+
+// One LFO's parameters, read once for a block (or a sample).
+#[derive(Clone, Copy, Debug)]
+pub struct LfoSettings {
+    pub shape: LfoShape,
+    // With sync on, the rate parameter picks a note length instead of a frequency.
+    pub sync: bool,
+    pub rate: f32,
+    // Restart the cycle on every note.
+    pub retrigger: bool,
+}
+
+impl LfoSettings {
+    pub fn new(shape: f32, rate: f32, sync: f32, retrigger: f32) -> Self {
+        Self {
+            shape: LfoShape::from_parameter(shape),
+            sync: choice_index(sync, 2) == 1,
+            rate,
+            retrigger: choice_index(retrigger, 2) == 1,
+        }
+    }
+
+    // Cycles per second, at this tempo.
+    pub fn frequency(&self, tempo: f64) -> f64 {
+        if self.sync {
+            tempo / 60.0 / self.cycle_beats()
+        } else {
+            lfo_rate_hz(self.rate)
+        }
+    }
+
+    // Quarter notes per cycle, when synced.
+    pub fn cycle_beats(&self) -> f64 {
+        SYNC_RATES[choice_index(self.rate, SYNC_RATES.len())]
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Lfo {
+    phase: f64, // 0.0 - 1.0
+    held_value: f64,
+    random_state: u32,
+}
+
+impl Lfo {
+    pub fn new() -> Self {
+        Self {
+            phase: 0.0,
+            held_value: 0.0,
+            random_state: 0x9E37_79B9,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.held_value = self.next_random();
+    }
+
+    // For following the host's position. A new cycle still picks a new sample-and-hold value.
+    pub fn set_phase(&mut self, phase: f64) {
+        if phase < self.phase {
+            self.held_value = self.next_random();
+        }
+        self.phase = phase;
+    }
+
+    pub fn next_sample(&mut self, shape: LfoShape, frequency: f64, sample_rate: f32) -> f64 {
+        let value = self.value(shape);
+        self.phase += frequency / sample_rate as f64;
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            self.held_value = self.next_random();
+        }
+        value
+    }
+
+    fn value(&self, shape: LfoShape) -> f64 {
+        let phase = self.phase;
+        match shape {
+            LfoShape::Sine => (2.0 * PI * phase).sin(),
+            LfoShape::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            LfoShape::SawUp => 2.0 * phase - 1.0,
+            LfoShape::SawDown => 1.0 - 2.0 * phase,
+            LfoShape::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            LfoShape::SampleAndHold => self.held_value,
+        }
+    }
+
+    // xorshift32, scaled to -1.0 - 1.0.
+    fn next_random(&mut self) -> f64 {
+        let mut x = self.random_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_state = x;
+        x as f64 / u32::max_value() as f64 * 2.0 - 1.0
+    }
+}
+
+// Exponential, like the filter cutoff.
+pub fn lfo_rate_hz(value: f32) -> f64 {
+    MIN_RATE * (MAX_RATE / MIN_RATE).powf(value as f64)
+}
+
+pub fn lfo_rate_text(rate: f32, sync: f32) -> String {
+    if choice_index(sync, 2) == 1 {
+        SYNC_RATE_NAMES[choice_index(rate, SYNC_RATES.len())].to_string()
+    } else {
+        format!("{:0.2} Hz", lfo_rate_hz(rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    // The rate parameter's value for one of the synced choices.
+    fn synced_rate(name: &str) -> f32 {
+        let index = SYNC_RATE_NAMES.iter().position(|&rate| rate == name).unwrap();
+        index as f32 / (SYNC_RATES.len() - 1) as f32
+    }
+
+    #[test]
+    fn synced_rate_follows_the_tempo() {
+        let quarter = LfoSettings::new(0.0, synced_rate("1/4"), 1.0, 0.0);
+        assert_eq!(lfo_rate_text(quarter.rate, 1.0), "1/4");
+        assert_eq!(quarter.frequency(120.0), 2.0);
+        assert_eq!(quarter.frequency(90.0), 1.5);
+
+        let bar = LfoSettings::new(0.0, synced_rate("1 bar"), 1.0, 0.0);
+        assert_eq!(bar.frequency(120.0), 0.5);
+
+        // The same rate unsynced is a fixed frequency.
+        let free = LfoSettings::new(0.0, quarter.rate, 0.0, 0.0);
+        assert_eq!(free.frequency(120.0), free.frequency(90.0));
+    }
+
+    #[test]
+    fn synced_cycle_lasts_its_note_length() {
+        // A quarter note at 120 BPM is half a second.
+        let settings = LfoSettings::new(0.4, synced_rate("1/4"), 1.0, 0.0);
+        let frequency = settings.frequency(120.0);
+        let mut lfo = Lfo::new();
+        let cycle: Vec<f64> = (0..24001)
+            .map(|_| lfo.next_sample(LfoShape::SawUp, frequency, SAMPLE_RATE))
+            .collect();
+        assert_eq!(cycle[0], -1.0);
+        assert!(cycle[12000].abs() < 1e-9);
+        assert!((cycle[23999] - 1.0).abs() < 1e-3);
+        assert!((cycle[24000] + 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn free_rate_covers_its_range() {
+        assert!((lfo_rate_hz(0.0) - MIN_RATE).abs() < 1e-12);
+        assert!((lfo_rate_hz(1.0) - MAX_RATE).abs() < 1e-9);
+        assert_eq!(lfo_rate_text(0.0, 0.0), "0.02 Hz");
+    }
+}
@@ -1,75 +1,635 @@
+use num_traits::Float;
+use std::mem;
 use std::sync::Arc;
-use std::{mem, ptr};
 use vst::buffer::AudioBuffer;
 
-mod square_oscillator;
-use self::square_oscillator::SquareOscillator;
-use crate::parameters::Parameters;
+mod envelope;
+mod filter;
+mod fm;
+mod glide;
+mod lfo;
+mod note_stack;
+mod oscillator;
+mod oscillator_stack;
+mod oversampler;
+mod resampler;
+mod sampler;
+mod unison;
+mod voice;
+pub use self::filter::{envelope_time_seconds, filter_cutoff_hz, filter_env_octaves};
+pub use self::fm::{fm_ratio, ALGORITHM_NAMES};
+pub use self::lfo::lfo_rate_text;
+pub use self::oscillator_stack::{oscillator_fine_cents, oscillator_octave, oscillator_semitone};
+pub use self::unison::{unison_count, unison_detune_cents};
+use self::fm::FmSettings;
+use self::glide::{GlideCurve, GlideMode, Portamento};
+use self::lfo::{Lfo, LfoSettings};
+use self::note_stack::{NotePriority, NoteStack};
+use self::oscillator_stack::StackSettings;
+use self::oversampler::{oversampling_latency, LatencyDelay, Oversampler, MAX_FACTOR};
+use self::sampler::Sampler;
+use self::voice::{BlockContext, Expression, VoiceSettings, Voices};
+use crate::effects::Effects;
+use crate::master::MasterStage;
+use crate::midi_input_processor::{NoteEvent, TimedEvent};
+use crate::parameters::{choice_index, Parameters};
+use crate::sample_map::SampleMap;
+use crate::transport::Transport;
+use crate::tuning::{reference_pitch_hz, Tuning};
+use crate::ui_state::UiState;
+use crate::wavetable::Wavetable;
 
-pub struct AudioEngine {
-    oscillators: [SquareOscillator; 128],
-    sample_rate: f32,
+// Enough for every MIDI note at once.
+const MAX_VOICES: usize = 128;
+// The voices render in pieces of up to this many samples (at the sample rate), split wherever an
+// event lands.
+const MAX_BLOCK: usize = 64;
+// What the output buffers start out sized for, until the host says how big its blocks are.
+const DEFAULT_BLOCK_SIZE: usize = 1024;
+// How long the clip light stays on after the output clips.
+const CLIP_HOLD_SECONDS: f32 = 1.0;
+// How long the output takes to fade out when the host bypasses us, and back in again after.
+const BYPASS_FADE_SECONDS: f64 = 0.005;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoiceMode {
+    Poly,
+    Mono,
 }
 
-impl AudioEngine {
-    pub fn new(params: Arc<Parameters>) -> Self {
-        // Create a bank of 128 different SquareOscillators, tuned to all the different MIDI notes.
-        // This is a terrible way to actually do it, but it works for now.
-        // TODO: make this a little smarter.
-        let oscillators = unsafe {
-            let mut oscillators: [SquareOscillator; 128] = mem::uninitialized();
+impl VoiceMode {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 2) {
+            0 => VoiceMode::Poly,
+            _ => VoiceMode::Mono,
+        }
+    }
+}
 
-            for (i, osc) in oscillators.iter_mut().enumerate() {
-                let mut oscillator = SquareOscillator::new(params.clone());
-                oscillator.change_frequency(midi_pitch_to_freq(i as u8));
-                ptr::write(osc, oscillator);
-            }
+// What the voices play: the oscillators (through the filter), or the FM operators (also through
+// the filter).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SynthMode {
+    Subtractive,
+    Fm,
+}
 
-            oscillators
-        };
+impl SynthMode {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 2) {
+            0 => SynthMode::Subtractive,
+            _ => SynthMode::Fm,
+        }
+    }
+}
+
+pub struct AudioEngine {
+    params: Arc<Parameters>,
+    ui_state: Arc<UiState>,
+    voices: Voices,
+    // Every key that's down, oldest first. Mono mode picks its note from here.
+    note_stack: NoteStack,
+    voice_mode: VoiceMode,
+    // The channel and velocity of the last note-on, which the mono voice follows.
+    mono_velocity: u8,
+    // The latest pitch bend, pressure and slide on each channel, for new notes to start from.
+    channel_expression: [Expression; 16],
+    tuning: Tuning,
+    // None until a wavetable is loaded, which leaves the wavetable oscillator silent. Shared with
+    // `UiState`, like the sample map.
+    wavetable: Option<Arc<Wavetable>>,
+    sampler: Sampler,
+    // The voices can run faster than the sample rate, by a different factor when the host is
+    // rendering offline. The sampler doesn't, so it's held back to line up with them.
+    oversampler: Oversampler,
+    offline: bool,
+    voice_delay: LatencyDelay,
+    sampler_delay: LatencyDelay,
+    // Everything the block adds up to, left and right, before it goes out as the host's sample
+    // type. Sized by `set_block_size`.
+    output_buffers: [Vec<f64>; 2],
+    // What the voices add up to over one piece of a block, left and right, at the oversampled
+    // rate. Allocated up front for the longest piece at the highest factor.
+    voice_buffers: [Vec<f64>; 2],
+    // LFO 1 for each sample of that piece, also at the oversampled rate.
+    lfo_buffer: Vec<f64>,
+    effects: Effects,
+    master: MasterStage,
+    // Samples left before the clip light goes out.
+    clip_hold: usize,
+    // Soft bypass only fades the output: everything underneath keeps running, so the voices stay
+    // in step with the MIDI coming in.
+    bypassed: bool,
+    output_gain: f64, // 0.0 - 1.0
+    // LFO 1, shared by every voice. (Each voice has its own LFO 2.)
+    lfo: Lfo,
+    mod_wheel: f64,
+    // Counts up on every trigger, so we know which voice is the oldest.
+    voice_counter: u64,
+    // The pitch of the most recent note. New notes glide from here.
+    last_frequency: Option<f64>,
+    sample_rate: f32,
+}
 
+impl AudioEngine {
+    pub fn new(params: Arc<Parameters>, ui_state: Arc<UiState>) -> Self {
         Self {
-            oscillators,
+            voice_mode: VoiceMode::from_parameter(params.voice_mode.get()),
+            params,
+            ui_state,
+            voices: Voices::new(MAX_VOICES, MAX_BLOCK * MAX_FACTOR),
+            note_stack: NoteStack::new(),
+            mono_velocity: 0,
+            channel_expression: [Expression::new(); 16],
+            tuning: Tuning::new(),
+            wavetable: None,
+            sampler: Sampler::new(),
+            oversampler: Oversampler::new(),
+            offline: false,
+            voice_delay: LatencyDelay::new(),
+            sampler_delay: LatencyDelay::new(),
+            output_buffers: [vec![0.0; DEFAULT_BLOCK_SIZE], vec![0.0; DEFAULT_BLOCK_SIZE]],
+            voice_buffers: [vec![0.0; MAX_BLOCK * MAX_FACTOR], vec![0.0; MAX_BLOCK * MAX_FACTOR]],
+            lfo_buffer: vec![0.0; MAX_BLOCK * MAX_FACTOR],
+            effects: Effects::new(),
+            master: MasterStage::new(),
+            clip_hold: 0,
+            bypassed: false,
+            output_gain: 1.0,
+            lfo: Lfo::new(),
+            mod_wheel: 0.0,
+            voice_counter: 0,
+            last_frequency: None,
             sample_rate: 44100.0,
         }
     }
 
-    pub fn process(&mut self, buffer: &mut AudioBuffer<f32>, active_notes: Vec<u8>) {
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.effects.set_sample_rate(sample_rate);
+    }
+
+    // Allocates, so it's for the host's `set_block_size`, not the audio thread.
+    pub fn set_block_size(&mut self, size: usize) {
+        for side in &mut self.output_buffers {
+            side.resize(size, 0.0);
+        }
+    }
+
+    pub fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        self.tuning = tuning;
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.bypassed
+    }
+
+    pub fn set_bypass(&mut self, bypassed: bool) {
+        self.bypassed = bypassed;
+    }
+
+    pub fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    // In samples. It's always what the highest oversampling factor needs, whatever the settings
+    // and whether the host is rendering offline or not (anything with less gets held back to
+    // match). The host only asks once, so this way its delay compensation can't go stale when the
+    // oversampling parameters change.
+    pub fn latency(&self) -> usize {
+        oversampling_latency(MAX_FACTOR)
+    }
+
+    // What the voices run at, with oversampling.
+    fn voice_sample_rate(&self) -> f32 {
+        self.sample_rate * self.oversampler.factor() as f32
+    }
+
+    // In samples, for the host to keep processing after the input stops.
+    pub fn tail_size(&self) -> usize {
+        (self.effects.tail_seconds(&self.params) * self.sample_rate as f64).ceil() as usize
+    }
+
+    pub fn wavetable(&self) -> Option<&Wavetable> {
+        self.wavetable.as_deref()
+    }
+
+    pub fn set_wavetable(&mut self, wavetable: Option<Arc<Wavetable>>) {
+        self.wavetable = wavetable;
+    }
+
+    pub fn sample_map(&self) -> Option<&SampleMap> {
+        self.sampler.map()
+    }
+
+    pub fn set_sample_map(&mut self, map: Option<Arc<SampleMap>>) {
+        self.sampler.set_map(map);
+    }
+
+    // Everything's worked out in f64, whatever the host wants the output in.
+    pub fn process<T: Float>(&mut self, buffer: &mut AudioBuffer<T>, events: &[TimedEvent], transport: &Transport) {
         let output_channels = buffer.output_count();
         let num_samples = buffer.samples();
         let (_, output_buffer) = buffer.split();
+        let samples = self.render(num_samples, events, transport);
 
-        // Precompute the samples that should go to each channel.
-        // Our oscillator will output the same signal to all channels.
-        // TODO: put a tiny envelope here (based on sample rate) to prevent clicks
+        // Write the output to each channel. Any channels past the first two get the same left and
+        // right again.
+        for channel in 0..output_channels {
+            let output_channel = output_buffer.get_mut(channel);
+            for (output_sample, sample_value) in output_channel.iter_mut().zip(samples[channel % 2]) {
+                // Can't fail: an f64 converts to f32 or f64 (rounding, or going to infinity).
+                *output_sample = T::from(*sample_value).unwrap();
+            }
+        }
+    }
 
-        // TODO: don't allocate inside process() !!!
-        let mut samples: Vec<f64> = Vec::new();
-        samples.resize(num_samples, 0.0);
+    // Runs a block of `num_samples` and returns it, left and right. This is all of `process()`
+    // apart from the host's buffers, so the benchmarks can drive it too.
+    pub fn render(&mut self, num_samples: usize, events: &[TimedEvent], transport: &Transport) -> [&[f64]; 2] {
+        // Switching between poly and mono with notes held would leave voices behind that the
+        // other mode doesn't know about, so let everything go.
+        let voice_mode = VoiceMode::from_parameter(self.params.voice_mode.get());
+        if voice_mode != self.voice_mode {
+            self.voice_mode = voice_mode;
+            for voice in 0..MAX_VOICES {
+                self.voices.release(voice);
+            }
+        }
 
-        for sample_num in 0..num_samples {
-            for note in &active_notes {
-                samples[sample_num] +=
-                    self.oscillators[*note as usize].next_sample(self.sample_rate);
+        // Precompute the samples that should go to each channel: left, then right. The buffers
+        // are taken out of `self` for the block so events can still be handled as they come up.
+        // A host that sends a bigger block than it said it would gets them reallocated, rather
+        // than losing samples.
+        if num_samples > self.output_buffers[0].len() {
+            self.set_block_size(num_samples);
+        }
+        let mut samples = mem::replace(&mut self.output_buffers, [Vec::new(), Vec::new()]);
+        for side in &mut samples {
+            for sample in &mut side[..num_samples] {
+                *sample = 0.0;
             }
         }
 
-        // Write the output to each channel.
-        for channel in 0..output_channels {
-            let output_channel = output_buffer.get_mut(channel);
-            let mut sample_counter = 0;
-            for output_sample in output_channel {
-                let sample_value = samples[sample_counter] as f32;
-                *output_sample = sample_value;
-                sample_counter += 1;
+        let params = &self.params;
+        let lfo1 = LfoSettings::new(
+            params.lfo1_shape.get(),
+            params.lfo1_rate.get(),
+            params.lfo1_sync.get(),
+            params.lfo1_retrigger.get(),
+        );
+        let lfo2 = LfoSettings::new(
+            params.lfo2_shape.get(),
+            params.lfo2_rate.get(),
+            params.lfo2_sync.get(),
+            params.lfo2_retrigger.get(),
+        );
+        let voice_settings = VoiceSettings::from_parameters(params);
+        let synth_mode = SynthMode::from_parameter(params.synth_mode.get());
+        let oscillators = StackSettings::from_parameters(params);
+        let fm = FmSettings::from_parameters(params);
+        let mod_slots = self.ui_state.mod_matrix.slots();
+        let sampler_level = params.sampler_level.get() as f64;
+        let sampler_release = envelope_time_seconds(params.sampler_release.get());
+        let beats_per_sample = transport.beats_per_sample(self.sample_rate);
+        let oversampling = if self.offline {
+            params.offline_oversampling.get()
+        } else {
+            params.oversampling.get()
+        };
+        self.oversampler.set_factor(oversampling_factor(oversampling));
+        let latency = self.latency();
+        let voice_delay = latency - oversampling_latency(self.oversampler.factor());
+        let voice_sample_rate = self.voice_sample_rate();
+
+        let factor = self.oversampler.factor();
+
+        let mut events = events.iter().peekable();
+        let mut start = 0;
+        while start < num_samples {
+            while let Some(event) = events.peek() {
+                if event.delta_frames > start {
+                    break;
+                }
+                self.handle_event(event.event);
+                events.next();
+            }
+            let end = match events.peek() {
+                Some(event) => event.delta_frames.min(start + MAX_BLOCK).min(num_samples),
+                None => (start + MAX_BLOCK).min(num_samples),
+            };
+            let length = end - start;
+
+            // A synced LFO 1 lines up with the host's bars while it's playing, unless it restarts
+            // with every note instead. It runs at the sample rate, and holds still through each
+            // sample's oversampled ones.
+            for sample_num in start..end {
+                if let Some(position) = transport.position {
+                    if lfo1.sync && !lfo1.retrigger {
+                        let beat = position + sample_num as f64 * beats_per_sample;
+                        self.lfo.set_phase((beat / lfo1.cycle_beats()).fract());
+                    }
+                }
+                let value = self.lfo.next_sample(lfo1.shape, lfo1.frequency(transport.tempo), self.sample_rate);
+                let offset = (sample_num - start) * factor;
+                for sample in &mut self.lfo_buffer[offset..offset + factor] {
+                    *sample = value;
+                }
+            }
+
+            let context = BlockContext {
+                sample_rate: voice_sample_rate,
+                mod_wheel: self.mod_wheel,
+                lfo2,
+                lfo2_frequency: lfo2.frequency(transport.tempo),
+                voice: voice_settings,
+                synth_mode,
+                oscillators,
+                fm,
+                wavetable: self.wavetable.as_deref(),
+                mod_slots: &mod_slots,
+            };
+            let [voice_left, voice_right] = &mut self.voice_buffers;
+            let voice_left = &mut voice_left[..length * factor];
+            let voice_right = &mut voice_right[..length * factor];
+            for sample in voice_left.iter_mut().chain(voice_right.iter_mut()) {
+                *sample = 0.0;
+            }
+            let lfo_values = &self.lfo_buffer[..length * factor];
+            self.voices.render(&context, lfo_values, voice_left, voice_right);
+            self.oversampler.process(voice_left, voice_right);
+
+            for index in 0..length {
+                let sample_num = start + index;
+                let (left, right) = self.voice_delay.process((voice_left[index], voice_right[index]), voice_delay);
+                samples[0][sample_num] += left;
+                samples[1][sample_num] += right;
+
+                let sampler_output = self
+                    .sampler
+                    .next_sample(&self.channel_expression, sampler_release, self.sample_rate);
+                let (left, right) = self.sampler_delay.process(sampler_output, latency);
+                samples[0][sample_num] += left * sampler_level;
+                samples[1][sample_num] += right * sampler_level;
+            }
+            start = end;
+        }
+
+        // Anything the host put past the end of the block still counts.
+        for event in events {
+            self.handle_event(event.event);
+        }
+
+        let [left, right] = &mut samples;
+        let left = &mut left[..num_samples];
+        let right = &mut right[..num_samples];
+        self.effects.process(left, right, &self.params, transport.tempo);
+        if self.master.process(left, right, &self.params, self.sample_rate) {
+            self.clip_hold = (CLIP_HOLD_SECONDS * self.sample_rate) as usize;
+        } else {
+            self.clip_hold = self.clip_hold.saturating_sub(num_samples);
+        }
+        self.ui_state.set_clipping(self.clip_hold > 0);
+
+        let step = 1.0 / (BYPASS_FADE_SECONDS * self.sample_rate as f64);
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            self.output_gain = if self.bypassed {
+                (self.output_gain - step).max(0.0)
+            } else {
+                (self.output_gain + step).min(1.0)
+            };
+            *left *= self.output_gain;
+            *right *= self.output_gain;
+        }
+
+        self.output_buffers = samples;
+        let [left, right] = &self.output_buffers;
+        [&left[..num_samples], &right[..num_samples]]
+    }
+
+    fn handle_event(&mut self, event: NoteEvent) {
+        match event {
+            NoteEvent::NoteOn { channel, note, velocity } => {
+                // Keys the keyboard mapping leaves out don't play at all.
+                let frequency = match self.note_frequency(note) {
+                    Some(frequency) => frequency,
+                    None => return,
+                };
+                let reference_pitch = reference_pitch_hz(self.params.reference_pitch.get());
+                self.sampler.note_on(channel, note, velocity, frequency, reference_pitch);
+                if choice_index(self.params.lfo1_retrigger.get(), 2) == 1 {
+                    self.lfo.reset();
+                }
+                // Whether this note overlaps another key that's still down, for "legato only" glide.
+                let overlapping = self
+                    .note_stack
+                    .pick(NotePriority::Last)
+                    .map_or(false, |held| held != (channel, note));
+                self.note_stack.push(channel, note);
+                match self.voice_mode {
+                    VoiceMode::Poly => self.poly_note_on(channel, note, velocity, overlapping),
+                    VoiceMode::Mono => {
+                        self.mono_velocity = velocity;
+                        self.update_mono_voice();
+                    }
+                }
+            }
+            NoteEvent::NoteOff { channel, note } => {
+                self.note_stack.remove(channel, note);
+                self.sampler.note_off(channel, note);
+                match self.voice_mode {
+                    VoiceMode::Poly => self.poly_note_off(channel, note),
+                    VoiceMode::Mono => self.update_mono_voice(),
+                }
+            }
+            NoteEvent::AllSoundOff => {
+                self.note_stack.clear();
+                self.last_frequency = None;
+                for voice in 0..MAX_VOICES {
+                    self.voices.kill(voice);
+                }
+                self.sampler.kill_all();
+            }
+            NoteEvent::PitchBend { channel, semitones } => {
+                self.channel_expression[channel as usize].pitch_bend = semitones;
+                self.update_expression(channel);
+            }
+            NoteEvent::Pressure { channel, value } => {
+                self.channel_expression[channel as usize].pressure = value;
+                self.update_expression(channel);
+            }
+            NoteEvent::Slide { channel, value } => {
+                self.channel_expression[channel as usize].slide = value;
+                self.update_expression(channel);
+            }
+            NoteEvent::ModWheel { value } => self.mod_wheel = value as f64,
+            NoteEvent::KeyPressure { channel, note, value } => {
+                for voice in 0..MAX_VOICES {
+                    if self.is_playing(voice, channel, note) {
+                        self.voices.set_key_pressure(voice, value);
+                    }
+                }
+            }
+            NoteEvent::PulseWidthLock { channel, note, value } => {
+                for voice in 0..MAX_VOICES {
+                    if self.is_playing(voice, channel, note) {
+                        self.voices.set_pulse_width_lock(voice, value);
+                    }
+                }
             }
         }
     }
+
+    fn update_expression(&mut self, channel: u8) {
+        let expression = self.channel_expression[channel as usize];
+        for voice in 0..MAX_VOICES {
+            if self.voices.is_active(voice) && self.voices.channel(voice) == channel {
+                self.voices.set_expression(voice, expression);
+            }
+        }
+    }
+
+    // Whether `voice` is holding this key down.
+    fn is_playing(&self, voice: usize, channel: u8, note: u8) -> bool {
+        self.voices.is_gate_open(voice) && self.voices.channel(voice) == channel && self.voices.note(voice) == note
+    }
+
+    fn next_voice_age(&mut self) -> u64 {
+        self.voice_counter += 1;
+        self.voice_counter
+    }
+
+    fn note_frequency(&self, note: u8) -> Option<f64> {
+        let reference_pitch = reference_pitch_hz(self.params.reference_pitch.get());
+        self.tuning
+            .frequency(note)
+            .map(|frequency| frequency * reference_pitch / 440.0)
+    }
+
+    // How a new note should get to its pitch, or None if it should just start there.
+    fn portamento(&self, overlapping: bool) -> Option<Portamento> {
+        let time = glide_time_seconds(self.params.glide_time.get());
+        let from = self.last_frequency?;
+        if time <= 0.0 {
+            return None;
+        }
+
+        match GlideMode::from_parameter(self.params.glide_mode.get()) {
+            GlideMode::LegatoOnly if !overlapping => None,
+            _ => Some(Portamento {
+                from,
+                time,
+                curve: GlideCurve::from_parameter(self.params.glide_curve.get()),
+            }),
+        }
+    }
+
+    fn poly_note_on(&mut self, channel: u8, note: u8, velocity: u8, overlapping: bool) {
+        let frequency = match self.note_frequency(note) {
+            Some(frequency) => frequency,
+            None => return,
+        };
+        let age = self.next_voice_age();
+        let portamento = self.portamento(overlapping);
+
+        // Reuse the voice that's already playing this note, then a free one, and if there are
+        // none of those, steal the oldest.
+        let voices = &self.voices;
+        let voice = (0..MAX_VOICES)
+            .find(|&voice| voices.is_active(voice) && voices.channel(voice) == channel && voices.note(voice) == note)
+            .or_else(|| (0..MAX_VOICES).find(|&voice| !voices.is_active(voice)))
+            .unwrap_or_else(|| {
+                let mut oldest = 0;
+                for voice in 0..MAX_VOICES {
+                    if voices.age(voice) < voices.age(oldest) {
+                        oldest = voice;
+                    }
+                }
+                oldest
+            });
+
+        let sample_rate = self.voice_sample_rate();
+        let lfo2_retrigger = choice_index(self.params.lfo2_retrigger.get(), 2) == 1;
+        self.voices.trigger(voice, age);
+        self.voices.change_note(voice, channel, note, frequency, portamento, sample_rate);
+        if lfo2_retrigger {
+            self.voices.reset_lfo(voice);
+        }
+        self.voices.set_expression(voice, self.channel_expression[channel as usize]);
+        self.voices.set_velocity(voice, velocity);
+        self.last_frequency = Some(frequency);
+    }
+
+    fn poly_note_off(&mut self, channel: u8, note: u8) {
+        for voice in 0..MAX_VOICES {
+            if self.is_playing(voice, channel, note) {
+                self.voices.release(voice);
+            }
+        }
+    }
+
+    // Mono mode only ever uses the first voice. Whenever the held keys change, work out which
+    // note it should be playing and get it there.
+    fn update_mono_voice(&mut self) {
+        let priority = NotePriority::from_parameter(self.params.note_priority.get());
+        let legato = choice_index(self.params.legato.get(), 2) == 1;
+        let age = self.next_voice_age();
+
+        // A mono voice glides from wherever it is right now, even if that's partway through
+        // another glide.
+        if self.voices.is_active(0) {
+            self.last_frequency = Some(self.voices.frequency(0));
+        }
+        let portamento = self.portamento(self.voices.is_gate_open(0));
+        let sample_rate = self.voice_sample_rate();
+        let lfo2_retrigger = choice_index(self.params.lfo2_retrigger.get(), 2) == 1;
+        let velocity = self.mono_velocity;
+        // The tuning can change while keys are held, leaving the picked note unmapped.
+        let picked = self
+            .note_stack
+            .pick(priority)
+            .and_then(|(channel, note)| Some((channel, note, self.note_frequency(note)?)));
+        let voices = &mut self.voices;
+
+        match picked {
+            None => voices.release(0),
+            Some((channel, note, frequency)) => {
+                let expression = self.channel_expression[channel as usize];
+                if voices.is_gate_open(0) {
+                    if voices.channel(0) == channel && voices.note(0) == note {
+                        return;
+                    }
+                    if legato {
+                        voices.change_note(0, channel, note, frequency, portamento, sample_rate);
+                        voices.set_expression(0, expression);
+                        self.last_frequency = Some(frequency);
+                        return;
+                    }
+                }
+                voices.trigger(0, age);
+                voices.change_note(0, channel, note, frequency, portamento, sample_rate);
+                if lfo2_retrigger {
+                    voices.reset_lfo(0);
+                }
+                voices.set_expression(0, expression);
+                voices.set_velocity(0, velocity);
+                self.last_frequency = Some(frequency);
+            }
+        }
+    }
+}
+
+pub fn oversampling_factor(value: f32) -> usize {
+    1 << choice_index(value, 4)
 }
 
-fn midi_pitch_to_freq(pitch: u8) -> f64 {
-    const A4_PITCH: i8 = 69;
-    const A4_FREQ: f64 = 440.0;
+// The glide time parameter is squared so there's more room at the short end.
+pub fn glide_time_seconds(value: f32) -> f64 {
+    const MAX_GLIDE_TIME: f64 = 2.0;
 
-    (((pitch as i8 - A4_PITCH) as f64) / 12.).exp2() * A4_FREQ
+    value as f64 * value as f64 * MAX_GLIDE_TIME
 }
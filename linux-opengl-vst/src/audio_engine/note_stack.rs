@@ -0,0 +1,105 @@
+use crate::parameters::choice_index;
+
+// Keeps track of which keys are held down, in the order they were pressed, so a monophonic voice
+// can fall back to an older note when the newest one is released. Keys are (channel, note), so
+// two MPE channels playing the same note are held and released separately.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotePriority {
+    Last,
+    Low,
+    High,
+}
+
+impl NotePriority {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 3) {
+            0 => NotePriority::Last,
+            1 => NotePriority::Low,
+            _ => NotePriority::High,
+        }
+    }
+}
+
+pub struct NoteStack {
+    notes: Vec<(u8, u8)>, // (channel, note), oldest first
+}
+
+impl NoteStack {
+    pub fn new() -> Self {
+        Self {
+            // Room for every note on every channel, so pushing never allocates on the audio thread.
+            notes: Vec::with_capacity(16 * 128),
+        }
+    }
+
+    pub fn push(&mut self, channel: u8, note: u8) {
+        // A key that's pressed again (without a note off in between) counts as the newest one.
+        self.remove(channel, note);
+        self.notes.push((channel, note));
+    }
+
+    pub fn remove(&mut self, channel: u8, note: u8) {
+        self.notes.retain(|&held| held != (channel, note));
+    }
+
+    pub fn clear(&mut self) {
+        self.notes.clear();
+    }
+
+    // The (channel, note) a monophonic voice should be playing right now, if any. When the same
+    // note is held on more than one channel, the newest of them wins.
+    pub fn pick(&self, priority: NotePriority) -> Option<(u8, u8)> {
+        match priority {
+            NotePriority::Last => self.notes.last().cloned(),
+            NotePriority::Low => self.notes.iter().rev().min_by_key(|&&(_, note)| note).cloned(),
+            NotePriority::High => self.notes.iter().rev().max_by_key(|&&(_, note)| note).cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(keys: &[(u8, u8)]) -> NoteStack {
+        let mut stack = NoteStack::new();
+        for &(channel, note) in keys {
+            stack.push(channel, note);
+        }
+        stack
+    }
+
+    #[test]
+    fn picks_by_priority() {
+        let stack = stack(&[(0, 64), (0, 60), (0, 67)]);
+        assert_eq!(stack.pick(NotePriority::Last), Some((0, 67)));
+        assert_eq!(stack.pick(NotePriority::Low), Some((0, 60)));
+        assert_eq!(stack.pick(NotePriority::High), Some((0, 67)));
+    }
+
+    #[test]
+    fn falls_back_to_older_notes() {
+        let mut stack = stack(&[(0, 60), (0, 64), (0, 67)]);
+        stack.remove(0, 67);
+        assert_eq!(stack.pick(NotePriority::Last), Some((0, 64)));
+        stack.remove(0, 60);
+        stack.remove(0, 64);
+        assert_eq!(stack.pick(NotePriority::Last), None);
+    }
+
+    #[test]
+    fn pressing_a_key_again_makes_it_newest() {
+        let stack = stack(&[(0, 60), (0, 64), (0, 60)]);
+        assert_eq!(stack.pick(NotePriority::Last), Some((0, 60)));
+    }
+
+    #[test]
+    fn same_note_on_two_channels_is_held_separately() {
+        let mut stack = stack(&[(1, 60), (2, 60)]);
+        assert_eq!(stack.pick(NotePriority::Low), Some((2, 60)));
+        stack.remove(2, 60);
+        assert_eq!(stack.pick(NotePriority::Last), Some((1, 60)));
+        assert_eq!(stack.pick(NotePriority::High), Some((1, 60)));
+    }
+}
@@ -0,0 +1,160 @@
+use std::f64::consts::PI;
+
+use super::voice::{Lanes, VOICE_LANES};
+use crate::parameters::choice_index;
+use crate::wavetable::Wavetable;
+
+// A phase accumulator, which every waveform reads from. The waveforms aren't
+// band-limited, but wavetables are (they pick a mip-map level for the frequency).
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Pulse,
+    Saw,
+    Triangle,
+    Sine,
+}
+
+impl Waveform {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 4) {
+            0 => Waveform::Pulse,
+            1 => Waveform::Saw,
+            2 => Waveform::Triangle,
+            _ => Waveform::Sine,
+        }
+    }
+}
+
+// One oscillator for every voice in a group, one lane per voice. The lanes are kept side by side
+// in plain arrays, and each waveform is a loop over them with nothing in between, so the compiler
+// can vectorize it.
+pub struct Oscillator {
+    phases: Lanes,
+    // Whether each phase wrapped around on the last sample, for hard sync.
+    wrapped: [bool; VOICE_LANES],
+}
+
+impl Oscillator {
+    pub fn new() -> Self {
+        Self {
+            phases: [0.0; VOICE_LANES],
+            wrapped: [false; VOICE_LANES],
+        }
+    }
+
+    pub fn reset(&mut self, lane: usize) {
+        self.phases[lane] = 0.0;
+        self.wrapped[lane] = false;
+    }
+
+    // Hard sync: start a new cycle in each lane where `master` just did. The new phase is however
+    // far this oscillator would have got in the time since the master wrapped, so the sync point
+    // doesn't jitter by up to a sample.
+    pub fn sync_to(&mut self, master: &Oscillator, frequencies: &Lanes, master_frequencies: &Lanes) {
+        for (lane, phase) in self.phases.iter_mut().enumerate() {
+            if master.wrapped[lane] && master_frequencies[lane] > 0.0 {
+                *phase = (master.phases[lane] * frequencies[lane] / master_frequencies[lane]).fract();
+            }
+        }
+    }
+
+    // One output per lane. The pulse width comes from each voice, so it can be modulated (or
+    // locked) per note. The other waveforms ignore it.
+    pub fn next_samples(
+        &mut self,
+        waveform: Waveform,
+        pulse_widths: &Lanes,
+        frequencies: &Lanes,
+        sample_rate: f32,
+    ) -> Lanes {
+        let mut outputs = [0.0; VOICE_LANES];
+        let lanes = outputs.iter_mut().zip(&self.phases);
+        match waveform {
+            Waveform::Pulse => {
+                for ((output, phase), pulse_width) in lanes.zip(pulse_widths) {
+                    *output = if *phase <= pulse_width.max(0.0).min(1.0) {
+                        -1.0
+                    } else {
+                        1.0
+                    };
+                }
+            }
+            Waveform::Saw => {
+                for (output, phase) in lanes {
+                    *output = 2.0 * phase - 1.0;
+                }
+            }
+            Waveform::Triangle => {
+                for (output, phase) in lanes {
+                    *output = 4.0 * (phase - 0.5).abs() - 1.0;
+                }
+            }
+            Waveform::Sine => {
+                for (output, phase) in lanes {
+                    *output = (2.0 * PI * phase).sin();
+                }
+            }
+        }
+        self.advance(frequencies, sample_rate);
+        outputs
+    }
+
+    // `positions` are 0.0 - 1.0 through the table's frames.
+    pub fn next_wavetable_samples(
+        &mut self,
+        table: &Wavetable,
+        positions: &Lanes,
+        frequencies: &Lanes,
+        sample_rate: f32,
+    ) -> Lanes {
+        let mut outputs = [0.0; VOICE_LANES];
+        for (lane, output) in outputs.iter_mut().enumerate() {
+            *output = table.sample(self.phases[lane], positions[lane], frequencies[lane], sample_rate);
+        }
+        self.advance(frequencies, sample_rate);
+        outputs
+    }
+
+    fn advance(&mut self, frequencies: &Lanes, sample_rate: f32) {
+        let lanes = self.phases.iter_mut().zip(self.wrapped.iter_mut());
+        for ((phase, wrapped), frequency) in lanes.zip(frequencies) {
+            *phase += frequency / sample_rate as f64;
+            *wrapped = *phase >= 1.0;
+            if *wrapped {
+                *phase = phase.fract();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hard_sync_restarts_the_slave_when_the_master_wraps() {
+        let sample_rate = 1000.0;
+        let pulse_widths = [0.5; VOICE_LANES];
+        // Only lane 0's master gets to the end of a cycle, on the fourth sample.
+        let mut master_frequencies = [100.0; VOICE_LANES];
+        master_frequencies[0] = 300.0;
+        let frequencies = [450.0; VOICE_LANES];
+
+        let mut master = Oscillator::new();
+        let mut slave = Oscillator::new();
+        for _ in 0..4 {
+            master.next_samples(Waveform::Saw, &pulse_widths, &master_frequencies, sample_rate);
+            slave.next_samples(Waveform::Saw, &pulse_widths, &frequencies, sample_rate);
+            slave.sync_to(&master, &frequencies, &master_frequencies);
+        }
+
+        // The master is 0.2 into its new cycle, so the slave (half as fast again) is 0.3 into
+        // its own. The other lanes carry on from 0.8.
+        let outputs = slave.next_samples(Waveform::Saw, &pulse_widths, &frequencies, sample_rate);
+        assert!((outputs[0] - (2.0 * 0.3 - 1.0)).abs() < 1e-9, "{}", outputs[0]);
+        for &output in &outputs[1..] {
+            assert!((output - (2.0 * 0.8 - 1.0)).abs() < 1e-9, "{}", output);
+        }
+    }
+}
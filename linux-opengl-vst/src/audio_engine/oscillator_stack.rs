@@ -0,0 +1,173 @@
+use super::oscillator::{Oscillator, Waveform};
+use super::voice::{Lanes, VOICE_LANES};
+use crate::parameters::{choice_index, Parameters};
+use crate::wavetable::Wavetable;
+
+// The oscillators that make up one voice (and each unison copy of it): three main oscillators, each
+// tuned relative to the note, plus a square sub-oscillator an octave under oscillator 1 and a
+// wavetable oscillator at the note's own pitch. Oscillator 2 can be hard synced to oscillator 1,
+// and the two can be ring modulated.
+
+pub const NUM_OSCILLATORS: usize = 3;
+const OCTAVES: [i32; 5] = [-2, -1, 0, 1, 2];
+const MAX_SEMITONES: i32 = 12;
+const MAX_FINE_CENTS: f64 = 100.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct OscillatorSettings {
+    pub waveform: Waveform,
+    // Frequency relative to the note.
+    pub ratio: f64,
+    pub level: f64,
+}
+
+// Everything the stack needs from the parameters, read once a block.
+#[derive(Clone, Copy, Debug)]
+pub struct StackSettings {
+    pub oscillators: [OscillatorSettings; NUM_OSCILLATORS],
+    pub sync: bool,
+    pub ring_level: f64,
+    pub sub_level: f64,
+    pub wavetable_level: f64,
+}
+
+impl StackSettings {
+    pub fn from_parameters(params: &Parameters) -> Self {
+        let oscillator = |waveform: f32, octave: f32, semitone: f32, fine: f32, level: f32| {
+            let semitones = 12.0 * oscillator_octave(octave) as f64
+                + oscillator_semitone(semitone) as f64
+                + oscillator_fine_cents(fine) / 100.0;
+            OscillatorSettings {
+                waveform: Waveform::from_parameter(waveform),
+                ratio: (semitones / 12.0).exp2(),
+                level: level as f64,
+            }
+        };
+
+        Self {
+            oscillators: [
+                oscillator(
+                    params.osc1_waveform.get(),
+                    params.osc1_octave.get(),
+                    params.osc1_semitone.get(),
+                    params.osc1_fine.get(),
+                    params.osc1_level.get(),
+                ),
+                oscillator(
+                    params.osc2_waveform.get(),
+                    params.osc2_octave.get(),
+                    params.osc2_semitone.get(),
+                    params.osc2_fine.get(),
+                    params.osc2_level.get(),
+                ),
+                oscillator(
+                    params.osc3_waveform.get(),
+                    params.osc3_octave.get(),
+                    params.osc3_semitone.get(),
+                    params.osc3_fine.get(),
+                    params.osc3_level.get(),
+                ),
+            ],
+            sync: choice_index(params.osc_sync.get(), 2) == 1,
+            ring_level: params.ring_mod_level.get() as f64,
+            sub_level: params.sub_level.get() as f64,
+            wavetable_level: params.wavetable_level.get() as f64,
+        }
+    }
+}
+
+// One unison copy of a group's oscillators, each oscillator holding a lane per voice.
+pub struct OscillatorStack {
+    oscillators: [Oscillator; NUM_OSCILLATORS],
+    sub: Oscillator,
+    wavetable: Oscillator,
+}
+
+impl OscillatorStack {
+    pub fn new() -> Self {
+        Self {
+            oscillators: [Oscillator::new(), Oscillator::new(), Oscillator::new()],
+            sub: Oscillator::new(),
+            wavetable: Oscillator::new(),
+        }
+    }
+
+    pub fn reset(&mut self, lane: usize) {
+        for oscillator in &mut self.oscillators {
+            oscillator.reset(lane);
+        }
+        self.sub.reset(lane);
+        self.wavetable.reset(lane);
+    }
+
+    // One output per voice lane. The wavetable (silent until one is loaded) plays from
+    // `wavetable_positions`, 0.0 - 1.0 through its frames.
+    pub fn next_sample(
+        &mut self,
+        settings: &StackSettings,
+        wavetable: Option<&Wavetable>,
+        wavetable_positions: &Lanes,
+        frequencies: &Lanes,
+        pulse_widths: &Lanes,
+        sample_rate: f32,
+    ) -> Lanes {
+        let mut oscillator_frequencies = [[0.0; VOICE_LANES]; NUM_OSCILLATORS];
+        let mut oscillator_outputs = [[0.0; VOICE_LANES]; NUM_OSCILLATORS];
+        for (index, oscillator) in self.oscillators.iter_mut().enumerate() {
+            let oscillator_settings = &settings.oscillators[index];
+            for (lane, frequency) in oscillator_frequencies[index].iter_mut().zip(frequencies) {
+                *lane = frequency * oscillator_settings.ratio;
+            }
+            oscillator_outputs[index] = oscillator.next_samples(
+                oscillator_settings.waveform,
+                pulse_widths,
+                &oscillator_frequencies[index],
+                sample_rate,
+            );
+        }
+        // Oscillator 2 has already made this sample's output, so the sync shows up from the next.
+        if settings.sync {
+            let (master, slaves) = self.oscillators.split_at_mut(1);
+            slaves[0].sync_to(&master[0], &oscillator_frequencies[1], &oscillator_frequencies[0]);
+        }
+
+        let mut sub_frequencies = [0.0; VOICE_LANES];
+        for (lane, frequency) in sub_frequencies.iter_mut().zip(&oscillator_frequencies[0]) {
+            *lane = frequency / 2.0;
+        }
+        let sub = self
+            .sub
+            .next_samples(Waveform::Pulse, &[0.5; VOICE_LANES], &sub_frequencies, sample_rate);
+
+        let [first, second, third] = &oscillator_outputs;
+        let mut outputs = [0.0; VOICE_LANES];
+        for (lane, output) in outputs.iter_mut().enumerate() {
+            *output = first[lane] * second[lane] * settings.ring_level + sub[lane] * settings.sub_level;
+            *output += first[lane] * settings.oscillators[0].level;
+            *output += second[lane] * settings.oscillators[1].level;
+            *output += third[lane] * settings.oscillators[2].level;
+        }
+
+        if let Some(table) = wavetable {
+            let samples = self
+                .wavetable
+                .next_wavetable_samples(table, wavetable_positions, frequencies, sample_rate);
+            for (output, sample) in outputs.iter_mut().zip(&samples) {
+                *output += sample * settings.wavetable_level;
+            }
+        }
+        outputs
+    }
+}
+
+pub fn oscillator_octave(value: f32) -> i32 {
+    OCTAVES[choice_index(value, OCTAVES.len())]
+}
+
+pub fn oscillator_semitone(value: f32) -> i32 {
+    choice_index(value, MAX_SEMITONES as usize * 2 + 1) as i32 - MAX_SEMITONES
+}
+
+pub fn oscillator_fine_cents(value: f32) -> f64 {
+    (value as f64 - 0.5) * 2.0 * MAX_FINE_CENTS
+}
@@ -0,0 +1,208 @@
+use std::f64::consts::PI;
+
+// Runs the voices at 2x, 4x or 8x the sample rate and brings them back down, one half-band
+// lowpass and decimation per octave, so oscillator sync, FM and a screaming filter alias less.
+// Each stage is a linear-phase FIR, computed polyphase: half of a half-band filter's taps are
+// zero, and only every other output is kept, so each output costs about a quarter of the taps.
+
+pub const MAX_FACTOR: usize = 8;
+// Half-band taps either side of the centre that aren't zero, for the last stage (down to the
+// sample rate) and the ones before it. The earlier stages have lots of room between the audio
+// and their Nyquist, so they can be much shorter.
+const LAST_STAGE_TAPS: usize = 16;
+const EARLY_STAGE_TAPS: usize = 4;
+
+struct HalfBand {
+    // The non-zero taps either side of the centre, nearest first. The centre tap is 0.5.
+    taps: Vec<f64>,
+    // The most recent inputs, twice over, so the filter can always read them in one piece.
+    history: Vec<f64>,
+    position: usize,
+}
+
+impl HalfBand {
+    fn new(half_taps: usize) -> Self {
+        // The taps at odd distances from the centre of a windowed sinc at half Nyquist.
+        let length = 4 * half_taps - 1;
+        let centre = (length / 2) as f64;
+        let mut taps: Vec<f64> = (0..half_taps)
+            .map(|k| {
+                let distance = (2 * k + 1) as f64;
+                let x = PI * distance / 2.0;
+                let window = 0.42 + 0.5 * (PI * distance / (centre + 1.0)).cos()
+                    + 0.08 * (2.0 * PI * distance / (centre + 1.0)).cos();
+                0.5 * x.sin() / x * window
+            })
+            .collect();
+        // Unity gain at DC: the centre tap plus both sides should add up to 1.0.
+        let sum: f64 = taps.iter().sum();
+        for tap in &mut taps {
+            *tap *= 0.25 / sum;
+        }
+
+        Self {
+            taps,
+            history: vec![0.0; 2 * length],
+            position: 0,
+        }
+    }
+
+    fn length(&self) -> usize {
+        self.history.len() / 2
+    }
+
+    // In samples at the stage's input rate.
+    fn latency(&self) -> usize {
+        self.length() / 2
+    }
+
+    fn reset(&mut self) {
+        for sample in &mut self.history {
+            *sample = 0.0;
+        }
+    }
+
+    fn push(&mut self, input: f64) {
+        let length = self.length();
+        self.history[self.position] = input;
+        self.history[self.position + length] = input;
+        self.position = (self.position + 1) % length;
+    }
+
+    // Takes two samples and gives back one.
+    fn decimate(&mut self, first: f64, second: f64) -> f64 {
+        self.push(first);
+        self.push(second);
+
+        // Oldest first.
+        let window = &self.history[self.position..self.position + self.length()];
+        let centre = self.latency();
+        let mut output = 0.5 * window[centre];
+        for (k, tap) in self.taps.iter().enumerate() {
+            let distance = 2 * k + 1;
+            output += tap * (window[centre - distance] + window[centre + distance]);
+        }
+        output
+    }
+}
+
+pub struct Oversampler {
+    factor: usize,
+    // For each side, the stages from the sample rate up: 2x -> 1x, then 4x -> 2x, then 8x -> 4x.
+    stages: [Vec<HalfBand>; 2],
+}
+
+impl Oversampler {
+    pub fn new() -> Self {
+        let side = || {
+            (0..num_stages(MAX_FACTOR))
+                .map(|stage| HalfBand::new(if stage == 0 { LAST_STAGE_TAPS } else { EARLY_STAGE_TAPS }))
+                .collect()
+        };
+        Self {
+            factor: 1,
+            stages: [side(), side()],
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    // Whatever's left in the filters belongs to the old rate, so it goes.
+    pub fn set_factor(&mut self, factor: usize) {
+        if factor == self.factor {
+            return;
+        }
+        self.factor = factor;
+        for stage in self.stages.iter_mut().flatten() {
+            stage.reset();
+        }
+    }
+
+    // Brings a block at the oversampled rate down to the sample rate, in place, one stage at a
+    // time: afterwards the first `left.len() / factor` samples of each side are the result.
+    pub fn process(&mut self, left: &mut [f64], right: &mut [f64]) {
+        let mut length = left.len();
+        for stage in (0..num_stages(self.factor)).rev() {
+            length /= 2;
+            for (side, samples) in [&mut *left, &mut *right].iter_mut().enumerate() {
+                let filter = &mut self.stages[side][stage];
+                for index in 0..length {
+                    samples[index] = filter.decimate(samples[2 * index], samples[2 * index + 1]);
+                }
+            }
+        }
+    }
+}
+
+fn num_stages(factor: usize) -> usize {
+    factor.trailing_zeros() as usize
+}
+
+// In samples at the sample rate, rounded to the nearest one. Each stage holds the centre tap back
+// by one output sample fewer than it has taps either side, counted at its own output rate.
+pub fn oversampling_latency(factor: usize) -> usize {
+    let mut latency = 0.0;
+    for stage in 0..num_stages(factor) {
+        let half_taps = if stage == 0 { LAST_STAGE_TAPS } else { EARLY_STAGE_TAPS };
+        let stage_latency = (half_taps - 1) as f64;
+        latency += stage_latency / (1 << stage) as f64;
+    }
+    latency.round() as usize
+}
+
+// Holds a signal back by a whole number of samples, so paths with less latency line up with the
+// ones that have more.
+pub struct LatencyDelay {
+    buffer: [(f64, f64); MAX_FACTOR * 4],
+    position: usize,
+}
+
+impl LatencyDelay {
+    pub fn new() -> Self {
+        Self {
+            buffer: [(0.0, 0.0); MAX_FACTOR * 4],
+            position: 0,
+        }
+    }
+
+    pub fn process(&mut self, input: (f64, f64), delay: usize) -> (f64, f64) {
+        let length = self.buffer.len();
+        self.buffer[self.position] = input;
+        let output = self.buffer[(self.position + length - delay.min(length - 1)) % length];
+        self.position = (self.position + 1) % length;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What comes out at the sample rate for an impulse at the start of the oversampled block,
+    // where a note starting on the first sample would start.
+    fn impulse_response(factor: usize) -> Vec<f64> {
+        let mut oversampler = Oversampler::new();
+        oversampler.set_factor(factor);
+        let mut left = vec![0.0; 64 * factor];
+        let mut right = vec![0.0; 64 * factor];
+        left[0] = 1.0;
+        oversampler.process(&mut left, &mut right);
+        left.truncate(64);
+        left
+    }
+
+    #[test]
+    fn impulse_peaks_at_the_reported_latency() {
+        assert_eq!(oversampling_latency(1), 0);
+        assert_eq!(oversampling_latency(MAX_FACTOR), 17);
+        for &factor in &[2, 4, 8] {
+            let response = impulse_response(factor);
+            let latency = oversampling_latency(factor);
+            // At 4x the peak is halfway between two samples, so it can be a tie.
+            let peak = response.iter().fold(0.0f64, |peak, &sample| peak.max(sample));
+            assert_eq!(response[latency], peak, "{}x: {:?}", factor, response);
+        }
+    }
+}
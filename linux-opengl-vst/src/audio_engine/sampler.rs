@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use super::envelope::Envelope;
+use super::resampler::Resampler;
+use super::unison::pan_gains;
+use super::voice::Expression;
+use crate::sample_map::{LoopMode, SampleMap};
+
+// Plays the sample map alongside the synth voices, with voices of its own. It's always
+// polyphonic, and every zone that a note and velocity fall in plays, so zones can be layered.
+// The sampler doesn't go through the filter, which suits drums.
+
+const MAX_SAMPLER_VOICES: usize = 64;
+// Just enough to get rid of clicks at the start of a note.
+const DECLICK_ATTACK: f64 = 0.001;
+
+struct SamplerVoice {
+    // Index into the map's zones.
+    zone: usize,
+    channel: u8,
+    note: u8,
+    // In the sample's frames.
+    position: f64,
+    // Playback speed relative to the sample's own, before pitch bend and sample rates.
+    pitch: f64,
+    gain: f64,
+    envelope: Envelope,
+    // When this voice was last triggered, for stealing the oldest voice when we run out.
+    age: u64,
+}
+
+impl SamplerVoice {
+    fn new() -> Self {
+        Self {
+            zone: 0,
+            channel: 0,
+            note: 0,
+            position: 0.0,
+            pitch: 1.0,
+            gain: 0.0,
+            envelope: Envelope::new(DECLICK_ATTACK, 0.0, 1.0, 0.0),
+            age: 0,
+        }
+    }
+}
+
+pub struct Sampler {
+    // Shared with `UiState`, which keeps hold of it for patch dumps.
+    map: Option<Arc<SampleMap>>,
+    voices: Vec<SamplerVoice>,
+    resampler: Resampler,
+    voice_counter: u64,
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        let mut voices = Vec::with_capacity(MAX_SAMPLER_VOICES);
+        for _ in 0..MAX_SAMPLER_VOICES {
+            voices.push(SamplerVoice::new());
+        }
+
+        Self {
+            map: None,
+            voices,
+            resampler: Resampler::new(),
+            voice_counter: 0,
+        }
+    }
+
+    pub fn map(&self) -> Option<&SampleMap> {
+        self.map.as_deref()
+    }
+
+    // The voices point at the old map's zones, so they can't keep going.
+    pub fn set_map(&mut self, map: Option<Arc<SampleMap>>) {
+        self.kill_all();
+        self.map = map;
+    }
+
+    pub fn kill_all(&mut self) {
+        for voice in &mut self.voices {
+            voice.envelope.kill();
+        }
+    }
+
+    // `frequency` is what the note plays at with the current tuning. Each zone's root note plays
+    // the sample at its own speed in 12-TET at `reference_pitch`, so the sampler follows
+    // alternative tunings too.
+    pub fn note_on(&mut self, channel: u8, note: u8, velocity: u8, frequency: f64, reference_pitch: f64) {
+        let map = match &self.map {
+            Some(map) => map,
+            None => return,
+        };
+
+        for (index, zone) in map.zones().iter().enumerate() {
+            if !zone.contains(note, velocity) {
+                continue;
+            }
+
+            // A free voice, or if there are none of those, the oldest.
+            let free = self.voices.iter().position(|voice| !voice.envelope.is_active());
+            let voice = match free {
+                Some(free) => &mut self.voices[free],
+                None => self.voices.iter_mut().min_by_key(|voice| voice.age).unwrap(),
+            };
+
+            let root_frequency = reference_pitch * ((zone.root_note as f64 - 69.0) / 12.0).exp2();
+            self.voice_counter += 1;
+            voice.zone = index;
+            voice.channel = channel;
+            voice.note = note;
+            voice.position = 0.0;
+            voice.pitch = frequency / root_frequency * (zone.tune_cents / 1200.0).exp2();
+            // Squared, so velocity follows loudness a bit more like you'd expect.
+            let velocity = velocity as f64 / 127.0;
+            voice.gain = zone.gain * velocity * velocity;
+            voice.age = self.voice_counter;
+            voice.envelope.kill();
+            voice.envelope.trigger();
+        }
+    }
+
+    pub fn note_off(&mut self, channel: u8, note: u8) {
+        let map = match &self.map {
+            Some(map) => map,
+            None => return,
+        };
+
+        for voice in &mut self.voices {
+            let one_shot = map.zones()[voice.zone].loop_mode == LoopMode::OneShot;
+            if voice.envelope.is_gate_open() && voice.channel == channel && voice.note == note && !one_shot {
+                voice.envelope.release();
+            }
+        }
+    }
+
+    // Pitch bend comes from each voice's channel. Returns (left, right).
+    pub fn next_sample(&mut self, expression: &[Expression; 16], release: f64, sample_rate: f32) -> (f64, f64) {
+        let map = match &self.map {
+            Some(map) => map,
+            None => return (0.0, 0.0),
+        };
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for voice in &mut self.voices {
+            if !voice.envelope.is_active() {
+                continue;
+            }
+            let zone = &map.zones()[voice.zone];
+            let audio = &map.file(zone.file).audio;
+            let num_frames = audio.num_frames();
+
+            voice.envelope.set_adsr(DECLICK_ATTACK, 0.0, 1.0, release);
+            let level = voice.envelope.next_sample(sample_rate);
+
+            // A sustain loop lets go once the key does, and plays on to the end.
+            let looping = match zone.loop_mode {
+                LoopMode::Continuous => true,
+                LoopMode::Sustain => voice.envelope.is_gate_open(),
+                _ => false,
+            };
+            if looping {
+                let loop_length = (zone.loop_end + 1 - zone.loop_start) as f64;
+                while voice.position >= zone.loop_end as f64 + 1.0 {
+                    voice.position -= loop_length;
+                }
+            } else if voice.position >= num_frames as f64 {
+                voice.envelope.kill();
+                continue;
+            }
+
+            let bend = (expression[voice.channel as usize & 0x0F].pitch_bend as f64 / 12.0).exp2();
+            let step = voice.pitch * bend * audio.sample_rate / sample_rate as f64;
+            let channels = &audio.channels;
+            let (sample_left, sample_right) = self.resampler.interpolate(voice.position, step, |i| {
+                // While looping, the frames past the loop's end are the ones from its start.
+                let i = if looping && i > zone.loop_end as isize {
+                    let loop_length = (zone.loop_end + 1 - zone.loop_start) as isize;
+                    zone.loop_start as isize + (i - zone.loop_start as isize) % loop_length
+                } else {
+                    i
+                };
+                if i < 0 || i as usize >= num_frames {
+                    return (0.0, 0.0);
+                }
+                let first = channels[0][i as usize];
+                (first, channels.get(1).map_or(first, |second| second[i as usize]))
+            });
+            voice.position += step;
+
+            let (left_gain, right_gain) = pan_gains(zone.pan);
+            let gain = voice.gain * level;
+            left += sample_left * left_gain * gain;
+            right += sample_right * right_gain * gain;
+        }
+        (left, right)
+    }
+}
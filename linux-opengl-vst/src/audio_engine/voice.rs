@@ -0,0 +1,770 @@
+use super::envelope::EnvelopeLanes;
+use super::filter::{
+    envelope_time_seconds, filter_cutoff_hz, filter_env_octaves, FilterCoefficients, FilterMode, StateVariableFilter,
+};
+use super::fm::{FmSettings, NUM_OPERATORS};
+use super::glide::{GlideLanes, Portamento};
+use super::lfo::{Lfo, LfoSettings};
+use super::oscillator_stack::StackSettings;
+use super::unison::{unison_count, Unison, UnisonInputs, UnisonSettings};
+use super::SynthMode;
+use crate::mod_matrix::{ModDestination, ModSlot, ModSource, NUM_DESTINATIONS};
+use crate::parameters::{pressure_to_pulse_width, Parameters};
+use crate::wavetable::Wavetable;
+
+// The voices are laid out in groups of this many. A group keeps its voices' state side by side in
+// arrays, one element (lane) per voice, and renders a piece of a block one stage at a time: all the
+// envelopes for every sample, then the modulation matrix, then the pitches, and so on. Each stage
+// is a loop over samples and lanes with the settings worked out once, rather than one voice at a
+// time going through everything for each sample.
+pub const VOICE_LANES: usize = 8;
+
+// Just enough of an envelope to get rid of clicks at the start and end of notes.
+const DECLICK_ATTACK: f64 = 0.002;
+const DECLICK_RELEASE: f64 = 0.01;
+
+// Key tracking follows the pitch relative to this (middle C), where the cutoff is what the
+// parameter says.
+const KEY_TRACKING_CENTER: f64 = 261.625_565;
+
+// What the modulation matrix's destinations do at full amount.
+const MOD_PITCH_SEMITONES: f64 = 12.0;
+const MOD_PULSE_WIDTH: f64 = 0.5;
+const MOD_CUTOFF_OCTAVES: f64 = 6.0;
+const MOD_PAN: f64 = 1.0;
+const MOD_UNISON_DETUNE: f64 = 1.0;
+const MOD_FM_INDEX: f64 = 1.0;
+const MOD_WAVETABLE_POSITION: f64 = 1.0;
+
+// Everything a voice needs from the parameters, read once a block.
+#[derive(Clone, Copy, Debug)]
+pub struct VoiceSettings {
+    pub amplitude: f64,
+    pub pulse_width: f32,
+    pub pressure_to_pulse_width: f64,
+    pub pressure_to_amplitude: f64,
+    pub unison_count: usize,
+    pub unison_detune: f64,
+    pub unison_width: f64,
+    pub pan: f64, // -1.0 (left) - 1.0 (right)
+    pub wavetable_position: f64,
+    pub filter_mode: FilterMode,
+    pub filter_cutoff: f64, // Hz
+    pub filter_resonance: f64,
+    pub filter_key_tracking: f64,
+    pub filter_env_octaves: f64,
+    // Attack, decay, sustain, release, like `Envelope::set_adsr()`.
+    pub filter_envelope: (f64, f64, f64, f64),
+}
+
+impl VoiceSettings {
+    pub fn from_parameters(params: &Parameters) -> Self {
+        Self {
+            amplitude: params.amplitude.get() as f64,
+            pulse_width: params.pulse_width.get(),
+            pressure_to_pulse_width: pressure_to_pulse_width(params.pressure_to_pulse_width.get()),
+            pressure_to_amplitude: params.pressure_to_amplitude.get() as f64,
+            unison_count: unison_count(params.unison_voices.get()),
+            unison_detune: params.unison_detune.get() as f64,
+            unison_width: params.unison_width.get() as f64,
+            pan: params.pan.get() as f64 * 2.0 - 1.0,
+            wavetable_position: params.wavetable_position.get() as f64,
+            filter_mode: FilterMode::from_parameter(params.filter_mode.get()),
+            filter_cutoff: filter_cutoff_hz(params.filter_cutoff.get()),
+            filter_resonance: params.filter_resonance.get() as f64,
+            filter_key_tracking: params.filter_key_tracking.get() as f64,
+            filter_env_octaves: filter_env_octaves(params.filter_env_amount.get()),
+            filter_envelope: (
+                envelope_time_seconds(params.filter_attack.get()),
+                envelope_time_seconds(params.filter_decay.get()),
+                params.filter_sustain.get() as f64,
+                envelope_time_seconds(params.filter_release.get()),
+            ),
+        }
+    }
+}
+
+// What every voice needs from the engine for a block. LFO 1 is shared, and changes every sample,
+// so it comes separately.
+pub struct BlockContext<'a> {
+    pub sample_rate: f32,
+    pub mod_wheel: f64,
+    pub lfo2: LfoSettings,
+    // LFO 2's rate, worked out once for the block since it can depend on the tempo.
+    pub lfo2_frequency: f64,
+    pub voice: VoiceSettings,
+    pub synth_mode: SynthMode,
+    pub oscillators: StackSettings,
+    pub fm: FmSettings,
+    pub wavetable: Option<&'a Wavetable>,
+    pub mod_slots: &'a [ModSlot],
+}
+
+// Per-note expression, from pitch bend, channel pressure and CC74 (slide). In MPE mode each note
+// has a channel to itself, so each voice gets its own; otherwise they all share channel 0.
+#[derive(Clone, Copy, Debug)]
+pub struct Expression {
+    pub pitch_bend: f32, // semitones
+    pub pressure: f32,   // 0.0 - 1.0
+    pub slide: f32,      // 0.0 - 1.0, centered on 0.5
+}
+
+impl Expression {
+    pub fn new() -> Self {
+        Self {
+            pitch_bend: 0.0,
+            pressure: 0.0,
+            slide: 0.5,
+        }
+    }
+}
+
+// A value for every lane.
+pub type Lanes = [f64; VOICE_LANES];
+
+// Every voice, a group of lanes at a time. Voices are picked out by their index, as though they
+// were all in one list.
+pub struct Voices {
+    groups: Vec<VoiceGroup>,
+    buffers: GroupBuffers,
+}
+
+impl Voices {
+    // `max_samples` is the longest piece of a block `render()` gets.
+    pub fn new(count: usize, max_samples: usize) -> Self {
+        let mut groups = Vec::new();
+        while groups.len() * VOICE_LANES < count {
+            groups.push(VoiceGroup::new());
+        }
+        Self {
+            groups,
+            buffers: GroupBuffers::new(max_samples),
+        }
+    }
+
+    fn lane(&self, voice: usize) -> (&VoiceGroup, usize) {
+        (&self.groups[voice / VOICE_LANES], voice % VOICE_LANES)
+    }
+
+    fn lane_mut(&mut self, voice: usize) -> (&mut VoiceGroup, usize) {
+        (&mut self.groups[voice / VOICE_LANES], voice % VOICE_LANES)
+    }
+
+    pub fn channel(&self, voice: usize) -> u8 {
+        let (group, lane) = self.lane(voice);
+        group.channel[lane]
+    }
+
+    pub fn note(&self, voice: usize) -> u8 {
+        let (group, lane) = self.lane(voice);
+        group.note[lane]
+    }
+
+    pub fn age(&self, voice: usize) -> u64 {
+        let (group, lane) = self.lane(voice);
+        group.age[lane]
+    }
+
+    // The pitch right now, which might be partway through a glide.
+    pub fn frequency(&self, voice: usize) -> f64 {
+        let (group, lane) = self.lane(voice);
+        group.glides.frequency(lane)
+    }
+
+    pub fn is_active(&self, voice: usize) -> bool {
+        let (group, lane) = self.lane(voice);
+        group.envelopes.is_active(lane)
+    }
+
+    pub fn is_gate_open(&self, voice: usize) -> bool {
+        let (group, lane) = self.lane(voice);
+        group.envelopes.is_gate_open(lane)
+    }
+
+    // Starts the envelopes again. The note to play comes from `change_note()`, straight after.
+    pub fn trigger(&mut self, voice: usize, age: u64) {
+        let (group, lane) = self.lane_mut(voice);
+        if !group.envelopes.is_active(lane) {
+            group.unison.reset(lane);
+            for filter in &mut group.filters {
+                filter.reset(lane);
+            }
+        }
+        group.key_pressure[lane] = 0.0;
+        group.pulse_width_lock[lane] = None;
+        group.age[lane] = age;
+        group.envelopes.trigger(lane);
+        group.filter_envelopes.trigger(lane);
+        for envelopes in &mut group.fm_envelopes {
+            envelopes.trigger(lane);
+        }
+    }
+
+    // For LFO 2 set to restart on every note.
+    pub fn reset_lfo(&mut self, voice: usize) {
+        let (group, lane) = self.lane_mut(voice);
+        group.lfos[lane].reset();
+    }
+
+    // Moves to a different pitch without retriggering anything (legato).
+    pub fn change_note(
+        &mut self,
+        voice: usize,
+        channel: u8,
+        note: u8,
+        frequency: f64,
+        portamento: Option<Portamento>,
+        sample_rate: f32,
+    ) {
+        let (group, lane) = self.lane_mut(voice);
+        group.channel[lane] = channel;
+        group.note[lane] = note;
+        match portamento {
+            Some(portamento) => group.glides.glide_to(lane, frequency, portamento, sample_rate),
+            None => group.glides.jump_to(lane, frequency),
+        }
+    }
+
+    pub fn set_expression(&mut self, voice: usize, expression: Expression) {
+        let (group, lane) = self.lane_mut(voice);
+        group.expression[lane] = expression;
+    }
+
+    pub fn set_velocity(&mut self, voice: usize, velocity: u8) {
+        let (group, lane) = self.lane_mut(voice);
+        group.velocity[lane] = velocity as f64 / 127.0;
+    }
+
+    pub fn set_key_pressure(&mut self, voice: usize, pressure: f32) {
+        let (group, lane) = self.lane_mut(voice);
+        group.key_pressure[lane] = pressure;
+    }
+
+    pub fn set_pulse_width_lock(&mut self, voice: usize, pulse_width: f32) {
+        let (group, lane) = self.lane_mut(voice);
+        group.pulse_width_lock[lane] = Some(pulse_width);
+    }
+
+    pub fn release(&mut self, voice: usize) {
+        let (group, lane) = self.lane_mut(voice);
+        group.envelopes.release(lane);
+        group.filter_envelopes.release(lane);
+        for envelopes in &mut group.fm_envelopes {
+            envelopes.release(lane);
+        }
+    }
+
+    pub fn kill(&mut self, voice: usize) {
+        let (group, lane) = self.lane_mut(voice);
+        group.envelopes.kill(lane);
+        group.filter_envelopes.kill(lane);
+        for envelopes in &mut group.fm_envelopes {
+            envelopes.kill(lane);
+        }
+    }
+
+    // Adds every voice that's playing to `left` and `right`, with LFO 1's value for each sample in
+    // `lfo1`. Voices that finish partway through stop there.
+    pub fn render(&mut self, context: &BlockContext, lfo1: &[f64], left: &mut [f64], right: &mut [f64]) {
+        for group in &mut self.groups {
+            group.render(context, lfo1, &mut self.buffers, left, right);
+        }
+    }
+}
+
+// One group's worth of voices.
+struct VoiceGroup {
+    unison: Unison,
+    envelopes: EnvelopeLanes,
+    // One for each side, left and right.
+    filters: [StateVariableFilter; 2],
+    filter_envelopes: EnvelopeLanes,
+    fm_envelopes: [EnvelopeLanes; NUM_OPERATORS],
+    glides: GlideLanes,
+    lfos: [Lfo; VOICE_LANES],
+    expression: [Expression; VOICE_LANES],
+    velocity: Lanes, // 0.0 - 1.0
+    // Polyphonic aftertouch for each voice's key alone.
+    key_pressure: [f32; VOICE_LANES],
+    // Set by the step sequencer for notes that play at a fixed pulse width.
+    pulse_width_lock: [Option<f32>; VOICE_LANES],
+    channel: [u8; VOICE_LANES],
+    note: [u8; VOICE_LANES],
+    // When each voice was last triggered, for stealing the oldest voice when we run out.
+    age: [u64; VOICE_LANES],
+}
+
+// Scratch space for rendering a group: what each stage comes to, for every sample of the piece of
+// the block and every lane. The groups render one after another, so they all share it.
+struct GroupBuffers {
+    amp_envelope: Vec<Lanes>,
+    filter_envelope: Vec<Lanes>,
+    // One for each operator.
+    fm_envelopes: Vec<Vec<Lanes>>,
+    lfo: Vec<Lanes>,
+    // One for each destination, in the order of `DESTINATIONS`.
+    modulation: Vec<Vec<Lanes>>,
+    frequency: Vec<Lanes>,
+    left: Vec<Lanes>,
+    right: Vec<Lanes>,
+}
+
+impl GroupBuffers {
+    fn new(max_samples: usize) -> Self {
+        let buffer = || vec![[0.0; VOICE_LANES]; max_samples];
+        Self {
+            amp_envelope: buffer(),
+            filter_envelope: buffer(),
+            fm_envelopes: (0..NUM_OPERATORS).map(|_| buffer()).collect(),
+            lfo: buffer(),
+            modulation: (0..NUM_DESTINATIONS).map(|_| buffer()).collect(),
+            frequency: buffer(),
+            left: buffer(),
+            right: buffer(),
+        }
+    }
+}
+
+// Everything the modulation matrix can use, for a group over a piece of a block. Velocity and
+// aftertouch only change between pieces, and the mod wheel is the same for every voice.
+struct ModSourceLanes<'a> {
+    lfo1: &'a [f64],
+    lfo2: &'a [Lanes],
+    amp_envelope: &'a [Lanes],
+    filter_envelope: &'a [Lanes],
+    velocity: Lanes,
+    mod_wheel: f64,
+    aftertouch: Lanes,
+}
+
+impl VoiceGroup {
+    fn new() -> Self {
+        Self {
+            unison: Unison::new(),
+            envelopes: EnvelopeLanes::new(DECLICK_ATTACK, 0.0, 1.0, DECLICK_RELEASE),
+            filters: [StateVariableFilter::new(), StateVariableFilter::new()],
+            filter_envelopes: EnvelopeLanes::new(0.0, 0.0, 1.0, 0.0),
+            fm_envelopes: [
+                EnvelopeLanes::new(0.0, 0.0, 1.0, 0.0),
+                EnvelopeLanes::new(0.0, 0.0, 1.0, 0.0),
+                EnvelopeLanes::new(0.0, 0.0, 1.0, 0.0),
+                EnvelopeLanes::new(0.0, 0.0, 1.0, 0.0),
+            ],
+            glides: GlideLanes::new(),
+            lfos: [Lfo::new(); VOICE_LANES],
+            expression: [Expression::new(); VOICE_LANES],
+            velocity: [0.0; VOICE_LANES],
+            key_pressure: [0.0; VOICE_LANES],
+            pulse_width_lock: [None; VOICE_LANES],
+            channel: [0; VOICE_LANES],
+            note: [0; VOICE_LANES],
+            age: [0; VOICE_LANES],
+        }
+    }
+
+    // The pressure modulation source: whichever is higher of channel pressure (per note in MPE)
+    // and poly aftertouch, so it works the same from either kind of controller.
+    fn pressures(&self) -> Lanes {
+        let mut pressures = [0.0; VOICE_LANES];
+        for (lane, pressure) in pressures.iter_mut().enumerate() {
+            *pressure = self.expression[lane].pressure.max(self.key_pressure[lane]) as f64;
+        }
+        pressures
+    }
+
+    fn render(
+        &mut self,
+        context: &BlockContext,
+        lfo1: &[f64],
+        buffers: &mut GroupBuffers,
+        left: &mut [f64],
+        right: &mut [f64],
+    ) {
+        if !(0..VOICE_LANES).any(|lane| self.envelopes.is_active(lane)) {
+            return;
+        }
+        let settings = &context.voice;
+        let sample_rate = context.sample_rate;
+        let length = lfo1.len();
+        let GroupBuffers {
+            amp_envelope,
+            filter_envelope,
+            fm_envelopes,
+            lfo: lfo2,
+            modulation,
+            frequency,
+            left: voice_left,
+            right: voice_right,
+        } = buffers;
+        let amp_envelope = &mut amp_envelope[..length];
+        let filter_envelope = &mut filter_envelope[..length];
+        let lfo2 = &mut lfo2[..length];
+        let frequency = &mut frequency[..length];
+        let voice_left = &mut voice_left[..length];
+        let voice_right = &mut voice_right[..length];
+
+        // In FM mode the carriers' envelopes shape the sound, so the voice has to keep going
+        // until their releases are done.
+        let release = match context.synth_mode {
+            SynthMode::Subtractive => DECLICK_RELEASE,
+            SynthMode::Fm => context.fm.longest_release().max(DECLICK_RELEASE),
+        };
+        self.envelopes.set_adsr(DECLICK_ATTACK, 0.0, 1.0, release);
+        // How many samples each voice plays for: all of them, unless it finishes first. Nothing
+        // else in a lane moves on past that.
+        let lengths = self.envelopes.render([length; VOICE_LANES], sample_rate, amp_envelope);
+
+        let (attack, decay, sustain, release) = settings.filter_envelope;
+        self.filter_envelopes.set_adsr(attack, decay, sustain, release);
+        self.filter_envelopes.render(lengths, sample_rate, filter_envelope);
+        if context.synth_mode == SynthMode::Fm {
+            for (operator, envelopes) in self.fm_envelopes.iter_mut().enumerate() {
+                let (attack, decay, sustain, release) = context.fm.operators[operator].envelope;
+                envelopes.set_adsr(attack, decay, sustain, release);
+                envelopes.render(lengths, sample_rate, &mut fm_envelopes[operator][..length]);
+            }
+        }
+        for (lane, lfo) in self.lfos.iter_mut().enumerate() {
+            for lanes in &mut lfo2[..lengths[lane]] {
+                lanes[lane] = lfo.next_sample(context.lfo2.shape, context.lfo2_frequency, sample_rate);
+            }
+        }
+
+        let pressures = self.pressures();
+        let sources = ModSourceLanes {
+            lfo1,
+            lfo2,
+            amp_envelope,
+            filter_envelope,
+            velocity: self.velocity,
+            mod_wheel: context.mod_wheel,
+            aftertouch: pressures,
+        };
+        modulate(context.mod_slots, &sources, modulation);
+        let modulation: &[Vec<Lanes>] = modulation;
+        let modulation = |destination: ModDestination| &modulation[destination.index()][..length];
+
+        let mut pitch_bends = [0.0; VOICE_LANES];
+        for (lane, pitch_bend) in pitch_bends.iter_mut().enumerate() {
+            *pitch_bend = self.expression[lane].pitch_bend as f64;
+        }
+        let pitch_modulation = modulation(ModDestination::Pitch);
+        for (index, (frequencies, pitch)) in frequency.iter_mut().zip(pitch_modulation).enumerate() {
+            let mut active = [false; VOICE_LANES];
+            for (lane, active) in active.iter_mut().enumerate() {
+                *active = index < lengths[lane];
+            }
+            let glides = self.glides.next_frequencies(&active);
+            for (lane, frequency) in frequencies.iter_mut().enumerate() {
+                let semitones = pitch_bends[lane] + pitch[lane] * MOD_PITCH_SEMITONES;
+                *frequency = if semitones == 0.0 {
+                    glides[lane]
+                } else {
+                    glides[lane] * (semitones / 12.0).exp2()
+                };
+            }
+        }
+
+        // The oscillators run every lane, finished or not, so they can go through all the voices
+        // together. A finished voice's output is left out of the filter and the mix, and its
+        // oscillators start again when it's next triggered.
+        //
+        // Slide moves the pulse width either way from the parameter (or the sequencer's lock).
+        // Pressure goes wherever its amount parameters send it.
+        let mut pulse_widths = [0.0; VOICE_LANES];
+        for (lane, pulse_width) in pulse_widths.iter_mut().enumerate() {
+            *pulse_width = self.pulse_width_lock[lane].unwrap_or(settings.pulse_width) as f64
+                + self.expression[lane].slide as f64 - 0.5
+                + pressures[lane] * settings.pressure_to_pulse_width;
+        }
+        let detune_modulation = modulation(ModDestination::UnisonDetune);
+        let pan_modulation = modulation(ModDestination::Pan);
+        let pulse_width_modulation = modulation(ModDestination::PulseWidth);
+        let position_modulation = modulation(ModDestination::WavetablePosition);
+        let frequency: &[Lanes] = frequency;
+        let inputs = |index: usize| {
+            let mut inputs = UnisonInputs {
+                frequency: frequency[index],
+                detune: [0.0; VOICE_LANES],
+                pan: [0.0; VOICE_LANES],
+                pulse_width: [0.0; VOICE_LANES],
+                wavetable_position: [0.0; VOICE_LANES],
+            };
+            for lane in 0..VOICE_LANES {
+                inputs.detune[lane] = settings.unison_detune + detune_modulation[index][lane] * MOD_UNISON_DETUNE;
+                inputs.pan[lane] = settings.pan + pan_modulation[index][lane] * MOD_PAN;
+                inputs.pulse_width[lane] = pulse_widths[lane] + pulse_width_modulation[index][lane] * MOD_PULSE_WIDTH;
+                inputs.wavetable_position[lane] =
+                    settings.wavetable_position + position_modulation[index][lane] * MOD_WAVETABLE_POSITION;
+            }
+            inputs
+        };
+        let unison_settings = UnisonSettings::new(settings.unison_count, settings.unison_width);
+        match context.synth_mode {
+            SynthMode::Subtractive => {
+                for index in 0..length {
+                    let (left, right) = self.unison.next_oscillator_sample(
+                        &unison_settings,
+                        &inputs(index),
+                        &context.oscillators,
+                        context.wavetable,
+                        sample_rate,
+                    );
+                    voice_left[index] = left;
+                    voice_right[index] = right;
+                }
+            }
+            SynthMode::Fm => {
+                let index_modulation = modulation(ModDestination::FmIndex);
+                for index in 0..length {
+                    let envelopes = [
+                        &fm_envelopes[0][index],
+                        &fm_envelopes[1][index],
+                        &fm_envelopes[2][index],
+                        &fm_envelopes[3][index],
+                    ];
+                    let levels = fm_levels(&context.fm, envelopes, &index_modulation[index]);
+                    let (left, right) =
+                        self.unison
+                            .next_fm_sample(&unison_settings, &inputs(index), &context.fm, &levels, sample_rate);
+                    voice_left[index] = left;
+                    voice_right[index] = right;
+                }
+            }
+        }
+
+        let cutoff_modulation = modulation(ModDestination::FilterCutoff);
+        let resonance_modulation = modulation(ModDestination::FilterResonance);
+        let mut coefficients = FilterCoefficients::new();
+        let [left_filter, right_filter] = &mut self.filters;
+        for index in 0..length {
+            let mut active = [false; VOICE_LANES];
+            for lane in 0..VOICE_LANES {
+                active[lane] = index < lengths[lane];
+                if active[lane] {
+                    let cutoff = filter_cutoff(
+                        settings,
+                        frequency[index][lane],
+                        filter_envelope[index][lane],
+                        cutoff_modulation[index][lane],
+                    );
+                    let resonance = settings.filter_resonance + resonance_modulation[index][lane];
+                    coefficients.set(lane, cutoff, resonance, sample_rate);
+                }
+            }
+            let mode = settings.filter_mode;
+            voice_left[index] = left_filter.process(&voice_left[index], mode, &coefficients, &active);
+            voice_right[index] = right_filter.process(&voice_right[index], mode, &coefficients, &active);
+        }
+
+        // Lane by lane within each sample, so the voices add up in the same order whichever
+        // group they're in.
+        let amplitude_modulation = modulation(ModDestination::Amplitude);
+        for index in 0..length {
+            for lane in 0..VOICE_LANES {
+                if index < lengths[lane] {
+                    let level = (1.0 + pressures[lane] * settings.pressure_to_amplitude)
+                        * (1.0 + amplitude_modulation[index][lane]).max(0.0);
+                    let gain = level * amp_envelope[index][lane] * settings.amplitude;
+                    left[index] += voice_left[index][lane] * gain;
+                    right[index] += voice_right[index][lane] * gain;
+                }
+            }
+        }
+    }
+}
+
+// Works out what the matrix adds up to for each destination, for every sample and lane. Each is
+// -1.0 - 1.0 at full amount; the voices decide what that means in semitones, octaves and so on.
+fn modulate(slots: &[ModSlot], sources: &ModSourceLanes, modulation: &mut [Vec<Lanes>]) {
+    let length = sources.lfo1.len();
+    for destination in modulation.iter_mut() {
+        for lanes in &mut destination[..length] {
+            *lanes = [0.0; VOICE_LANES];
+        }
+    }
+
+    for slot in slots {
+        if slot.destination == ModDestination::Off {
+            continue;
+        }
+        let destination = &mut modulation[slot.destination.index()][..length];
+        let amount = slot.amount as f64;
+        match slot.source {
+            ModSource::Off => {}
+            ModSource::Lfo1 => {
+                for (lanes, value) in destination.iter_mut().zip(sources.lfo1) {
+                    for lane in lanes.iter_mut() {
+                        *lane += value * amount;
+                    }
+                }
+            }
+            ModSource::Lfo2 => add_each_sample(destination, sources.lfo2, amount),
+            ModSource::AmpEnvelope => add_each_sample(destination, sources.amp_envelope, amount),
+            ModSource::FilterEnvelope => add_each_sample(destination, sources.filter_envelope, amount),
+            ModSource::Velocity => add_each_lane(destination, &sources.velocity, amount),
+            ModSource::ModWheel => add_each_lane(destination, &[sources.mod_wheel; VOICE_LANES], amount),
+            ModSource::Aftertouch => add_each_lane(destination, &sources.aftertouch, amount),
+        }
+    }
+}
+
+// A source that changes every sample.
+fn add_each_sample(destination: &mut [Lanes], source: &[Lanes], amount: f64) {
+    for (lanes, values) in destination.iter_mut().zip(source) {
+        for (lane, value) in lanes.iter_mut().zip(values) {
+            *lane += value * amount;
+        }
+    }
+}
+
+// A source that holds still for the whole piece of the block.
+fn add_each_lane(destination: &mut [Lanes], values: &Lanes, amount: f64) {
+    for lanes in destination.iter_mut() {
+        for (lane, value) in lanes.iter_mut().zip(values) {
+            *lane += value * amount;
+        }
+    }
+}
+
+// Each operator's level, with its envelope and the matrix's FM index applied. Modulators get
+// the matrix's index modulation; carriers are left alone, since that'd just be volume.
+fn fm_levels(fm: &FmSettings, envelopes: [&Lanes; NUM_OPERATORS], index_modulation: &Lanes) -> [Lanes; NUM_OPERATORS] {
+    let mut levels = [[0.0; VOICE_LANES]; NUM_OPERATORS];
+    for (operator, levels) in levels.iter_mut().enumerate() {
+        let level = fm.operators[operator].level;
+        let lanes = levels.iter_mut().zip(envelopes[operator]);
+        if fm.is_carrier(operator) {
+            for (lane, envelope) in lanes {
+                *lane = level * envelope;
+            }
+        } else {
+            for ((lane, envelope), modulation) in lanes.zip(index_modulation) {
+                *lane = level * envelope * (1.0 + modulation * MOD_FM_INDEX).max(0.0);
+            }
+        }
+    }
+    levels
+}
+
+// The envelope, key tracking and modulation all move the cutoff in octaves, so they add up the
+// way they sound.
+fn filter_cutoff(settings: &VoiceSettings, frequency: f64, envelope: f64, modulation: f64) -> f64 {
+    let octaves = envelope * settings.filter_env_octaves
+        + (frequency / KEY_TRACKING_CENTER).log2() * settings.filter_key_tracking
+        + modulation * MOD_CUTOFF_OCTAVES;
+    settings.filter_cutoff * octaves.exp2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::Parameters;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const BLOCK: usize = 64;
+    const VOICES: usize = 32;
+
+    // Renders `blocks` pieces with the default parameters, and some modulation that's different
+    // for every voice.
+    fn render(voices: &mut Voices, blocks: usize) -> Vec<(f64, f64)> {
+        let params = Parameters::new();
+        let lfo2 = LfoSettings::new(
+            params.lfo2_shape.get(),
+            params.lfo2_rate.get(),
+            params.lfo2_sync.get(),
+            params.lfo2_retrigger.get(),
+        );
+        let slots = [
+            ModSlot { source: ModSource::Lfo2, destination: ModDestination::Pitch, amount: 0.1 },
+            ModSlot { source: ModSource::Velocity, destination: ModDestination::FilterCutoff, amount: 0.5 },
+            ModSlot { source: ModSource::AmpEnvelope, destination: ModDestination::Pan, amount: -0.5 },
+        ];
+        let context = BlockContext {
+            sample_rate: SAMPLE_RATE,
+            mod_wheel: 0.0,
+            lfo2,
+            lfo2_frequency: lfo2.frequency(120.0),
+            voice: VoiceSettings::from_parameters(&params),
+            synth_mode: SynthMode::Subtractive,
+            oscillators: StackSettings::from_parameters(&params),
+            fm: FmSettings::from_parameters(&params),
+            wavetable: None,
+            mod_slots: &slots,
+        };
+
+        let lfo1 = [0.0; BLOCK];
+        let mut output = Vec::new();
+        for _ in 0..blocks {
+            let (mut left, mut right) = ([0.0; BLOCK], [0.0; BLOCK]);
+            voices.render(&context, &lfo1, &mut left, &mut right);
+            output.extend(left.iter().cloned().zip(right.iter().cloned()));
+        }
+        output
+    }
+
+    fn play(voices: &mut Voices, voice: usize, note: u8, velocity: u8) {
+        let frequency = 440.0 * ((note as f64 - 69.0) / 12.0).exp2();
+        voices.trigger(voice, 1);
+        voices.change_note(voice, 0, note, frequency, None, SAMPLE_RATE);
+        voices.set_velocity(voice, velocity);
+    }
+
+    #[test]
+    fn every_lane_sounds_the_same() {
+        let mut first = Voices::new(VOICES, BLOCK);
+        play(&mut first, 0, 60, 100);
+        let expected = render(&mut first, 20);
+        assert!(expected.iter().any(|&(left, right)| left != 0.0 && right != 0.0));
+
+        for &voice in &[1, VOICE_LANES - 1, VOICE_LANES, VOICES - 1] {
+            let mut voices = Voices::new(VOICES, BLOCK);
+            play(&mut voices, voice, 60, 100);
+            assert!(render(&mut voices, 20) == expected, "voice {}", voice);
+        }
+    }
+
+    #[test]
+    fn voices_dont_affect_each_other() {
+        let notes = [(0, 48, 20), (3, 55, 127), (VOICE_LANES + 2, 67, 64)];
+        let mut alone = Vec::new();
+        for &(voice, note, velocity) in &notes {
+            let mut voices = Voices::new(VOICES, BLOCK);
+            play(&mut voices, voice, note, velocity);
+            alone.push(render(&mut voices, 20));
+        }
+
+        let mut voices = Voices::new(VOICES, BLOCK);
+        for &(voice, note, velocity) in &notes {
+            play(&mut voices, voice, note, velocity);
+        }
+        let together = render(&mut voices, 20);
+        for (index, &(left, right)) in together.iter().enumerate() {
+            // Added up in the same order as the voices, so it comes out exactly the same.
+            let sum = alone.iter().fold((0.0, 0.0), |sum, output| (sum.0 + output[index].0, sum.1 + output[index].1));
+            assert_eq!((left, right), sum, "sample {}", index);
+        }
+    }
+
+    #[test]
+    fn finished_voices_stop_partway_through() {
+        let mut voices = Voices::new(VOICES, BLOCK);
+        play(&mut voices, 2, 60, 100);
+        play(&mut voices, 5, 64, 100);
+        render(&mut voices, 10);
+        voices.release(2);
+        voices.kill(5);
+        assert!(!voices.is_active(5));
+
+        // The declick release is 10 ms, so it's done well within 20 pieces.
+        let output = render(&mut voices, 20);
+        assert!(!voices.is_active(2) && !voices.is_gate_open(2));
+        let last = output.iter().rposition(|&(left, right)| left != 0.0 || right != 0.0).unwrap();
+        assert!(last > 0 && last < output.len() - BLOCK);
+
+        // And they can be played again.
+        play(&mut voices, 2, 60, 100);
+        assert!(voices.is_gate_open(2));
+        assert!(render(&mut voices, 1).iter().any(|&(left, _)| left != 0.0));
+    }
+}
@@ -0,0 +1,330 @@
+use super::{decode_samples, AudioFile, SampleFormat};
+
+// AIFF, and the uncompressed kinds of AIFF-C: big-endian ("NONE"), little-endian ("sowt") and
+// float ("fl32"/"fl64"). Everything in the file itself is big-endian.
+
+struct Common {
+    channels: usize,
+    sample_format: SampleFormat,
+    sample_rate: f64,
+    little_endian: bool,
+}
+
+// Reads big-endian values, returning None once it runs out of data.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.position + count > self.data.len() {
+            return None;
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.bytes(4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // The 80-bit extended float that AIFF uses for sample rates.
+    fn extended(&mut self) -> Option<f64> {
+        let bytes = self.bytes(10)?;
+        let exponent = (((bytes[0] & 0x7F) as i32) << 8 | bytes[1] as i32) - 16383;
+        let mut mantissa = [0u8; 8];
+        mantissa.copy_from_slice(&bytes[2..10]);
+        let value = u64::from_be_bytes(mantissa) as f64 * 2f64.powi(exponent - 63);
+        Some(if bytes[0] & 0x80 != 0 { -value } else { value })
+    }
+}
+
+pub fn parse(data: &[u8]) -> Result<AudioFile, String> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(4) != Some(&b"FORM"[..]) {
+        return Err("not an IFF file".to_string());
+    }
+    let _form_length = reader.u32();
+    let compressed = match reader.bytes(4) {
+        Some(b"AIFF") => false,
+        Some(b"AIFC") => true,
+        _ => return Err("not an AIFF file".to_string()),
+    };
+
+    let mut common = None;
+    let mut sample_data = None;
+    let mut markers = Vec::new();
+    let mut instrument = None;
+    while let Some(tag) = reader.bytes(4) {
+        let length = reader.u32().ok_or("truncated chunk header")? as usize;
+        let payload = match reader.bytes(length) {
+            Some(payload) => payload,
+            None => break,
+        };
+        if length % 2 == 1 {
+            reader.bytes(1);
+        }
+
+        match tag {
+            b"COMM" => common = Some(parse_common(payload, compressed)?),
+            b"SSND" => {
+                // Skip the offset and block size, plus however much padding the offset says.
+                let mut sound = Reader::new(payload);
+                let offset = sound.u32().unwrap_or(0) as usize;
+                let _block_size = sound.u32();
+                sample_data = payload.get(8 + offset..);
+            }
+            b"MARK" => markers = parse_markers(payload),
+            b"INST" => instrument = Some(payload),
+            _ => (),
+        }
+    }
+
+    let common = common.ok_or("no COMM chunk")?;
+    let sample_data = sample_data.ok_or("no SSND chunk")?;
+    let (root_note, loop_points) = match instrument {
+        Some(instrument) => parse_instrument(instrument, &markers),
+        None => (None, None),
+    };
+    let unsigned_8_bit = false;
+    Ok(AudioFile {
+        channels: decode_samples(
+            sample_data,
+            common.sample_format,
+            common.channels,
+            !common.little_endian,
+            unsigned_8_bit,
+        )?,
+        sample_rate: common.sample_rate,
+        root_note,
+        loop_points,
+        frame_size: None,
+    })
+}
+
+fn parse_common(payload: &[u8], compressed: bool) -> Result<Common, String> {
+    let mut reader = Reader::new(payload);
+    let truncated = || "truncated COMM chunk".to_string();
+
+    let channels = reader.u16().ok_or_else(truncated)? as usize;
+    let _num_frames = reader.u32();
+    let bits = reader.u16().ok_or_else(truncated)? as usize;
+    let sample_rate = reader.extended().ok_or_else(truncated)?;
+    let compression = if compressed { reader.bytes(4).ok_or_else(truncated)? } else { &b"NONE"[..] };
+
+    // Sample sizes that aren't a whole number of bytes are stored padded out to the next byte.
+    let padded_bits = (bits + 7) / 8 * 8;
+    let (sample_format, little_endian) = match compression {
+        b"NONE" | b"twos" => (SampleFormat::Int(padded_bits), false),
+        b"sowt" => (SampleFormat::Int(padded_bits), true),
+        b"fl32" | b"FL32" => (SampleFormat::Float(32), false),
+        b"fl64" | b"FL64" => (SampleFormat::Float(64), false),
+        _ => return Err(format!("unsupported compression {:?}", String::from_utf8_lossy(compression))),
+    };
+
+    if channels == 0 {
+        return Err("no channels".to_string());
+    }
+    if !sample_format.is_supported() {
+        return Err(format!("unsupported sample size ({} bits)", bits));
+    }
+    Ok(Common {
+        channels,
+        sample_format,
+        sample_rate,
+        little_endian,
+    })
+}
+
+// (id, position) for each marker.
+fn parse_markers(payload: &[u8]) -> Vec<(u16, u32)> {
+    let mut reader = Reader::new(payload);
+    let count = reader.u16().unwrap_or(0);
+    let mut markers = Vec::new();
+    for _ in 0..count {
+        let (id, position, name_length) = match (reader.u16(), reader.u32(), reader.u8()) {
+            (Some(id), Some(position), Some(name_length)) => (id, position, name_length as usize),
+            _ => break,
+        };
+        // The name is a Pascal string, padded so the length byte and text come to an even size.
+        reader.bytes(name_length + (name_length + 1) % 2);
+        markers.push((id, position));
+    }
+    markers
+}
+
+// The root note, and the sustain loop if it's on. Markers sit between frames, so the loop's last
+// frame is the one before its end marker.
+fn parse_instrument(payload: &[u8], markers: &[(u16, u32)]) -> (Option<u8>, Option<(usize, usize)>) {
+    let mut reader = Reader::new(payload);
+    let root_note = reader.u8().filter(|&note| note < 128);
+    // Detune, key range, velocity range and gain.
+    reader.bytes(7);
+    let play_mode = reader.u16().unwrap_or(0);
+    let begin = reader.u16();
+    let end = reader.u16();
+
+    let position = |id: Option<u16>| {
+        let id = id?;
+        markers.iter().find(|marker| marker.0 == id).map(|marker| marker.1 as usize)
+    };
+    let loop_points = match (play_mode, position(begin), position(end)) {
+        (0, _, _) => None,
+        (_, Some(start), Some(end)) if end > start + 1 => Some((start, end - 1)),
+        _ => None,
+    };
+    (root_note, loop_points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(tag: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = tag.to_vec();
+        chunk.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        chunk.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    // Whole numbers only, which is all sample rates need.
+    fn extended(value: u32) -> [u8; 10] {
+        let exponent = 31 - value.leading_zeros();
+        let mantissa = (value as u64) << (63 - exponent);
+        let mut bytes = [0; 10];
+        bytes[..2].copy_from_slice(&(exponent as u16 + 16383).to_be_bytes());
+        bytes[2..].copy_from_slice(&mantissa.to_be_bytes());
+        bytes
+    }
+
+    fn comm(channels: u16, bits: u16, compression: Option<&[u8; 4]>) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&channels.to_be_bytes());
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        payload.extend_from_slice(&bits.to_be_bytes());
+        payload.extend_from_slice(&extended(44100));
+        if let Some(compression) = compression {
+            payload.extend_from_slice(compression);
+            payload.extend_from_slice(b"\x00\x00");
+        }
+        chunk(b"COMM", &payload)
+    }
+
+    fn ssnd(data: &[u8]) -> Vec<u8> {
+        let mut payload = vec![0; 8];
+        payload.extend_from_slice(data);
+        chunk(b"SSND", &payload)
+    }
+
+    fn aiff(kind: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut file = b"FORM".to_vec();
+        file.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        file.extend_from_slice(kind);
+        file.extend_from_slice(&body);
+        file
+    }
+
+    fn be_bytes(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|sample| sample.to_be_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn sixteen_bit_stereo() {
+        let data = be_bytes(&[0, 16384, -32768, -16384]);
+        let file = parse(&aiff(b"AIFF", &[comm(2, 16, None), ssnd(&data)])).unwrap();
+        assert_eq!(file.sample_rate, 44100.0);
+        assert_eq!(file.channels, vec![vec![0.0, -1.0], vec![0.5, -0.5]]);
+        assert_eq!(file.root_note, None);
+        assert_eq!(file.loop_points, None);
+    }
+
+    #[test]
+    fn sample_sizes_are_padded_to_whole_bytes() {
+        let data = be_bytes(&[0x4000, -0x8000]);
+        let file = parse(&aiff(b"AIFF", &[comm(1, 12, None), ssnd(&data)])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.5, -1.0]]);
+
+        let file = parse(&aiff(b"AIFF", &[comm(1, 8, None), ssnd(&[0x40, 0xC0])])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.5, -0.5]]);
+    }
+
+    #[test]
+    fn aifc_compression_types() {
+        let data: Vec<u8> = [16384i16, -16384].iter().flat_map(|sample| sample.to_le_bytes().to_vec()).collect();
+        let file = parse(&aiff(b"AIFC", &[comm(1, 16, Some(b"sowt")), ssnd(&data)])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.5, -0.5]]);
+
+        let data: Vec<u8> = [0.25f32, -0.75].iter().flat_map(|sample| sample.to_be_bytes().to_vec()).collect();
+        let file = parse(&aiff(b"AIFC", &[comm(1, 32, Some(b"fl32")), ssnd(&data)])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.25, -0.75]]);
+
+        let data: Vec<u8> = [0.25f64, -0.75].iter().flat_map(|sample| sample.to_be_bytes().to_vec()).collect();
+        let file = parse(&aiff(b"AIFC", &[comm(1, 64, Some(b"fl64")), ssnd(&data)])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.25, -0.75]]);
+    }
+
+    #[test]
+    fn instrument_and_markers() {
+        let mut mark = 2u16.to_be_bytes().to_vec();
+        for &(id, position, name) in &[(1u16, 100u32, &b"start"[..]), (2, 200, &b"end!"[..])] {
+            mark.extend_from_slice(&id.to_be_bytes());
+            mark.extend_from_slice(&position.to_be_bytes());
+            mark.push(name.len() as u8);
+            mark.extend_from_slice(name);
+            if name.len() % 2 == 0 {
+                mark.push(0);
+            }
+        }
+        let instrument = |play_mode: u16| {
+            let mut inst = vec![60, 0, 0, 127, 1, 127, 0, 0];
+            inst.extend_from_slice(&play_mode.to_be_bytes());
+            inst.extend_from_slice(&1u16.to_be_bytes());
+            inst.extend_from_slice(&2u16.to_be_bytes());
+            inst.extend_from_slice(&[0; 6]);
+            chunk(b"INST", &inst)
+        };
+        let (mark, data) = (chunk(b"MARK", &mark), ssnd(&be_bytes(&[0; 256])));
+
+        let file = parse(&aiff(b"AIFF", &[comm(1, 16, None), mark.clone(), instrument(1), data.clone()])).unwrap();
+        assert_eq!(file.root_note, Some(60));
+        assert_eq!(file.loop_points, Some((100, 199)));
+
+        // Play mode 0 means the loop is off.
+        let file = parse(&aiff(b"AIFF", &[comm(1, 16, None), mark, instrument(0), data])).unwrap();
+        assert_eq!(file.root_note, Some(60));
+        assert_eq!(file.loop_points, None);
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let data = ssnd(&be_bytes(&[0, 1]));
+        assert!(parse(b"FORM").is_err());
+        assert!(parse(&aiff(b"8SVX", &[comm(1, 16, None), data.clone()])).is_err());
+        assert!(parse(&aiff(b"AIFF", std::slice::from_ref(&data))).is_err());
+        assert!(parse(&aiff(b"AIFF", &[comm(1, 16, None)])).is_err());
+        assert!(parse(&aiff(b"AIFF", &[comm(0, 16, None), data.clone()])).is_err());
+        assert!(parse(&aiff(b"AIFF", &[comm(1, 40, None), data.clone()])).is_err());
+        assert!(parse(&aiff(b"AIFC", &[comm(1, 16, Some(b"ima4")), data.clone()])).is_err());
+        assert!(parse(&aiff(b"AIFF", &[chunk(b"COMM", &comm(1, 16, None)[8..12]), data])).is_err());
+    }
+}
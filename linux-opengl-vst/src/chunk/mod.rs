@@ -0,0 +1,165 @@
+// The plugin state chunk that the host saves with the project (and that presets are made of).
+//
+// Layout, all numbers little-endian:
+//   "GVLS" magic, u32 version
+//   then any number of sections: 4-byte tag, u32 payload length, payload
+//
+// Readers skip sections they don't know about, so adding a new section doesn't break old
+// projects, and old plugins can still open new projects (minus the new stuff).
+
+const MAGIC: &[u8; 4] = b"GVLS";
+const VERSION: u32 = 1;
+
+pub struct ChunkWriter {
+    data: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub fn new() -> Self {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        Self { data }
+    }
+
+    pub fn section(&mut self, tag: &[u8; 4], payload: &[u8]) {
+        self.data.extend_from_slice(tag);
+        self.data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(payload);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct ChunkReader<'a> {
+    reader: ByteReader<'a>,
+}
+
+impl<'a> ChunkReader<'a> {
+    // Returns None if this isn't one of our chunks.
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let mut reader = ByteReader::new(data);
+        if reader.bytes(4)? != MAGIC {
+            return None;
+        }
+        let _version = reader.u32()?;
+        Some(Self { reader })
+    }
+}
+
+impl<'a> Iterator for ChunkReader<'a> {
+    type Item = ([u8; 4], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tag = self.reader.bytes(4)?;
+        let length = self.reader.u32()? as usize;
+        let payload = self.reader.bytes(length)?;
+        Some(([tag[0], tag[1], tag[2], tag[3]], payload))
+    }
+}
+
+// Pulls little-endian values out of a byte slice, returning None once it runs out of data.
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.position + count > self.data.len() {
+            return None;
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Some(bytes)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        let bytes = self.bytes(4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn i32(&mut self) -> Option<i32> {
+        Some(self.u32()? as i32)
+    }
+
+    pub fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_bits(self.u32()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_round_trip() {
+        let mut chunk = ChunkWriter::new();
+        chunk.section(b"PARM", &[1, 2, 3]);
+        chunk.section(b"EMPT", &[]);
+        chunk.section(b"SEQ ", &[4; 300]);
+        let data = chunk.finish();
+
+        let sections: Vec<([u8; 4], &[u8])> = ChunkReader::new(&data).unwrap().collect();
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[0], (*b"PARM", &[1u8, 2, 3][..]));
+        assert_eq!(sections[1], (*b"EMPT", &[][..]));
+        assert_eq!(sections[2], (*b"SEQ ", &[4u8; 300][..]));
+    }
+
+    #[test]
+    fn other_data_isnt_a_chunk() {
+        assert!(ChunkReader::new(b"").is_none());
+        assert!(ChunkReader::new(b"GVL").is_none());
+        assert!(ChunkReader::new(b"RIFF\x01\x00\x00\x00").is_none());
+        assert!(ChunkReader::new(b"GVLS\x01\x00").is_none());
+    }
+
+    #[test]
+    fn truncated_section_ends_the_chunk() {
+        let mut chunk = ChunkWriter::new();
+        chunk.section(b"PARM", &[1, 2, 3]);
+        chunk.section(b"MLRN", &[5; 10]);
+        let data = chunk.finish();
+
+        let tags: Vec<[u8; 4]> = ChunkReader::new(&data[..data.len() - 1]).unwrap().map(|(tag, _)| tag).collect();
+        assert_eq!(tags, vec![*b"PARM"]);
+    }
+
+    #[test]
+    fn byte_reader_reads_little_endian() {
+        let data = [0x01, 0x34, 0x12, 0x78, 0x56, 0x34, 0x12, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x80, 0x3F];
+        let mut reader = ByteReader::new(&data);
+        assert_eq!(reader.u8(), Some(0x01));
+        assert_eq!(reader.u16(), Some(0x1234));
+        assert_eq!(reader.u32(), Some(0x1234_5678));
+        assert_eq!(reader.i32(), Some(-1));
+        assert_eq!(reader.f32(), Some(1.0));
+        assert_eq!(reader.u8(), None);
+    }
+
+    #[test]
+    fn byte_reader_doesnt_read_past_the_end() {
+        let mut reader = ByteReader::new(&[1, 2, 3]);
+        assert_eq!(reader.u32(), None);
+        // A failed read doesn't use anything up.
+        assert_eq!(reader.bytes(3), Some(&[1u8, 2, 3][..]));
+        assert_eq!(reader.bytes(0), Some(&[][..]));
+        assert_eq!(reader.bytes(1), None);
+    }
+}
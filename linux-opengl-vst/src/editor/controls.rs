@@ -0,0 +1,188 @@
+// A bare-bones slider for every parameter, drawn as plain rectangles with scissored clears (so
+// there's no shader setup needed). There's no text rendering yet: sliders go down the columns in
+// parameter index order, left to right. The step sequencer's grid goes underneath, then the
+// modulation matrix.
+//
+// Everything in here is platform-independent. The windows just forward their mouse events and
+// call `draw()` with their GL context current.
+
+use crate::parameters::{Parameters, NUM_PARAMETERS};
+use crate::ui_state::UiState;
+use super::matrix_grid::{self, MatrixControl};
+use super::step_grid;
+
+const MARGIN: i32 = 20;
+const SLIDER_WIDTH: i32 = 180;
+const SLIDER_HEIGHT: i32 = 14;
+const SLIDER_SPACING: i32 = 4;
+const COLUMN_SPACING: i32 = 18;
+const ROWS_PER_COLUMN: i32 = 24;
+
+pub const TRACK_COLOR: [f32; 3] = [0.25, 0.25, 0.5];
+pub const VALUE_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+pub const LEARNING_COLOR: [f32; 3] = [1.0, 0.6, 0.0];
+const LEARNED_COLOR: [f32; 3] = [0.0, 0.8, 0.0];
+const CLIP_COLOR: [f32; 3] = [1.0, 0.0, 0.0];
+const NO_CLIP_COLOR: [f32; 3] = [0.1, 0.1, 0.1];
+
+#[derive(Clone, Copy)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+// What the right-click menu can do to a slider.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContextAction {
+    // Starts MIDI learn, or cancels it if this slider is already learning.
+    Learn,
+    Forget,
+    SetMin,
+    SetMax,
+}
+
+pub fn slider_rect(index: i32) -> Rect {
+    let column = index / ROWS_PER_COLUMN;
+    let row = index % ROWS_PER_COLUMN;
+    Rect {
+        x: MARGIN + column * (SLIDER_WIDTH + COLUMN_SPACING),
+        y: MARGIN + row * (SLIDER_HEIGHT + SLIDER_SPACING),
+        width: SLIDER_WIDTH,
+        height: SLIDER_HEIGHT,
+    }
+}
+
+pub fn slider_at(x: i32, y: i32) -> Option<i32> {
+    (0..NUM_PARAMETERS).find(|&index| slider_rect(index).contains(x, y))
+}
+
+fn slider_value(index: i32, x: i32) -> f32 {
+    let rect = slider_rect(index);
+    ((x - rect.x) as f32 / rect.width as f32).max(0.0).min(1.0)
+}
+
+#[derive(Clone, Copy)]
+enum Drag {
+    Slider(i32),
+    // A lane of the step grid
+    Lane(usize),
+    // One of a modulation matrix slot's controls
+    Matrix(usize, MatrixControl),
+}
+
+pub struct Controls {
+    dragging: Option<Drag>,
+}
+
+impl Controls {
+    pub fn new() -> Self {
+        Self { dragging: None }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.dragging.is_some()
+    }
+
+    // These return the parameter change (if any) so the window can pass it on to the host.
+    // Step grid and matrix edits aren't parameters, so they don't return anything.
+    pub fn mouse_down(&mut self, x: i32, y: i32, params: &Parameters, ui_state: &UiState) -> Option<(i32, f32)> {
+        self.dragging = slider_at(x, y)
+            .map(Drag::Slider)
+            .or_else(|| step_grid::lane_at(x, y).map(Drag::Lane))
+            .or_else(|| matrix_grid::control_at(x, y).map(|(slot, control)| Drag::Matrix(slot, control)));
+        self.mouse_drag(x, y, params, ui_state)
+    }
+
+    pub fn mouse_drag(&mut self, x: i32, y: i32, params: &Parameters, ui_state: &UiState) -> Option<(i32, f32)> {
+        match self.dragging? {
+            Drag::Slider(index) => {
+                let value = slider_value(index, x);
+                params.set_parameter(index, value);
+                Some((index, value))
+            }
+            Drag::Lane(lane) => {
+                step_grid::set_step(lane, x, y, &ui_state.sequence);
+                None
+            }
+            Drag::Matrix(slot, control) => {
+                matrix_grid::set(slot, control, x, &ui_state.mod_matrix);
+                None
+            }
+        }
+    }
+
+    pub fn mouse_up(&mut self) {
+        self.dragging = None;
+    }
+}
+
+pub fn apply_context_action(index: i32, action: ContextAction, params: &Parameters, ui_state: &UiState) {
+    let midi_learn = &ui_state.midi_learn;
+    match action {
+        ContextAction::Learn => {
+            if midi_learn.learning() == Some(index) {
+                midi_learn.cancel_learning();
+            } else {
+                midi_learn.start_learning(index);
+            }
+        }
+        ContextAction::Forget => midi_learn.forget(index),
+        ContextAction::SetMin => midi_learn.set_min(index, params.get_parameter(index)),
+        ContextAction::SetMax => midi_learn.set_max(index, params.get_parameter(index)),
+    }
+}
+
+pub unsafe fn fill_rect(rect: Rect, color: [f32; 3], window_height: i32) {
+    // GL counts y from the bottom of the window.
+    gl::Scissor(rect.x, window_height - rect.y - rect.height, rect.width, rect.height);
+    gl::ClearColor(color[0], color[1], color[2], 1.0);
+    gl::Clear(gl::COLOR_BUFFER_BIT);
+}
+
+// Expects the window's GL context to be current.
+pub fn draw(params: &Parameters, ui_state: &UiState, window_height: i32) {
+    let learning = ui_state.midi_learn.learning();
+
+    unsafe {
+        gl::Enable(gl::SCISSOR_TEST);
+
+        for index in 0..NUM_PARAMETERS {
+            let track = slider_rect(index);
+            let track_color = if learning == Some(index) { LEARNING_COLOR } else { TRACK_COLOR };
+            fill_rect(track, track_color, window_height);
+
+            let value = Rect {
+                width: (track.width as f32 * params.get_parameter(index)) as i32,
+                ..track
+            };
+            fill_rect(value, VALUE_COLOR, window_height);
+
+            // A little marker just past the end of the slider if it's bound to a MIDI CC.
+            if ui_state.midi_learn.controller_for(index).is_some() {
+                let marker = Rect {
+                    x: track.x + track.width + 3,
+                    width: 4,
+                    ..track
+                };
+                fill_rect(marker, LEARNED_COLOR, window_height);
+            }
+        }
+
+        // The clip light goes in the slot after the last slider.
+        let clip_color = if ui_state.is_clipping() { CLIP_COLOR } else { NO_CLIP_COLOR };
+        fill_rect(slider_rect(NUM_PARAMETERS), clip_color, window_height);
+
+        step_grid::draw(params, ui_state, window_height);
+        matrix_grid::draw(ui_state, window_height);
+
+        gl::Disable(gl::SCISSOR_TEST);
+    }
+}
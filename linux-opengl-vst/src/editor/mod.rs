@@ -6,7 +6,12 @@ use vst::editor::Editor as VstEditor;
 use vst::plugin::HostCallback;
 
 use crate::parameters::Parameters;
+use crate::ui_state::UiState;
 
+mod controls;
+mod matrix_grid;
+mod files;
+mod step_grid;
 mod window;
 
 const DEFAULT_WIDTH: i32 = 1024;
@@ -14,14 +19,16 @@ const DEFAULT_HEIGHT: i32 = 1024;
 
 pub struct Editor {
     params: Arc<Parameters>,
+    ui_state: Arc<UiState>,
     window: Option<window::Window>,
     host_callback: Arc<Mutex<HostCallback>>,
 }
 
 impl Editor {
-    pub fn new(host_callback: Arc<Mutex<HostCallback>>, params: Arc<Parameters>) -> Self {
+    pub fn new(host_callback: Arc<Mutex<HostCallback>>, params: Arc<Parameters>, ui_state: Arc<UiState>) -> Self {
         Self {
             params,
+            ui_state,
             window: None,
             host_callback,
         }
@@ -55,7 +62,12 @@ impl VstEditor for Editor {
     fn open(&mut self, parent: *mut c_void) -> bool {
         info!("Editor::open()");
 
-        self.window = Some(window::Window::new(self.host_callback.clone(), self.params.clone(), parent));
+        self.window = Some(window::Window::new(
+            self.host_callback.clone(),
+            self.params.clone(),
+            self.ui_state.clone(),
+            parent,
+        ));
 
         // success
         true
@@ -1,8 +1,12 @@
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::os::raw::{c_int, c_void};
 use std::ffi::{CStr, CString};
+use std::path::PathBuf;
+use std::process::Command;
 use std::ptr::null_mut;
 use std::thread;
+use std::time::Duration;
 
 use vst::plugin::HostCallback;
 use vst::host::Host;
@@ -13,6 +17,10 @@ use log::*;
 use rand::Rng;
 
 use crate::parameters::Parameters;
+use crate::sysex::patch_dump;
+use crate::ui_state::UiState;
+use super::controls::{self, ContextAction, Controls};
+use super::files::{self, FileKind};
 
 type GlXCreateContextAttribsARBProc =
 unsafe extern "C" fn (dpy: *mut xlib::Display, fbc: GLXFBConfig,
@@ -30,13 +38,43 @@ unsafe extern "C" fn ctx_error_handler(
 const GLX_CONTEXT_MAJOR_VERSION_ARB: u32 = 0x2091;
 const GLX_CONTEXT_MINOR_VERSION_ARB: u32 = 0x2092;
 
+// X keycode for the Escape key, which is our "panic" button.
+const ESCAPE_KEYCODE: u8 = 9;
+
+// X keycodes for S, K and R: load a Scala scale, load a keyboard mapping, reset the tuning.
+const S_KEYCODE: u8 = 39;
+const K_KEYCODE: u8 = 45;
+const R_KEYCODE: u8 = 27;
+
+// X keycode for D: send the patch out as a SysEx dump (with the wavetable and samples too if shift
+// is held).
+const D_KEYCODE: u8 = 40;
+
+// X keycodes for W and L: load a wavetable, load samples.
+const W_KEYCODE: u8 = 25;
+const L_KEYCODE: u8 = 46;
+
+const WINDOW_HEIGHT: i32 = 1024;
+
+// Parameters can change without us getting any X events (host automation, MIDI learn), so we
+// redraw on a timer instead of only on expose.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(30);
+
 pub struct Window {
-    t: thread::JoinHandle<()>,
+    t: Option<thread::JoinHandle<()>>,
+    running: Arc<AtomicBool>,
 }
 
 impl Window {
-    pub fn new(host_callback: Arc<Mutex<HostCallback>>, params: Arc<Parameters>, parent: *mut std::ffi::c_void) -> Self {
+    pub fn new(
+        host_callback: Arc<Mutex<HostCallback>>,
+        params: Arc<Parameters>,
+        ui_state: Arc<UiState>,
+        parent: *mut std::ffi::c_void,
+    ) -> Self {
         let parent_id = parent as u32;
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
         let t = thread::spawn(move || {
             let (conn, screen_num) = xcb::Connection::connect_with_xlib_display().unwrap();
             //conn.set_event_queue_owner(xcb::EventQueueOwner::Xcb); // TODO: need this?
@@ -104,7 +142,9 @@ impl Window {
                 (xcb::CW_BACK_PIXEL, screen.white_pixel()),
                 (xcb::CW_BORDER_PIXEL, screen.black_pixel()),
                 (xcb::CW_EVENT_MASK,
-                 xcb::EVENT_MASK_KEY_PRESS | xcb::EVENT_MASK_EXPOSURE),
+                 xcb::EVENT_MASK_KEY_PRESS | xcb::EVENT_MASK_EXPOSURE |
+                 xcb::EVENT_MASK_BUTTON_PRESS | xcb::EVENT_MASK_BUTTON_RELEASE |
+                 xcb::EVENT_MASK_BUTTON_1_MOTION),
                 (xcb::CW_COLORMAP, cmap)
             ];
 
@@ -197,8 +237,10 @@ impl Window {
             }
 
             handle_events(
+                thread_running,
                 host_callback,
                 params,
+                ui_state,
                 Arc::new(conn),
                 win,
                 ctx,
@@ -209,7 +251,8 @@ impl Window {
         });
 
         Self {
-            t,
+            t: Some(t),
+            running,
         }
     }
 
@@ -222,6 +265,16 @@ impl Window {
     }
 }
 
+impl Drop for Window {
+    fn drop(&mut self) {
+        // Stop drawing before the host gets rid of our parent window.
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(t) = self.t.take() {
+            let _ = t.join();
+        }
+    }
+}
+
 
 // returns the glx version in a decimal form
 // eg. 1.3  => 13
@@ -283,9 +336,52 @@ unsafe fn check_gl_error() {
     }
 }
 
+// There's no file dialog in X itself, so borrow zenity's. It gets its own thread so the editor
+// keeps drawing while the dialog is up.
+fn open_file(file: FileKind, ui_state: Arc<UiState>) {
+    thread::spawn(move || {
+        let patterns: Vec<String> = file.extensions().iter().map(|extension| format!("*.{}", extension)).collect();
+        let output = Command::new("zenity")
+            .arg("--file-selection")
+            .arg(format!("--title=Load {}", file.description()))
+            .arg(format!("--file-filter={} ({}) | {}", file.description(), patterns.join(" "), patterns.join(" ")))
+            .output();
+
+        match output {
+            // A non-zero exit status just means the dialog was cancelled.
+            Ok(output) => if output.status.success() {
+                let path = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
+                files::load(file, &PathBuf::from(path), &ui_state);
+            },
+            Err(error) => warn!("Couldn't run zenity to pick a file: {}", error),
+        }
+    });
+}
+
+fn draw_window(
+    conn: &xcb::Connection,
+    win: u32,
+    ctx: *mut x11::glx::__GLXcontextRec,
+    params: &Parameters,
+    ui_state: &UiState,
+) {
+    unsafe {
+        glXMakeCurrent(conn.get_raw_dpy(), win as xlib::XID, ctx);
+        gl::ClearColor(0.5f32, 0.5f32, 1.0f32, 1.0f32);
+        gl::Clear(gl::COLOR_BUFFER_BIT);
+        controls::draw(params, ui_state, WINDOW_HEIGHT);
+        gl::Flush();
+        check_gl_error();
+        glXSwapBuffers(conn.get_raw_dpy(), win as xlib::XID);
+        glXMakeCurrent(conn.get_raw_dpy(), 0, null_mut());
+    }
+}
+
 fn handle_events(
+    running: Arc<AtomicBool>,
     host_callback: Arc<Mutex<HostCallback>>,
     params: Arc<Parameters>,
+    ui_state: Arc<UiState>,
     conn: Arc<xcb::Connection>,
     win: u32,
     ctx: *mut x11::glx::__GLXcontextRec,
@@ -294,28 +390,96 @@ fn handle_events(
     dri2_ev: u8,
 ) {
     let mut rng = rand::thread_rng();
-    loop {
-        info!("Event loop begin");
-        if let Some(ev) = conn.wait_for_event() {
+    let mut controls = Controls::new();
+    while running.load(Ordering::Relaxed) {
+        if conn.has_error().is_err() {
+            break;
+        }
+
+        while let Some(ev) = conn.poll_for_event() {
             let ev_type = ev.response_type() & !0x80;
             match ev_type {
                 xcb::EXPOSE => {
-                    unsafe {
-                        glXMakeCurrent(conn.get_raw_dpy(), win as xlib::XID, ctx);
-                        gl::ClearColor(0.5f32, 0.5f32, 1.0f32, 1.0f32);
-                        gl::Clear(gl::COLOR_BUFFER_BIT);
-                        gl::Flush();
-                        check_gl_error();
-                        glXSwapBuffers(conn.get_raw_dpy(), win as xlib::XID);
-                        glXMakeCurrent(conn.get_raw_dpy(), 0, null_mut());
-                    }
+                    draw_window(&conn, win, ctx, &params, &ui_state);
                 },
                 xcb::KEY_PRESS => {
-                    let new_param_val = rng.gen_range(0.0, 1.0);
-                    params.pulse_width.set(new_param_val);
-                    host_callback.lock().unwrap().automate(1, new_param_val);
+                    let key_press = unsafe {
+                        xcb::cast_event::<xcb::KeyPressEvent>(&ev)
+                    };
+                    if key_press.detail() == ESCAPE_KEYCODE {
+                        info!("Panic!");
+                        ui_state.request_panic();
+                    }
+                    else if key_press.detail() == S_KEYCODE {
+                        open_file(FileKind::Scale, ui_state.clone());
+                    }
+                    else if key_press.detail() == K_KEYCODE {
+                        open_file(FileKind::KeyboardMapping, ui_state.clone());
+                    }
+                    else if key_press.detail() == R_KEYCODE {
+                        files::reset_tuning(&ui_state);
+                    }
+                    else if key_press.detail() == W_KEYCODE {
+                        open_file(FileKind::Wavetable, ui_state.clone());
+                    }
+                    else if key_press.detail() == L_KEYCODE {
+                        open_file(FileKind::Samples, ui_state.clone());
+                    }
+                    else if key_press.detail() == D_KEYCODE {
+                        let with_wavetable_and_samples = key_press.state() & xcb::MOD_MASK_SHIFT as u16 != 0;
+                        ui_state.request_sysex_dump(patch_dump(&params, &ui_state, with_wavetable_and_samples));
+                    }
+                    else {
+                        let new_param_val = rng.gen_range(0.0, 1.0);
+                        params.pulse_width.set(new_param_val);
+                        host_callback.lock().unwrap().automate(1, new_param_val);
+                    }
                     // break;
                 },
+                xcb::BUTTON_PRESS => {
+                    let press = unsafe {
+                        xcb::cast_event::<xcb::ButtonPressEvent>(&ev)
+                    };
+                    let (x, y) = (press.event_x() as i32, press.event_y() as i32);
+                    match press.detail() {
+                        1 => {
+                            if let Some((index, value)) = controls.mouse_down(x, y, &params, &ui_state) {
+                                host_callback.lock().unwrap().automate(index, value);
+                            }
+                        },
+                        // There's no text rendering for a proper menu yet, so the "menu" is
+                        // spread over the other buttons: right click learns (or cancels
+                        // learning), shift/ctrl + right click set the learned range to the
+                        // current value, and middle click forgets the CC.
+                        2 | 3 => {
+                            let action = if press.detail() == 2 {
+                                ContextAction::Forget
+                            } else if press.state() & xcb::MOD_MASK_SHIFT as u16 != 0 {
+                                ContextAction::SetMin
+                            } else if press.state() & xcb::MOD_MASK_CONTROL as u16 != 0 {
+                                ContextAction::SetMax
+                            } else {
+                                ContextAction::Learn
+                            };
+                            if let Some(index) = controls::slider_at(x, y) {
+                                controls::apply_context_action(index, action, &params, &ui_state);
+                            }
+                        },
+                        _ => (),
+                    }
+                },
+                xcb::MOTION_NOTIFY => {
+                    let motion = unsafe {
+                        xcb::cast_event::<xcb::MotionNotifyEvent>(&ev)
+                    };
+                    let (x, y) = (motion.event_x() as i32, motion.event_y() as i32);
+                    if let Some((index, value)) = controls.mouse_drag(x, y, &params, &ui_state) {
+                        host_callback.lock().unwrap().automate(index, value);
+                    }
+                },
+                xcb::BUTTON_RELEASE => {
+                    controls.mouse_up();
+                },
                 xcb::CLIENT_MESSAGE => {
                     let cmev = unsafe {
                         xcb::cast_event::<xcb::ClientMessageEvent>(&ev)
@@ -323,7 +487,7 @@ fn handle_events(
                     if cmev.type_() == wm_protocols && cmev.format() == 32 {
                         let protocol = cmev.data().data32()[0];
                         if protocol == wm_delete_window {
-                            break;
+                            return;
                         }
                     }
                 },
@@ -355,12 +519,10 @@ fn handle_events(
                     }
                 }
             }
-            conn.flush();
-        }
-        else {
-            break;
         }
 
-        info!("Event loop end");
+        draw_window(&conn, win, ctx, &params, &ui_state);
+        conn.flush();
+        thread::sleep(REDRAW_INTERVAL);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,79 @@
+use std::f64::consts::PI;
+
+// A stereo chorus: one short modulated delay per side, with the two sides' LFOs a quarter cycle
+// apart so the sound spreads out.
+
+const BASE_DELAY_SECONDS: f64 = 0.007;
+const MAX_DEPTH_SECONDS: f64 = 0.005;
+const MIN_RATE: f64 = 0.05; // Hz
+const MAX_RATE: f64 = 5.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChorusSettings {
+    pub rate: f64, // Hz
+    pub depth: f64, // 0.0 - 1.0
+}
+
+impl ChorusSettings {
+    pub fn new(rate: f32, depth: f32) -> Self {
+        Self {
+            rate: chorus_rate_hz(rate),
+            depth: depth as f64,
+        }
+    }
+
+    pub fn tail_seconds(&self) -> f64 {
+        BASE_DELAY_SECONDS + MAX_DEPTH_SECONDS
+    }
+}
+
+pub struct Chorus {
+    lines: [Vec<f64>; 2],
+    write: usize,
+    phase: f64, // 0.0 - 1.0
+}
+
+impl Chorus {
+    pub fn new(sample_rate: f32) -> Self {
+        let length = ((BASE_DELAY_SECONDS + MAX_DEPTH_SECONDS) * sample_rate as f64) as usize + 2;
+        Self {
+            lines: [vec![0.0; length], vec![0.0; length]],
+            write: 0,
+            phase: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for line in &mut self.lines {
+            for sample in line.iter_mut() {
+                *sample = 0.0;
+            }
+        }
+    }
+
+    // Returns the delayed signal alone.
+    pub fn process(&mut self, input: (f64, f64), settings: &ChorusSettings, sample_rate: f32) -> (f64, f64) {
+        let length = self.lines[0].len();
+        self.lines[0][self.write] = input.0;
+        self.lines[1][self.write] = input.1;
+
+        let mut output = [0.0; 2];
+        for (side, line) in self.lines.iter().enumerate() {
+            let lfo = (2.0 * PI * (self.phase + side as f64 * 0.25)).sin() * 0.5 + 0.5;
+            let delay = (BASE_DELAY_SECONDS + lfo * settings.depth * MAX_DEPTH_SECONDS) * sample_rate as f64;
+            let position = self.write as f64 + length as f64 - delay.min((length - 2) as f64);
+            let index = position as usize;
+            let a = line[index % length];
+            let b = line[(index + 1) % length];
+            output[side] = a + (b - a) * position.fract();
+        }
+
+        self.write = (self.write + 1) % length;
+        self.phase = (self.phase + settings.rate / sample_rate as f64).fract();
+        (output[0], output[1])
+    }
+}
+
+pub fn chorus_rate_hz(value: f32) -> f64 {
+    MIN_RATE * (MAX_RATE / MIN_RATE).powf(value as f64)
+}
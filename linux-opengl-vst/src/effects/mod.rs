@@ -0,0 +1,248 @@
+mod chorus;
+mod delay;
+mod reverb;
+pub use self::chorus::chorus_rate_hz;
+pub use self::delay::delay_time_text;
+use self::chorus::{Chorus, ChorusSettings};
+use self::delay::{Delay, DelaySettings};
+use self::reverb::{Reverb, ReverbSettings};
+use crate::parameters::{choice_index, Parameters};
+
+// The effects that run on the mixed voices: chorus, then delay, then reverb. Each one has a mix
+// between the dry and wet signal, and can be bypassed, which fades it out rather than cutting it
+// off so it doesn't click.
+
+const BYPASS_FADE_SECONDS: f64 = 0.01;
+
+struct BypassFade {
+    // How much of the effect is heard, 0.0 - 1.0.
+    amount: f64,
+    // Whether the effect has run since it was last cleared, so it has something left in its
+    // buffers.
+    dirty: bool,
+}
+
+impl BypassFade {
+    fn new() -> Self {
+        Self {
+            amount: 0.0,
+            dirty: false,
+        }
+    }
+
+    fn next(&mut self, bypassed: bool, sample_rate: f32) -> f64 {
+        let step = 1.0 / (BYPASS_FADE_SECONDS * sample_rate as f64);
+        self.amount = if bypassed {
+            (self.amount - step).max(0.0)
+        } else {
+            (self.amount + step).min(1.0)
+        };
+        if self.amount > 0.0 {
+            self.dirty = true;
+        }
+        self.amount
+    }
+
+    // True once, when the effect has faded out completely, so its buffers can be cleared and it
+    // starts from silence next time it's switched on.
+    fn finished(&mut self) -> bool {
+        if self.dirty && self.amount <= 0.0 {
+            self.dirty = false;
+            return true;
+        }
+        false
+    }
+}
+
+struct EffectsSettings {
+    chorus_bypassed: bool,
+    chorus: ChorusSettings,
+    chorus_mix: f64,
+    delay_bypassed: bool,
+    delay: DelaySettings,
+    delay_mix: f64,
+    reverb_bypassed: bool,
+    reverb: ReverbSettings,
+    reverb_mix: f64,
+}
+
+impl EffectsSettings {
+    fn from_parameters(params: &Parameters, tempo: f64) -> Self {
+        Self {
+            chorus_bypassed: choice_index(params.chorus_bypass.get(), 2) == 1,
+            chorus: ChorusSettings::new(params.chorus_rate.get(), params.chorus_depth.get()),
+            chorus_mix: params.chorus_mix.get() as f64,
+            delay_bypassed: choice_index(params.delay_bypass.get(), 2) == 1,
+            delay: DelaySettings::new(
+                params.delay_time.get(),
+                params.delay_sync.get(),
+                params.delay_feedback.get(),
+                params.delay_ping_pong.get(),
+                tempo,
+            ),
+            delay_mix: params.delay_mix.get() as f64,
+            reverb_bypassed: choice_index(params.reverb_bypass.get(), 2) == 1,
+            reverb: ReverbSettings::new(params.reverb_size.get(), params.reverb_damping.get()),
+            reverb_mix: params.reverb_mix.get() as f64,
+        }
+    }
+}
+
+pub struct Effects {
+    chorus: Chorus,
+    chorus_fade: BypassFade,
+    delay: Delay,
+    delay_fade: BypassFade,
+    reverb: Reverb,
+    reverb_fade: BypassFade,
+    // The host's tempo as of the last block, for working out the synced delay's tail.
+    tempo: f64,
+    sample_rate: f32,
+}
+
+impl Effects {
+    pub fn new() -> Self {
+        let sample_rate = 44100.0;
+        Self {
+            chorus: Chorus::new(sample_rate),
+            chorus_fade: BypassFade::new(),
+            delay: Delay::new(sample_rate),
+            delay_fade: BypassFade::new(),
+            reverb: Reverb::new(sample_rate),
+            reverb_fade: BypassFade::new(),
+            tempo: 120.0,
+            sample_rate,
+        }
+    }
+
+    // The buffers' lengths depend on the sample rate, so this allocates.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate == self.sample_rate {
+            return;
+        }
+        self.sample_rate = sample_rate;
+        self.chorus = Chorus::new(sample_rate);
+        self.delay = Delay::new(sample_rate);
+        self.reverb = Reverb::new(sample_rate);
+    }
+
+    pub fn process(&mut self, left: &mut [f64], right: &mut [f64], params: &Parameters, tempo: f64) {
+        self.tempo = tempo;
+        let settings = EffectsSettings::from_parameters(params, tempo);
+        let sample_rate = self.sample_rate;
+
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            let mut sample = (*left, *right);
+
+            let amount = self.chorus_fade.next(settings.chorus_bypassed, sample_rate);
+            if amount > 0.0 {
+                let wet = self.chorus.process(sample, &settings.chorus, sample_rate);
+                sample = mix(sample, wet, settings.chorus_mix * amount);
+            }
+
+            let amount = self.delay_fade.next(settings.delay_bypassed, sample_rate);
+            if amount > 0.0 {
+                let wet = self.delay.process(sample, &settings.delay, sample_rate);
+                sample = mix(sample, wet, settings.delay_mix * amount);
+            }
+
+            let amount = self.reverb_fade.next(settings.reverb_bypassed, sample_rate);
+            if amount > 0.0 {
+                let wet = self.reverb.process(sample, &settings.reverb);
+                sample = mix(sample, wet, settings.reverb_mix * amount);
+            }
+
+            *left = sample.0;
+            *right = sample.1;
+        }
+
+        if self.chorus_fade.finished() {
+            self.chorus.reset();
+        }
+        if self.delay_fade.finished() {
+            self.delay.reset();
+        }
+        if self.reverb_fade.finished() {
+            self.reverb.reset();
+        }
+    }
+
+    // How long the effects that are switched on keep ringing after the input stops. They're in
+    // series, so their tails add up.
+    pub fn tail_seconds(&self, params: &Parameters) -> f64 {
+        let settings = EffectsSettings::from_parameters(params, self.tempo);
+        let mut tail = 0.0;
+        if !settings.chorus_bypassed {
+            tail += settings.chorus.tail_seconds();
+        }
+        if !settings.delay_bypassed {
+            tail += settings.delay.tail_seconds();
+        }
+        if !settings.reverb_bypassed {
+            tail += settings.reverb.tail_seconds();
+        }
+        tail
+    }
+}
+
+fn mix(dry: (f64, f64), wet: (f64, f64), mix: f64) -> (f64, f64) {
+    (dry.0 + (wet.0 - dry.0) * mix, dry.1 + (wet.1 - dry.1) * mix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const FADE_SAMPLES: usize = (BYPASS_FADE_SECONDS * SAMPLE_RATE as f64) as usize;
+
+    #[test]
+    fn bypass_fade_finishes_once_it_reaches_silence() {
+        let mut fade = BypassFade::new();
+        assert!(!fade.finished());
+        for _ in 0..FADE_SAMPLES {
+            fade.next(false, SAMPLE_RATE);
+        }
+        assert!((fade.amount - 1.0).abs() < 1e-9);
+
+        let mut steps = 0;
+        while fade.next(true, SAMPLE_RATE) > 0.0 {
+            assert!(!fade.finished());
+            steps += 1;
+        }
+        assert!(steps <= FADE_SAMPLES);
+        assert!(fade.finished());
+        assert!(!fade.finished());
+    }
+
+    #[test]
+    fn bypassed_delay_fades_to_silence_and_starts_clean() {
+        let params = Parameters::new();
+        params.delay_bypass.set(0.0);
+        params.delay_time.set(0.1);
+        params.delay_feedback.set(0.9);
+        params.delay_mix.set(1.0);
+        let mut effects = Effects::new();
+        effects.set_sample_rate(SAMPLE_RATE);
+
+        // Get some echoes going, then bypass it.
+        let mut left = vec![0.0; 4800];
+        let mut right = vec![0.0; 4800];
+        left[FADE_SAMPLES] = 1.0;
+        effects.process(&mut left, &mut right, &params, 120.0);
+        assert!(left[FADE_SAMPLES + 1..].iter().any(|&sample| sample != 0.0));
+
+        params.delay_bypass.set(1.0);
+        let mut left = vec![0.0; 4800];
+        let mut right = vec![0.0; 4800];
+        effects.process(&mut left, &mut right, &params, 120.0);
+        assert!(left[FADE_SAMPLES + 1..].iter().all(|&sample| sample == 0.0));
+
+        // Nothing left in the buffers to come back when it's switched on again.
+        params.delay_bypass.set(0.0);
+        let mut left = vec![0.0; 48000];
+        let mut right = vec![0.0; 48000];
+        effects.process(&mut left, &mut right, &params, 120.0);
+        assert!(left.iter().chain(right.iter()).all(|&sample| sample == 0.0));
+    }
+}
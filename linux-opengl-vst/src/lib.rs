@@ -1,14 +1,32 @@
 extern crate log;
+extern crate num_traits;
 extern crate simplelog;
 extern crate vst;
 extern crate rand;
 
 use vst::plugin_main;
 
-mod audio_engine;
+// The public modules are what the benchmarks drive the engine through.
+mod arpeggiator;
+pub mod audio_engine;
+mod audio_file;
+mod chunk;
+mod denormals;
 mod editor;
+mod effects;
 mod gvl_plugin;
-mod midi_input_processor;
-mod parameters;
+mod master;
+pub mod midi_input_processor;
+mod midi_learn;
+mod midi_output;
+pub mod mod_matrix;
+pub mod parameters;
+mod sample_map;
+mod sequencer;
+mod sysex;
+pub mod transport;
+mod tuning;
+pub mod ui_state;
+mod wavetable;
 
 plugin_main!(gvl_plugin::GvlPlugin);
@@ -0,0 +1,96 @@
+mod limiter;
+pub use self::limiter::LOOKAHEAD_SAMPLES;
+use self::limiter::Limiter;
+use crate::parameters::{choice_index, Parameters};
+use std::f64::consts::PI;
+
+// The last thing before the output: master volume, then a DC blocker, a soft clipper, and an
+// optional brickwall limiter. The soft clipper leaves everything under its threshold alone, so
+// it only colours the sound when things are already too loud.
+
+const MIN_VOLUME_DB: f64 = -60.0;
+const MAX_VOLUME_DB: f64 = 6.0;
+const DC_CUTOFF_HZ: f64 = 10.0;
+const SOFT_CLIP_THRESHOLD: f64 = 0.8;
+
+struct DcBlocker {
+    last_input: f64,
+    last_output: f64,
+}
+
+impl DcBlocker {
+    fn new() -> Self {
+        Self {
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f64, coefficient: f64) -> f64 {
+        self.last_output = input - self.last_input + coefficient * self.last_output;
+        self.last_input = input;
+        self.last_output
+    }
+}
+
+pub struct MasterStage {
+    // Left, then right.
+    dc_blockers: [DcBlocker; 2],
+    limiter: Limiter,
+}
+
+impl MasterStage {
+    pub fn new() -> Self {
+        Self {
+            dc_blockers: [DcBlocker::new(), DcBlocker::new()],
+            limiter: Limiter::new(),
+        }
+    }
+
+    // Returns whether anything went over full scale on its way in, which is what the editor's
+    // clip light shows, whether or not the clipper and limiter caught it.
+    pub fn process(&mut self, left: &mut [f64], right: &mut [f64], params: &Parameters, sample_rate: f32) -> bool {
+        let volume = master_volume_db(params.master_volume.get()).map_or(0.0, |db| 10f64.powf(db / 20.0));
+        let soft_clip = choice_index(params.soft_clip.get(), 2) == 1;
+        let limiter = choice_index(params.limiter.get(), 2) == 1;
+        let dc_coefficient = 1.0 - 2.0 * PI * DC_CUTOFF_HZ / sample_rate as f64;
+
+        let mut clipped = false;
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            let mut sample = [*left * volume, *right * volume];
+            for (side, value) in sample.iter_mut().enumerate() {
+                *value = self.dc_blockers[side].process(*value, dc_coefficient);
+                if value.abs() > 1.0 {
+                    clipped = true;
+                }
+                if soft_clip {
+                    *value = soft_clip_sample(*value);
+                }
+            }
+            let (limited_left, limited_right) = self.limiter.process((sample[0], sample[1]), limiter, sample_rate);
+            *left = limited_left;
+            *right = limited_right;
+        }
+        clipped
+    }
+}
+
+// Straight through up to the threshold, then curves smoothly towards full scale without ever
+// reaching it.
+fn soft_clip_sample(value: f64) -> f64 {
+    let magnitude = value.abs();
+    if magnitude <= SOFT_CLIP_THRESHOLD {
+        return value;
+    }
+    let headroom = 1.0 - SOFT_CLIP_THRESHOLD;
+    let clipped = SOFT_CLIP_THRESHOLD + headroom * ((magnitude - SOFT_CLIP_THRESHOLD) / headroom).tanh();
+    clipped.copysign(value)
+}
+
+// None at the bottom of the range, which is silence.
+pub fn master_volume_db(value: f32) -> Option<f64> {
+    if value <= 0.0 {
+        return None;
+    }
+    Some(MIN_VOLUME_DB + value as f64 * (MAX_VOLUME_DB - MIN_VOLUME_DB))
+}
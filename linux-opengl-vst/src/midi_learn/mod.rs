@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use crate::chunk::{ByteReader, ChunkWriter};
+use crate::parameters::AtomicFloat;
+
+const NOT_LEARNING: i32 = -1;
+const UNBOUND: i32 = -1;
+
+// 120 and up are channel mode messages, which always keep their normal meaning.
+const LEARNABLE_CONTROLLERS: usize = 120;
+
+struct Binding {
+    parameter: AtomicI32,
+    min: AtomicFloat,
+    max: AtomicFloat,
+}
+
+// Maps MIDI CCs to parameters. Shared between the editor (which starts learning and edits the
+// ranges) and the audio thread (which receives the CCs), so everything in here is atomic.
+pub struct MidiLearn {
+    learning: AtomicI32,
+    bindings: Vec<Binding>, // Indexed by controller number
+}
+
+impl MidiLearn {
+    pub fn new() -> Self {
+        let mut bindings = Vec::with_capacity(LEARNABLE_CONTROLLERS);
+        for _ in 0..LEARNABLE_CONTROLLERS {
+            bindings.push(Binding {
+                parameter: AtomicI32::new(UNBOUND),
+                min: AtomicFloat::new(0.0),
+                max: AtomicFloat::new(1.0),
+            });
+        }
+
+        Self {
+            learning: AtomicI32::new(NOT_LEARNING),
+            bindings,
+        }
+    }
+
+    // The next learnable CC that comes in gets bound to `parameter`.
+    pub fn start_learning(&self, parameter: i32) {
+        self.learning.store(parameter, Ordering::Relaxed);
+    }
+
+    pub fn cancel_learning(&self) {
+        self.learning.store(NOT_LEARNING, Ordering::Relaxed);
+    }
+
+    pub fn learning(&self) -> Option<i32> {
+        match self.learning.load(Ordering::Relaxed) {
+            NOT_LEARNING => None,
+            parameter => Some(parameter),
+        }
+    }
+
+    pub fn controller_for(&self, parameter: i32) -> Option<u8> {
+        self.bindings
+            .iter()
+            .position(|binding| binding.parameter.load(Ordering::Relaxed) == parameter)
+            .map(|controller| controller as u8)
+    }
+
+    pub fn forget(&self, parameter: i32) {
+        for binding in &self.bindings {
+            if binding.parameter.load(Ordering::Relaxed) == parameter {
+                binding.parameter.store(UNBOUND, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // The CC's full range (0-127) gets scaled to min..max. Setting min above max inverts it.
+    pub fn set_min(&self, parameter: i32, value: f32) {
+        if let Some(controller) = self.controller_for(parameter) {
+            self.bindings[controller as usize].min.set(value);
+        }
+    }
+
+    pub fn set_max(&self, parameter: i32, value: f32) {
+        if let Some(controller) = self.controller_for(parameter) {
+            self.bindings[controller as usize].max.set(value);
+        }
+    }
+
+    // Called for every incoming CC. Returns the parameter index and its new value if the CC is
+    // bound to something (including if it just got learned).
+    pub fn control_change(&self, controller: u8, value: u8) -> Option<(i32, f32)> {
+        let binding = self.bindings.get(controller as usize)?;
+
+        let learning = self.learning.swap(NOT_LEARNING, Ordering::Relaxed);
+        if learning != NOT_LEARNING {
+            // One CC per parameter, so re-learning moves the binding instead of adding another.
+            self.forget(learning);
+            binding.parameter.store(learning, Ordering::Relaxed);
+            binding.min.set(0.0);
+            binding.max.set(1.0);
+        }
+
+        let parameter = binding.parameter.load(Ordering::Relaxed);
+        if parameter == UNBOUND {
+            return None;
+        }
+
+        let min = binding.min.get();
+        let max = binding.max.get();
+        Some((parameter, min + (max - min) * (value as f32 / 127.0)))
+    }
+
+    pub fn write_chunk(&self, chunk: &mut ChunkWriter) {
+        let mut payload = Vec::new();
+        for (controller, binding) in self.bindings.iter().enumerate() {
+            let parameter = binding.parameter.load(Ordering::Relaxed);
+            if parameter == UNBOUND {
+                continue;
+            }
+            payload.push(controller as u8);
+            payload.extend_from_slice(&parameter.to_le_bytes());
+            payload.extend_from_slice(&binding.min.get().to_le_bytes());
+            payload.extend_from_slice(&binding.max.get().to_le_bytes());
+        }
+        chunk.section(b"MLRN", &payload);
+    }
+
+    pub fn read_chunk(&self, payload: &[u8]) {
+        for binding in &self.bindings {
+            binding.parameter.store(UNBOUND, Ordering::Relaxed);
+        }
+
+        let mut reader = ByteReader::new(payload);
+        loop {
+            let (controller, parameter, min, max) =
+                match (reader.u8(), reader.i32(), reader.f32(), reader.f32()) {
+                    (Some(controller), Some(parameter), Some(min), Some(max)) => {
+                        (controller, parameter, min, max)
+                    }
+                    _ => break,
+                };
+
+            if let Some(binding) = self.bindings.get(controller as usize) {
+                binding.parameter.store(parameter, Ordering::Relaxed);
+                binding.min.set(min);
+                binding.max.set(max);
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::chunk::{ByteReader, ChunkWriter};
+use crate::parameters::AtomicFloat;
+
+// The modulation matrix: a handful of slots, each sending one source to one destination by some
+// amount. Like the step sequence, the slots aren't host parameters. They live in `UiState` for the
+// editor and the audio thread to share, and get their own section of the state chunk.
+//
+// Sources and destinations are saved by id rather than by position in a list, so new ones can be
+// added anywhere without changing what old patches do.
+
+pub const MAX_SLOTS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModSource {
+    Off,
+    Lfo1,
+    Lfo2,
+    AmpEnvelope,
+    FilterEnvelope,
+    Velocity,
+    ModWheel,
+    Aftertouch,
+}
+
+// In the order the editor shows them.
+pub const SOURCES: [ModSource; 8] = [
+    ModSource::Off,
+    ModSource::Lfo1,
+    ModSource::Lfo2,
+    ModSource::AmpEnvelope,
+    ModSource::FilterEnvelope,
+    ModSource::Velocity,
+    ModSource::ModWheel,
+    ModSource::Aftertouch,
+];
+pub const NUM_SOURCES: usize = SOURCES.len();
+
+impl ModSource {
+    pub fn id(self) -> u8 {
+        match self {
+            ModSource::Off => 0,
+            ModSource::Lfo1 => 1,
+            ModSource::Lfo2 => 2,
+            ModSource::AmpEnvelope => 3,
+            ModSource::FilterEnvelope => 4,
+            ModSource::Velocity => 5,
+            ModSource::ModWheel => 6,
+            ModSource::Aftertouch => 7,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Self {
+        SOURCES.iter().cloned().find(|source| source.id() == id).unwrap_or(ModSource::Off)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModDestination {
+    Off,
+    Pitch,
+    PulseWidth,
+    Amplitude,
+    FilterCutoff,
+    FilterResonance,
+    Pan,
+    UnisonDetune,
+    FmIndex,
+    WavetablePosition,
+}
+
+// In the order the editor shows them.
+pub const DESTINATIONS: [ModDestination; 10] = [
+    ModDestination::Off,
+    ModDestination::Pitch,
+    ModDestination::PulseWidth,
+    ModDestination::Amplitude,
+    ModDestination::FilterCutoff,
+    ModDestination::FilterResonance,
+    ModDestination::Pan,
+    ModDestination::UnisonDetune,
+    ModDestination::FmIndex,
+    ModDestination::WavetablePosition,
+];
+pub const NUM_DESTINATIONS: usize = DESTINATIONS.len();
+
+impl ModDestination {
+    pub fn id(self) -> u8 {
+        match self {
+            ModDestination::Off => 0,
+            ModDestination::Pitch => 1,
+            ModDestination::PulseWidth => 2,
+            ModDestination::Amplitude => 3,
+            ModDestination::FilterCutoff => 4,
+            ModDestination::FilterResonance => 5,
+            ModDestination::Pan => 6,
+            ModDestination::UnisonDetune => 7,
+            ModDestination::FmIndex => 8,
+            ModDestination::WavetablePosition => 9,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Self {
+        DESTINATIONS
+            .iter()
+            .cloned()
+            .find(|destination| destination.id() == id)
+            .unwrap_or(ModDestination::Off)
+    }
+
+    // Where the destination is in `DESTINATIONS`, for keeping a value (or a buffer) for each.
+    pub fn index(self) -> usize {
+        DESTINATIONS.iter().position(|&destination| destination == self).unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ModSlot {
+    pub source: ModSource,
+    pub destination: ModDestination,
+    pub amount: f32, // -1.0 - 1.0
+}
+
+struct AtomicSlot {
+    source: AtomicU8,
+    destination: AtomicU8,
+    amount: AtomicFloat,
+}
+
+pub struct ModMatrix {
+    slots: Vec<AtomicSlot>,
+}
+
+impl ModMatrix {
+    pub fn new() -> Self {
+        let mut slots = Vec::with_capacity(MAX_SLOTS);
+        for _ in 0..MAX_SLOTS {
+            slots.push(AtomicSlot {
+                source: AtomicU8::new(ModSource::Off.id()),
+                destination: AtomicU8::new(ModDestination::Off.id()),
+                amount: AtomicFloat::new(0.0),
+            });
+        }
+        Self { slots }
+    }
+
+    pub fn slot(&self, index: usize) -> ModSlot {
+        let slot = &self.slots[index];
+        ModSlot {
+            source: ModSource::from_id(slot.source.load(Ordering::Relaxed)),
+            destination: ModDestination::from_id(slot.destination.load(Ordering::Relaxed)),
+            amount: slot.amount.get(),
+        }
+    }
+
+    // A copy of every slot, so the audio thread can read them once per block.
+    pub fn slots(&self) -> [ModSlot; MAX_SLOTS] {
+        let mut slots = [ModSlot {
+            source: ModSource::Off,
+            destination: ModDestination::Off,
+            amount: 0.0,
+        }; MAX_SLOTS];
+        for (index, slot) in slots.iter_mut().enumerate() {
+            *slot = self.slot(index);
+        }
+        slots
+    }
+
+    pub fn set_source(&self, index: usize, source: ModSource) {
+        self.slots[index].source.store(source.id(), Ordering::Relaxed);
+    }
+
+    pub fn set_destination(&self, index: usize, destination: ModDestination) {
+        self.slots[index].destination.store(destination.id(), Ordering::Relaxed);
+    }
+
+    pub fn set_amount(&self, index: usize, amount: f32) {
+        self.slots[index].amount.set(amount.max(-1.0).min(1.0));
+    }
+
+    pub fn clear(&self) {
+        for index in 0..MAX_SLOTS {
+            self.set_source(index, ModSource::Off);
+            self.set_destination(index, ModDestination::Off);
+            self.set_amount(index, 0.0);
+        }
+    }
+
+    pub fn write_chunk(&self, chunk: &mut ChunkWriter) {
+        let mut payload = Vec::new();
+        for index in 0..MAX_SLOTS {
+            let slot = self.slot(index);
+            payload.push(slot.source.id());
+            payload.push(slot.destination.id());
+            payload.extend_from_slice(&slot.amount.to_le_bytes());
+        }
+        chunk.section(b"MODM", &payload);
+    }
+
+    pub fn read_chunk(&self, payload: &[u8]) {
+        self.clear();
+        let mut reader = ByteReader::new(payload);
+        for index in 0..MAX_SLOTS {
+            let (source, destination, amount) = match (reader.u8(), reader.u8(), reader.f32()) {
+                (Some(source), Some(destination), Some(amount)) => (source, destination, amount),
+                _ => return,
+            };
+            self.set_source(index, ModSource::from_id(source));
+            self.set_destination(index, ModDestination::from_id(destination));
+            self.set_amount(index, amount);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::ChunkReader;
+
+    #[test]
+    fn slots_round_trip_through_the_chunk() {
+        let matrix = ModMatrix::new();
+        matrix.set_source(0, ModSource::Lfo2);
+        matrix.set_destination(0, ModDestination::FilterCutoff);
+        matrix.set_amount(0, -0.25);
+        matrix.set_source(7, ModSource::Aftertouch);
+        matrix.set_destination(7, ModDestination::WavetablePosition);
+        matrix.set_amount(7, 2.0);
+        let mut chunk = ChunkWriter::new();
+        matrix.write_chunk(&mut chunk);
+        let data = chunk.finish();
+
+        let loaded = ModMatrix::new();
+        let (tag, payload) = ChunkReader::new(&data).unwrap().next().unwrap();
+        assert_eq!(&tag, b"MODM");
+        loaded.read_chunk(payload);
+        for (slot, loaded_slot) in matrix.slots().iter().zip(loaded.slots().iter()) {
+            assert_eq!(slot.source, loaded_slot.source);
+            assert_eq!(slot.destination, loaded_slot.destination);
+            assert_eq!(slot.amount, loaded_slot.amount);
+        }
+        assert_eq!(loaded.slot(7).amount, 1.0);
+    }
+
+    #[test]
+    fn unknown_ids_are_off() {
+        assert_eq!(ModSource::from_id(200), ModSource::Off);
+        assert_eq!(ModDestination::from_id(200), ModDestination::Off);
+        for (index, &destination) in DESTINATIONS.iter().enumerate() {
+            assert_eq!(ModDestination::from_id(destination.id()), destination);
+            assert_eq!(destination.index(), index);
+        }
+    }
+}
@@ -1,9 +1,137 @@
 mod atomic_float;
-use self::atomic_float::AtomicFloat;
+pub use self::atomic_float::AtomicFloat;
+
+use crate::audio_engine::{
+    envelope_time_seconds, filter_cutoff_hz, filter_env_octaves, fm_ratio, glide_time_seconds,
+    lfo_rate_text, oscillator_fine_cents, oscillator_octave, oscillator_semitone, unison_count,
+    unison_detune_cents, ALGORITHM_NAMES,
+};
+use crate::arpeggiator::{arp_gate, RATE_NAMES};
+use crate::chunk::{ByteReader, ChunkWriter};
+use crate::effects::{chorus_rate_hz, delay_time_text};
+use crate::master::master_volume_db;
+use crate::midi_input_processor::mpe_bend_range;
+use crate::sequencer::seq_root_note;
+use crate::tuning::reference_pitch_hz;
+
+pub const NUM_PARAMETERS: i32 = 115;
 
 pub struct Parameters {
     pub amplitude: AtomicFloat,
     pub pulse_width: AtomicFloat,
+    pub voice_mode: AtomicFloat,
+    pub note_priority: AtomicFloat,
+    pub legato: AtomicFloat,
+    pub glide_time: AtomicFloat,
+    pub glide_curve: AtomicFloat,
+    pub glide_mode: AtomicFloat,
+    pub reference_pitch: AtomicFloat,
+    pub mpe_mode: AtomicFloat,
+    pub mpe_lower_zone: AtomicFloat,
+    pub mpe_upper_zone: AtomicFloat,
+    pub mpe_bend_range: AtomicFloat,
+    pub pressure_to_amplitude: AtomicFloat,
+    pub pressure_to_pulse_width: AtomicFloat,
+    pub arp_enabled: AtomicFloat,
+    pub arp_mode: AtomicFloat,
+    pub arp_octaves: AtomicFloat,
+    pub arp_rate: AtomicFloat,
+    pub arp_gate: AtomicFloat,
+    pub arp_latch: AtomicFloat,
+    pub seq_enabled: AtomicFloat,
+    pub seq_length: AtomicFloat,
+    pub seq_rate: AtomicFloat,
+    pub seq_root: AtomicFloat,
+    pub midi_out: AtomicFloat,
+    pub filter_mode: AtomicFloat,
+    pub filter_cutoff: AtomicFloat,
+    pub filter_resonance: AtomicFloat,
+    pub filter_key_tracking: AtomicFloat,
+    pub filter_env_amount: AtomicFloat,
+    pub filter_attack: AtomicFloat,
+    pub filter_decay: AtomicFloat,
+    pub filter_sustain: AtomicFloat,
+    pub filter_release: AtomicFloat,
+    pub lfo1_shape: AtomicFloat,
+    pub lfo1_rate: AtomicFloat,
+    pub lfo1_sync: AtomicFloat,
+    pub lfo1_retrigger: AtomicFloat,
+    pub lfo2_shape: AtomicFloat,
+    pub lfo2_rate: AtomicFloat,
+    pub lfo2_sync: AtomicFloat,
+    pub lfo2_retrigger: AtomicFloat,
+    pub unison_voices: AtomicFloat,
+    pub unison_detune: AtomicFloat,
+    pub unison_width: AtomicFloat,
+    pub pan: AtomicFloat,
+    pub osc1_waveform: AtomicFloat,
+    pub osc1_octave: AtomicFloat,
+    pub osc1_semitone: AtomicFloat,
+    pub osc1_fine: AtomicFloat,
+    pub osc1_level: AtomicFloat,
+    pub osc2_waveform: AtomicFloat,
+    pub osc2_octave: AtomicFloat,
+    pub osc2_semitone: AtomicFloat,
+    pub osc2_fine: AtomicFloat,
+    pub osc2_level: AtomicFloat,
+    pub osc3_waveform: AtomicFloat,
+    pub osc3_octave: AtomicFloat,
+    pub osc3_semitone: AtomicFloat,
+    pub osc3_fine: AtomicFloat,
+    pub osc3_level: AtomicFloat,
+    pub osc_sync: AtomicFloat,
+    pub ring_mod_level: AtomicFloat,
+    pub sub_level: AtomicFloat,
+    pub synth_mode: AtomicFloat,
+    pub fm_algorithm: AtomicFloat,
+    pub fm_feedback: AtomicFloat,
+    pub op1_ratio: AtomicFloat,
+    pub op1_index: AtomicFloat,
+    pub op1_attack: AtomicFloat,
+    pub op1_decay: AtomicFloat,
+    pub op1_sustain: AtomicFloat,
+    pub op1_release: AtomicFloat,
+    pub op2_ratio: AtomicFloat,
+    pub op2_index: AtomicFloat,
+    pub op2_attack: AtomicFloat,
+    pub op2_decay: AtomicFloat,
+    pub op2_sustain: AtomicFloat,
+    pub op2_release: AtomicFloat,
+    pub op3_ratio: AtomicFloat,
+    pub op3_index: AtomicFloat,
+    pub op3_attack: AtomicFloat,
+    pub op3_decay: AtomicFloat,
+    pub op3_sustain: AtomicFloat,
+    pub op3_release: AtomicFloat,
+    pub op4_ratio: AtomicFloat,
+    pub op4_index: AtomicFloat,
+    pub op4_attack: AtomicFloat,
+    pub op4_decay: AtomicFloat,
+    pub op4_sustain: AtomicFloat,
+    pub op4_release: AtomicFloat,
+    pub wavetable_level: AtomicFloat,
+    pub wavetable_position: AtomicFloat,
+    pub sampler_level: AtomicFloat,
+    pub sampler_release: AtomicFloat,
+    pub delay_bypass: AtomicFloat,
+    pub delay_time: AtomicFloat,
+    pub delay_sync: AtomicFloat,
+    pub delay_feedback: AtomicFloat,
+    pub delay_ping_pong: AtomicFloat,
+    pub delay_mix: AtomicFloat,
+    pub chorus_bypass: AtomicFloat,
+    pub chorus_rate: AtomicFloat,
+    pub chorus_depth: AtomicFloat,
+    pub chorus_mix: AtomicFloat,
+    pub reverb_bypass: AtomicFloat,
+    pub reverb_size: AtomicFloat,
+    pub reverb_damping: AtomicFloat,
+    pub reverb_mix: AtomicFloat,
+    pub master_volume: AtomicFloat,
+    pub soft_clip: AtomicFloat,
+    pub limiter: AtomicFloat,
+    pub oversampling: AtomicFloat,
+    pub offline_oversampling: AtomicFloat,
 }
 
 impl Parameters {
@@ -11,6 +139,570 @@ impl Parameters {
         Self {
             amplitude: AtomicFloat::new(0.3),
             pulse_width: AtomicFloat::new(0.5),
+            voice_mode: AtomicFloat::new(0.0),
+            note_priority: AtomicFloat::new(0.0),
+            legato: AtomicFloat::new(0.0),
+            glide_time: AtomicFloat::new(0.0),
+            glide_curve: AtomicFloat::new(0.0),
+            glide_mode: AtomicFloat::new(0.0),
+            reference_pitch: AtomicFloat::new(0.5),
+            mpe_mode: AtomicFloat::new(0.0),
+            mpe_lower_zone: AtomicFloat::new(1.0),
+            mpe_upper_zone: AtomicFloat::new(0.0),
+            mpe_bend_range: AtomicFloat::new(0.5),
+            pressure_to_amplitude: AtomicFloat::new(1.0),
+            pressure_to_pulse_width: AtomicFloat::new(0.5),
+            arp_enabled: AtomicFloat::new(0.0),
+            arp_mode: AtomicFloat::new(0.0),
+            arp_octaves: AtomicFloat::new(0.0),
+            arp_rate: AtomicFloat::new(0.6), // 1/16
+            arp_gate: AtomicFloat::new(0.5),
+            arp_latch: AtomicFloat::new(0.0),
+            seq_enabled: AtomicFloat::new(0.0),
+            seq_length: AtomicFloat::new(0.0),
+            seq_rate: AtomicFloat::new(0.6), // 1/16
+            seq_root: AtomicFloat::new(60.0 / 127.0), // C4
+            midi_out: AtomicFloat::new(0.0),
+            filter_mode: AtomicFloat::new(0.0),
+            filter_cutoff: AtomicFloat::new(1.0),
+            filter_resonance: AtomicFloat::new(0.0),
+            filter_key_tracking: AtomicFloat::new(0.0),
+            filter_env_amount: AtomicFloat::new(0.5),
+            filter_attack: AtomicFloat::new(0.0),
+            filter_decay: AtomicFloat::new(0.2),
+            filter_sustain: AtomicFloat::new(0.5),
+            filter_release: AtomicFloat::new(0.1),
+            lfo1_shape: AtomicFloat::new(0.0),
+            lfo1_rate: AtomicFloat::new(0.5),
+            lfo1_sync: AtomicFloat::new(0.0),
+            lfo1_retrigger: AtomicFloat::new(0.0),
+            lfo2_shape: AtomicFloat::new(0.0),
+            lfo2_rate: AtomicFloat::new(0.5),
+            lfo2_sync: AtomicFloat::new(0.0),
+            lfo2_retrigger: AtomicFloat::new(1.0),
+            unison_voices: AtomicFloat::new(0.0),
+            unison_detune: AtomicFloat::new(0.2),
+            unison_width: AtomicFloat::new(0.5),
+            pan: AtomicFloat::new(0.5),
+            osc1_waveform: AtomicFloat::new(0.0),
+            osc1_octave: AtomicFloat::new(0.5),
+            osc1_semitone: AtomicFloat::new(0.5),
+            osc1_fine: AtomicFloat::new(0.5),
+            osc1_level: AtomicFloat::new(1.0),
+            osc2_waveform: AtomicFloat::new(0.0),
+            osc2_octave: AtomicFloat::new(0.5),
+            osc2_semitone: AtomicFloat::new(0.5),
+            osc2_fine: AtomicFloat::new(0.5),
+            osc2_level: AtomicFloat::new(0.0),
+            osc3_waveform: AtomicFloat::new(0.0),
+            osc3_octave: AtomicFloat::new(0.5),
+            osc3_semitone: AtomicFloat::new(0.5),
+            osc3_fine: AtomicFloat::new(0.5),
+            osc3_level: AtomicFloat::new(0.0),
+            osc_sync: AtomicFloat::new(0.0),
+            ring_mod_level: AtomicFloat::new(0.0),
+            sub_level: AtomicFloat::new(0.0),
+            synth_mode: AtomicFloat::new(0.0),
+            fm_algorithm: AtomicFloat::new(0.0),
+            fm_feedback: AtomicFloat::new(0.0),
+            op1_ratio: AtomicFloat::new(2.0 / 18.0),
+            op1_index: AtomicFloat::new(1.0),
+            op1_attack: AtomicFloat::new(0.0),
+            op1_decay: AtomicFloat::new(0.3),
+            op1_sustain: AtomicFloat::new(1.0),
+            op1_release: AtomicFloat::new(0.1),
+            op2_ratio: AtomicFloat::new(2.0 / 18.0),
+            op2_index: AtomicFloat::new(0.3),
+            op2_attack: AtomicFloat::new(0.0),
+            op2_decay: AtomicFloat::new(0.3),
+            op2_sustain: AtomicFloat::new(0.5),
+            op2_release: AtomicFloat::new(0.1),
+            op3_ratio: AtomicFloat::new(2.0 / 18.0),
+            op3_index: AtomicFloat::new(0.0),
+            op3_attack: AtomicFloat::new(0.0),
+            op3_decay: AtomicFloat::new(0.3),
+            op3_sustain: AtomicFloat::new(0.5),
+            op3_release: AtomicFloat::new(0.1),
+            op4_ratio: AtomicFloat::new(2.0 / 18.0),
+            op4_index: AtomicFloat::new(0.0),
+            op4_attack: AtomicFloat::new(0.0),
+            op4_decay: AtomicFloat::new(0.3),
+            op4_sustain: AtomicFloat::new(0.5),
+            op4_release: AtomicFloat::new(0.1),
+            wavetable_level: AtomicFloat::new(0.0),
+            wavetable_position: AtomicFloat::new(0.0),
+            sampler_level: AtomicFloat::new(0.5),
+            sampler_release: AtomicFloat::new(0.1),
+            delay_bypass: AtomicFloat::new(1.0),
+            delay_time: AtomicFloat::new(0.5),
+            delay_sync: AtomicFloat::new(0.0),
+            delay_feedback: AtomicFloat::new(0.4),
+            delay_ping_pong: AtomicFloat::new(0.0),
+            delay_mix: AtomicFloat::new(0.3),
+            chorus_bypass: AtomicFloat::new(1.0),
+            chorus_rate: AtomicFloat::new(0.4),
+            chorus_depth: AtomicFloat::new(0.5),
+            chorus_mix: AtomicFloat::new(0.5),
+            reverb_bypass: AtomicFloat::new(1.0),
+            reverb_size: AtomicFloat::new(0.5),
+            reverb_damping: AtomicFloat::new(0.5),
+            reverb_mix: AtomicFloat::new(0.25),
+            master_volume: AtomicFloat::new(10.0 / 11.0),
+            soft_clip: AtomicFloat::new(1.0),
+            limiter: AtomicFloat::new(0.0),
+            oversampling: AtomicFloat::new(0.0),
+            offline_oversampling: AtomicFloat::new(0.0),
+        }
+    }
+
+    fn get_atomic(&self, index: i32) -> Option<&AtomicFloat> {
+        match index {
+            0 => Some(&self.amplitude),
+            1 => Some(&self.pulse_width),
+            2 => Some(&self.voice_mode),
+            3 => Some(&self.note_priority),
+            4 => Some(&self.legato),
+            5 => Some(&self.glide_time),
+            6 => Some(&self.glide_curve),
+            7 => Some(&self.glide_mode),
+            8 => Some(&self.reference_pitch),
+            9 => Some(&self.mpe_mode),
+            10 => Some(&self.mpe_lower_zone),
+            11 => Some(&self.mpe_upper_zone),
+            12 => Some(&self.mpe_bend_range),
+            13 => Some(&self.pressure_to_amplitude),
+            14 => Some(&self.pressure_to_pulse_width),
+            15 => Some(&self.arp_enabled),
+            16 => Some(&self.arp_mode),
+            17 => Some(&self.arp_octaves),
+            18 => Some(&self.arp_rate),
+            19 => Some(&self.arp_gate),
+            20 => Some(&self.arp_latch),
+            21 => Some(&self.seq_enabled),
+            22 => Some(&self.seq_length),
+            23 => Some(&self.seq_rate),
+            24 => Some(&self.seq_root),
+            25 => Some(&self.midi_out),
+            26 => Some(&self.filter_mode),
+            27 => Some(&self.filter_cutoff),
+            28 => Some(&self.filter_resonance),
+            29 => Some(&self.filter_key_tracking),
+            30 => Some(&self.filter_env_amount),
+            31 => Some(&self.filter_attack),
+            32 => Some(&self.filter_decay),
+            33 => Some(&self.filter_sustain),
+            34 => Some(&self.filter_release),
+            35 => Some(&self.lfo1_shape),
+            36 => Some(&self.lfo1_rate),
+            37 => Some(&self.lfo1_sync),
+            38 => Some(&self.lfo1_retrigger),
+            39 => Some(&self.lfo2_shape),
+            40 => Some(&self.lfo2_rate),
+            41 => Some(&self.lfo2_sync),
+            42 => Some(&self.lfo2_retrigger),
+            43 => Some(&self.unison_voices),
+            44 => Some(&self.unison_detune),
+            45 => Some(&self.unison_width),
+            46 => Some(&self.pan),
+            47 => Some(&self.osc1_waveform),
+            48 => Some(&self.osc1_octave),
+            49 => Some(&self.osc1_semitone),
+            50 => Some(&self.osc1_fine),
+            51 => Some(&self.osc1_level),
+            52 => Some(&self.osc2_waveform),
+            53 => Some(&self.osc2_octave),
+            54 => Some(&self.osc2_semitone),
+            55 => Some(&self.osc2_fine),
+            56 => Some(&self.osc2_level),
+            57 => Some(&self.osc3_waveform),
+            58 => Some(&self.osc3_octave),
+            59 => Some(&self.osc3_semitone),
+            60 => Some(&self.osc3_fine),
+            61 => Some(&self.osc3_level),
+            62 => Some(&self.osc_sync),
+            63 => Some(&self.ring_mod_level),
+            64 => Some(&self.sub_level),
+            65 => Some(&self.synth_mode),
+            66 => Some(&self.fm_algorithm),
+            67 => Some(&self.fm_feedback),
+            68 => Some(&self.op1_ratio),
+            69 => Some(&self.op1_index),
+            70 => Some(&self.op1_attack),
+            71 => Some(&self.op1_decay),
+            72 => Some(&self.op1_sustain),
+            73 => Some(&self.op1_release),
+            74 => Some(&self.op2_ratio),
+            75 => Some(&self.op2_index),
+            76 => Some(&self.op2_attack),
+            77 => Some(&self.op2_decay),
+            78 => Some(&self.op2_sustain),
+            79 => Some(&self.op2_release),
+            80 => Some(&self.op3_ratio),
+            81 => Some(&self.op3_index),
+            82 => Some(&self.op3_attack),
+            83 => Some(&self.op3_decay),
+            84 => Some(&self.op3_sustain),
+            85 => Some(&self.op3_release),
+            86 => Some(&self.op4_ratio),
+            87 => Some(&self.op4_index),
+            88 => Some(&self.op4_attack),
+            89 => Some(&self.op4_decay),
+            90 => Some(&self.op4_sustain),
+            91 => Some(&self.op4_release),
+            92 => Some(&self.wavetable_level),
+            93 => Some(&self.wavetable_position),
+            94 => Some(&self.sampler_level),
+            95 => Some(&self.sampler_release),
+            96 => Some(&self.delay_bypass),
+            97 => Some(&self.delay_time),
+            98 => Some(&self.delay_sync),
+            99 => Some(&self.delay_feedback),
+            100 => Some(&self.delay_ping_pong),
+            101 => Some(&self.delay_mix),
+            102 => Some(&self.chorus_bypass),
+            103 => Some(&self.chorus_rate),
+            104 => Some(&self.chorus_depth),
+            105 => Some(&self.chorus_mix),
+            106 => Some(&self.reverb_bypass),
+            107 => Some(&self.reverb_size),
+            108 => Some(&self.reverb_damping),
+            109 => Some(&self.reverb_mix),
+            110 => Some(&self.master_volume),
+            111 => Some(&self.soft_clip),
+            112 => Some(&self.limiter),
+            113 => Some(&self.oversampling),
+            114 => Some(&self.offline_oversampling),
+            _ => None,
+        }
+    }
+
+    pub fn get_parameter(&self, index: i32) -> f32 {
+        match self.get_atomic(index) {
+            Some(parameter) => parameter.get(),
+            None => 0.0,
+        }
+    }
+
+    pub fn set_parameter(&self, index: i32, value: f32) {
+        if let Some(parameter) = self.get_atomic(index) {
+            parameter.set(value.max(0.0).min(1.0));
         }
     }
+
+    // "Amplitude", "Pulse width", etc.
+    pub fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            0 => format!("Amplitude"),
+            1 => format!("Pulse width"),
+            2 => format!("Voice mode"),
+            3 => format!("Note priority"),
+            4 => format!("Legato"),
+            5 => format!("Glide time"),
+            6 => format!("Glide curve"),
+            7 => format!("Glide mode"),
+            8 => format!("Reference pitch"),
+            9 => format!("MPE"),
+            10 => format!("MPE lower zone"),
+            11 => format!("MPE upper zone"),
+            12 => format!("MPE bend range"),
+            13 => format!("Pressure > amplitude"),
+            14 => format!("Pressure > pulse width"),
+            15 => format!("Arpeggiator"),
+            16 => format!("Arp mode"),
+            17 => format!("Arp octaves"),
+            18 => format!("Arp rate"),
+            19 => format!("Arp gate"),
+            20 => format!("Arp latch"),
+            21 => format!("Sequencer"),
+            22 => format!("Seq length"),
+            23 => format!("Seq rate"),
+            24 => format!("Seq root note"),
+            25 => format!("MIDI out"),
+            26 => format!("Filter mode"),
+            27 => format!("Filter cutoff"),
+            28 => format!("Filter resonance"),
+            29 => format!("Filter key tracking"),
+            30 => format!("Filter env amount"),
+            31 => format!("Filter attack"),
+            32 => format!("Filter decay"),
+            33 => format!("Filter sustain"),
+            34 => format!("Filter release"),
+            35 => format!("LFO 1 shape"),
+            36 => format!("LFO 1 rate"),
+            37 => format!("LFO 1 sync"),
+            38 => format!("LFO 1 retrigger"),
+            39 => format!("LFO 2 shape"),
+            40 => format!("LFO 2 rate"),
+            41 => format!("LFO 2 sync"),
+            42 => format!("LFO 2 retrigger"),
+            43 => format!("Unison voices"),
+            44 => format!("Unison detune"),
+            45 => format!("Unison width"),
+            46 => format!("Pan"),
+            47 => format!("Osc 1 waveform"),
+            48 => format!("Osc 1 octave"),
+            49 => format!("Osc 1 semitone"),
+            50 => format!("Osc 1 fine"),
+            51 => format!("Osc 1 level"),
+            52 => format!("Osc 2 waveform"),
+            53 => format!("Osc 2 octave"),
+            54 => format!("Osc 2 semitone"),
+            55 => format!("Osc 2 fine"),
+            56 => format!("Osc 2 level"),
+            57 => format!("Osc 3 waveform"),
+            58 => format!("Osc 3 octave"),
+            59 => format!("Osc 3 semitone"),
+            60 => format!("Osc 3 fine"),
+            61 => format!("Osc 3 level"),
+            62 => format!("Osc 2 sync"),
+            63 => format!("Ring mod level"),
+            64 => format!("Sub level"),
+            65 => format!("Synth mode"),
+            66 => format!("FM algorithm"),
+            67 => format!("FM feedback"),
+            68 => format!("Op 1 ratio"),
+            69 => format!("Op 1 index"),
+            70 => format!("Op 1 attack"),
+            71 => format!("Op 1 decay"),
+            72 => format!("Op 1 sustain"),
+            73 => format!("Op 1 release"),
+            74 => format!("Op 2 ratio"),
+            75 => format!("Op 2 index"),
+            76 => format!("Op 2 attack"),
+            77 => format!("Op 2 decay"),
+            78 => format!("Op 2 sustain"),
+            79 => format!("Op 2 release"),
+            80 => format!("Op 3 ratio"),
+            81 => format!("Op 3 index"),
+            82 => format!("Op 3 attack"),
+            83 => format!("Op 3 decay"),
+            84 => format!("Op 3 sustain"),
+            85 => format!("Op 3 release"),
+            86 => format!("Op 4 ratio"),
+            87 => format!("Op 4 index"),
+            88 => format!("Op 4 attack"),
+            89 => format!("Op 4 decay"),
+            90 => format!("Op 4 sustain"),
+            91 => format!("Op 4 release"),
+            92 => format!("Wavetable level"),
+            93 => format!("Wavetable position"),
+            94 => format!("Sampler level"),
+            95 => format!("Sampler release"),
+            96 => format!("Delay bypass"),
+            97 => format!("Delay time"),
+            98 => format!("Delay sync"),
+            99 => format!("Delay feedback"),
+            100 => format!("Delay ping-pong"),
+            101 => format!("Delay mix"),
+            102 => format!("Chorus bypass"),
+            103 => format!("Chorus rate"),
+            104 => format!("Chorus depth"),
+            105 => format!("Chorus mix"),
+            106 => format!("Reverb bypass"),
+            107 => format!("Reverb size"),
+            108 => format!("Reverb damping"),
+            109 => format!("Reverb mix"),
+            110 => format!("Master volume"),
+            111 => format!("Soft clip"),
+            112 => format!("Limiter"),
+            113 => format!("Oversampling"),
+            114 => format!("Offline oversampling"),
+            _ => format!(""),
+        }
+    }
+
+    // "1.0", "150", "Plate", etc.
+    pub fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            0 => format!("{:0.2} %", self.amplitude.get() * 100.0), // Amplitude
+            1 => format!("{:0.2} %", self.pulse_width.get() * 100.0), // Pulse width
+            2 => choice_text(self.voice_mode.get(), &["Poly", "Mono"]),
+            3 => choice_text(self.note_priority.get(), &["Last", "Low", "High"]),
+            4 => choice_text(self.legato.get(), &["Retrigger", "Legato"]),
+            5 => match glide_time_seconds(self.glide_time.get()) {
+                time if time <= 0.0 => format!("Off"),
+                time => format!("{:0.0} ms", time * 1000.0),
+            },
+            6 => choice_text(self.glide_curve.get(), &["Constant time", "Constant rate"]),
+            7 => choice_text(self.glide_mode.get(), &["Always", "Legato only"]),
+            8 => format!("{:0.1} Hz", reference_pitch_hz(self.reference_pitch.get())),
+            9 => choice_text(self.mpe_mode.get(), &["Off", "On"]),
+            10 => zone_text(self.mpe_lower_zone.get()),
+            11 => zone_text(self.mpe_upper_zone.get()),
+            12 => format!("{} semitones", mpe_bend_range(self.mpe_bend_range.get())),
+            13 => format!("{:0.0} %", self.pressure_to_amplitude.get() * 100.0),
+            14 => format!("{:+0.0} %", pressure_to_pulse_width(self.pressure_to_pulse_width.get()) * 100.0),
+            15 => choice_text(self.arp_enabled.get(), &["Off", "On"]),
+            16 => choice_text(self.arp_mode.get(), &["Up", "Down", "Up-down", "Random", "As played"]),
+            17 => choice_text(self.arp_octaves.get(), &["1", "2", "3", "4"]),
+            18 => choice_text(self.arp_rate.get(), &RATE_NAMES),
+            19 => format!("{:0.0} %", arp_gate(self.arp_gate.get()) * 100.0),
+            20 => choice_text(self.arp_latch.get(), &["Off", "On"]),
+            21 => choice_text(self.seq_enabled.get(), &["Off", "On"]),
+            22 => choice_text(self.seq_length.get(), &["16 steps", "32 steps"]),
+            23 => choice_text(self.seq_rate.get(), &RATE_NAMES),
+            24 => note_name(seq_root_note(self.seq_root.get())),
+            25 => choice_text(self.midi_out.get(), &["Off", "Thru", "Arp/Seq"]),
+            26 => choice_text(self.filter_mode.get(), &["Low-pass", "High-pass", "Band-pass", "Notch"]),
+            27 => format!("{:0.0} Hz", filter_cutoff_hz(self.filter_cutoff.get())),
+            28 => format!("{:0.0} %", self.filter_resonance.get() * 100.0),
+            29 => format!("{:0.0} %", self.filter_key_tracking.get() * 100.0),
+            30 => format!("{:+0.1} oct", filter_env_octaves(self.filter_env_amount.get())),
+            31 => time_text(envelope_time_seconds(self.filter_attack.get())),
+            32 => time_text(envelope_time_seconds(self.filter_decay.get())),
+            33 => format!("{:0.0} %", self.filter_sustain.get() * 100.0),
+            34 => time_text(envelope_time_seconds(self.filter_release.get())),
+            35 => choice_text(self.lfo1_shape.get(), &["Sine", "Triangle", "Saw up", "Saw down", "Square", "Sample & hold"]),
+            36 => lfo_rate_text(self.lfo1_rate.get(), self.lfo1_sync.get()),
+            37 => choice_text(self.lfo1_sync.get(), &["Off", "On"]),
+            38 => choice_text(self.lfo1_retrigger.get(), &["Off", "On"]),
+            39 => choice_text(self.lfo2_shape.get(), &["Sine", "Triangle", "Saw up", "Saw down", "Square", "Sample & hold"]),
+            40 => lfo_rate_text(self.lfo2_rate.get(), self.lfo2_sync.get()),
+            41 => choice_text(self.lfo2_sync.get(), &["Off", "On"]),
+            42 => choice_text(self.lfo2_retrigger.get(), &["Off", "On"]),
+            43 => format!("{}", unison_count(self.unison_voices.get())),
+            44 => format!("{:0.0} cents", unison_detune_cents(self.unison_detune.get())),
+            45 => format!("{:0.0} %", self.unison_width.get() * 100.0),
+            46 => pan_text(self.pan.get()),
+            47 => choice_text(self.osc1_waveform.get(), &["Pulse", "Saw", "Triangle", "Sine"]),
+            48 => format!("{:+}", oscillator_octave(self.osc1_octave.get())),
+            49 => format!("{:+} st", oscillator_semitone(self.osc1_semitone.get())),
+            50 => format!("{:+0.0} cents", oscillator_fine_cents(self.osc1_fine.get())),
+            51 => format!("{:0.0} %", self.osc1_level.get() * 100.0),
+            52 => choice_text(self.osc2_waveform.get(), &["Pulse", "Saw", "Triangle", "Sine"]),
+            53 => format!("{:+}", oscillator_octave(self.osc2_octave.get())),
+            54 => format!("{:+} st", oscillator_semitone(self.osc2_semitone.get())),
+            55 => format!("{:+0.0} cents", oscillator_fine_cents(self.osc2_fine.get())),
+            56 => format!("{:0.0} %", self.osc2_level.get() * 100.0),
+            57 => choice_text(self.osc3_waveform.get(), &["Pulse", "Saw", "Triangle", "Sine"]),
+            58 => format!("{:+}", oscillator_octave(self.osc3_octave.get())),
+            59 => format!("{:+} st", oscillator_semitone(self.osc3_semitone.get())),
+            60 => format!("{:+0.0} cents", oscillator_fine_cents(self.osc3_fine.get())),
+            61 => format!("{:0.0} %", self.osc3_level.get() * 100.0),
+            62 => choice_text(self.osc_sync.get(), &["Off", "On"]),
+            63 => format!("{:0.0} %", self.ring_mod_level.get() * 100.0),
+            64 => format!("{:0.0} %", self.sub_level.get() * 100.0),
+            65 => choice_text(self.synth_mode.get(), &["Subtractive", "FM"]),
+            66 => choice_text(self.fm_algorithm.get(), &ALGORITHM_NAMES),
+            67 => format!("{:0.0} %", self.fm_feedback.get() * 100.0),
+            68 => format!("x{}", fm_ratio(self.op1_ratio.get())),
+            69 => format!("{:0.0} %", self.op1_index.get() * 100.0),
+            70 => time_text(envelope_time_seconds(self.op1_attack.get())),
+            71 => time_text(envelope_time_seconds(self.op1_decay.get())),
+            72 => format!("{:0.0} %", self.op1_sustain.get() * 100.0),
+            73 => time_text(envelope_time_seconds(self.op1_release.get())),
+            74 => format!("x{}", fm_ratio(self.op2_ratio.get())),
+            75 => format!("{:0.0} %", self.op2_index.get() * 100.0),
+            76 => time_text(envelope_time_seconds(self.op2_attack.get())),
+            77 => time_text(envelope_time_seconds(self.op2_decay.get())),
+            78 => format!("{:0.0} %", self.op2_sustain.get() * 100.0),
+            79 => time_text(envelope_time_seconds(self.op2_release.get())),
+            80 => format!("x{}", fm_ratio(self.op3_ratio.get())),
+            81 => format!("{:0.0} %", self.op3_index.get() * 100.0),
+            82 => time_text(envelope_time_seconds(self.op3_attack.get())),
+            83 => time_text(envelope_time_seconds(self.op3_decay.get())),
+            84 => format!("{:0.0} %", self.op3_sustain.get() * 100.0),
+            85 => time_text(envelope_time_seconds(self.op3_release.get())),
+            86 => format!("x{}", fm_ratio(self.op4_ratio.get())),
+            87 => format!("{:0.0} %", self.op4_index.get() * 100.0),
+            88 => time_text(envelope_time_seconds(self.op4_attack.get())),
+            89 => time_text(envelope_time_seconds(self.op4_decay.get())),
+            90 => format!("{:0.0} %", self.op4_sustain.get() * 100.0),
+            91 => time_text(envelope_time_seconds(self.op4_release.get())),
+            92 => format!("{:0.0} %", self.wavetable_level.get() * 100.0),
+            93 => format!("{:0.0} %", self.wavetable_position.get() * 100.0),
+            94 => format!("{:0.0} %", self.sampler_level.get() * 100.0),
+            95 => time_text(envelope_time_seconds(self.sampler_release.get())),
+            96 => choice_text(self.delay_bypass.get(), &["Off", "On"]),
+            97 => delay_time_text(self.delay_time.get(), self.delay_sync.get()),
+            98 => choice_text(self.delay_sync.get(), &["Off", "On"]),
+            99 => format!("{:0.0} %", self.delay_feedback.get() * 100.0),
+            100 => choice_text(self.delay_ping_pong.get(), &["Off", "On"]),
+            101 => format!("{:0.0} %", self.delay_mix.get() * 100.0),
+            102 => choice_text(self.chorus_bypass.get(), &["Off", "On"]),
+            103 => format!("{:0.2} Hz", chorus_rate_hz(self.chorus_rate.get())),
+            104 => format!("{:0.0} %", self.chorus_depth.get() * 100.0),
+            105 => format!("{:0.0} %", self.chorus_mix.get() * 100.0),
+            106 => choice_text(self.reverb_bypass.get(), &["Off", "On"]),
+            107 => format!("{:0.0} %", self.reverb_size.get() * 100.0),
+            108 => format!("{:0.0} %", self.reverb_damping.get() * 100.0),
+            109 => format!("{:0.0} %", self.reverb_mix.get() * 100.0),
+            110 => match master_volume_db(self.master_volume.get()) {
+                Some(db) => format!("{:+0.1} dB", db),
+                None => format!("-inf dB"),
+            },
+            111 => choice_text(self.soft_clip.get(), &["Off", "On"]),
+            112 => choice_text(self.limiter.get(), &["Off", "On"]),
+            113 => choice_text(self.oversampling.get(), &["Off", "2x", "4x", "8x"]),
+            114 => choice_text(self.offline_oversampling.get(), &["Off", "2x", "4x", "8x"]),
+            _ => format!(""),
+        }
+    }
+
+    pub fn write_chunk(&self, chunk: &mut ChunkWriter) {
+        let mut payload = Vec::new();
+        for index in 0..NUM_PARAMETERS {
+            payload.extend_from_slice(&self.get_parameter(index).to_le_bytes());
+        }
+        chunk.section(b"PARM", &payload);
+    }
+
+    // Parameters that are missing from an older chunk keep their current value.
+    pub fn read_chunk(&self, payload: &[u8]) {
+        let mut reader = ByteReader::new(payload);
+        let mut index = 0;
+        while let Some(value) = reader.f32() {
+            self.set_parameter(index, value);
+            index += 1;
+        }
+    }
+}
+
+// For parameters that pick one of a few options: the options are spread evenly over 0.0 - 1.0.
+pub fn choice_index(value: f32, choices: usize) -> usize {
+    ((value * (choices - 1) as f32).round() as usize).min(choices - 1)
+}
+
+fn choice_text(value: f32, choices: &[&str]) -> String {
+    choices[choice_index(value, choices.len())].to_string()
+}
+
+fn time_text(seconds: f64) -> String {
+    if seconds < 1.0 {
+        format!("{:0.0} ms", seconds * 1000.0)
+    } else {
+        format!("{:0.2} s", seconds)
+    }
+}
+
+fn pan_text(value: f32) -> String {
+    let pan = ((value - 0.5) * 200.0).round();
+    if pan < 0.0 {
+        format!("{:0.0} % L", -pan)
+    } else if pan > 0.0 {
+        format!("{:0.0} % R", pan)
+    } else {
+        format!("Center")
+    }
+}
+
+// How far full pressure moves the pulse width: up to half the range, either way.
+pub fn pressure_to_pulse_width(value: f32) -> f64 {
+    value as f64 - 0.5
+}
+
+// MPE zones are a number of member channels, 0 - 15.
+fn zone_text(value: f32) -> String {
+    match choice_index(value, 16) {
+        0 => format!("Off"),
+        1 => format!("1 channel"),
+        channels => format!("{} channels", channels),
+    }
+}
+
+// "C4" is MIDI note 60.
+fn note_name(note: u8) -> String {
+    const NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+    format!("{}{}", NAMES[note as usize % 12], note as i32 / 12 - 1)
 }
@@ -0,0 +1,362 @@
+use log::*;
+
+mod sfz;
+
+use crate::audio_file::{self, AudioFile};
+use crate::chunk::{ByteReader, ChunkWriter};
+
+// What the sampler plays: some sample files, and zones that say which keys and velocities play
+// which file, at what pitch, and how it loops. Made from a single WAV/AIFF file (one zone over
+// the whole keyboard) or from an SFZ file.
+
+const DEFAULT_ROOT_NOTE: u8 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopMode {
+    // Plays to the end, or until the note's release is over.
+    NoLoop,
+    // Plays to the end, whatever happens to the note.
+    OneShot,
+    // Loops for as long as the voice is playing, release included.
+    Continuous,
+    // Loops until the note is released, then plays on to the end.
+    Sustain,
+}
+
+impl LoopMode {
+    fn id(self) -> u8 {
+        match self {
+            LoopMode::NoLoop => 0,
+            LoopMode::OneShot => 1,
+            LoopMode::Continuous => 2,
+            LoopMode::Sustain => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Self {
+        match id {
+            1 => LoopMode::OneShot,
+            2 => LoopMode::Continuous,
+            3 => LoopMode::Sustain,
+            _ => LoopMode::NoLoop,
+        }
+    }
+
+    pub fn loops(self) -> bool {
+        self == LoopMode::Continuous || self == LoopMode::Sustain
+    }
+}
+
+pub struct SampleFile {
+    name: String,
+    // The whole file, which is what goes in the plugin state.
+    source: Vec<u8>,
+    pub audio: AudioFile,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Zone {
+    // Index into the map's files.
+    pub file: usize,
+    pub low_key: u8,
+    pub high_key: u8,
+    pub low_velocity: u8,
+    pub high_velocity: u8,
+    // The key that plays the file at its own pitch.
+    pub root_note: u8,
+    pub loop_mode: LoopMode,
+    // First and last frames of the loop, both inclusive.
+    pub loop_start: usize,
+    pub loop_end: usize,
+    pub tune_cents: f64,
+    pub gain: f64,
+    pub pan: f64, // -1.0 (left) - 1.0 (right)
+}
+
+impl Zone {
+    fn new(file: usize, audio: &AudioFile) -> Self {
+        let (loop_start, loop_end) = audio.loop_points.unwrap_or((0, audio.num_frames() - 1));
+        Self {
+            file,
+            low_key: 0,
+            high_key: 127,
+            low_velocity: 1,
+            high_velocity: 127,
+            root_note: audio.root_note.unwrap_or(DEFAULT_ROOT_NOTE),
+            // Like SFZ: files with a loop in them loop unless told otherwise.
+            loop_mode: if audio.loop_points.is_some() { LoopMode::Continuous } else { LoopMode::NoLoop },
+            loop_start,
+            loop_end,
+            tune_cents: 0.0,
+            gain: 1.0,
+            pan: 0.0,
+        }
+    }
+
+    pub fn contains(&self, note: u8, velocity: u8) -> bool {
+        (self.low_key..=self.high_key).contains(&note)
+            && (self.low_velocity..=self.high_velocity).contains(&velocity)
+    }
+
+    // Keeps the loop inside the file, and turns looping off if there's nothing left of it. The
+    // start never ends up past the end, even for a loop that's entirely beyond the file.
+    fn validate(&mut self, num_frames: usize) {
+        self.loop_end = self.loop_end.min(num_frames - 1);
+        self.loop_start = self.loop_start.min(self.loop_end);
+        if self.loop_start >= self.loop_end && self.loop_mode.loops() {
+            self.loop_mode = LoopMode::NoLoop;
+        }
+    }
+}
+
+pub struct SampleMap {
+    name: String,
+    files: Vec<SampleFile>,
+    zones: Vec<Zone>,
+}
+
+impl SampleMap {
+    // One zone across every key and velocity, with the root note and loop from the file if it
+    // has them.
+    pub fn from_audio_file(name: &str, data: Vec<u8>) -> Result<Self, String> {
+        let audio = audio_file::read(&data)?;
+        let mut zone = Zone::new(0, &audio);
+        zone.validate(audio.num_frames());
+        info!("Loaded sample {:?}: {} frames, root note {}", name, audio.num_frames(), zone.root_note);
+        Ok(Self {
+            name: name.to_string(),
+            files: vec![SampleFile {
+                name: name.to_string(),
+                source: data,
+                audio,
+            }],
+            zones: vec![zone],
+        })
+    }
+
+    // `load` reads a sample file named in the SFZ, which is relative to wherever the SFZ is.
+    pub fn from_sfz<F>(name: &str, text: &str, mut load: F) -> Result<Self, String>
+    where
+        F: FnMut(&str) -> Result<Vec<u8>, String>,
+    {
+        let mut map = Self {
+            name: name.to_string(),
+            files: Vec::new(),
+            zones: Vec::new(),
+        };
+
+        for opcodes in sfz::parse(text)? {
+            let path = match opcodes.get("sample") {
+                Some(path) => path,
+                None => continue,
+            };
+            // Regions often share a sample, so each file is only read once.
+            let file = match map.files.iter().position(|file| &file.name == path) {
+                Some(file) => file,
+                None => {
+                    let data = load(path)?;
+                    let audio = audio_file::read(&data).map_err(|error| format!("{}: {}", path, error))?;
+                    map.files.push(SampleFile {
+                        name: path.clone(),
+                        source: data,
+                        audio,
+                    });
+                    map.files.len() - 1
+                }
+            };
+
+            let mut zone = Zone::new(file, &map.files[file].audio);
+            let note = |name: &str| opcodes.get(name).and_then(|value| sfz::parse_note(value));
+            let number = |name: &str| opcodes.get(name).and_then(|value| value.parse::<f64>().ok());
+            if let Some(key) = note("key") {
+                zone.low_key = key;
+                zone.high_key = key;
+                zone.root_note = key;
+            }
+            zone.low_key = note("lokey").unwrap_or(zone.low_key);
+            zone.high_key = note("hikey").unwrap_or(zone.high_key);
+            zone.root_note = note("pitch_keycenter").unwrap_or(zone.root_note);
+            zone.low_velocity = number("lovel").map_or(zone.low_velocity, |velocity| velocity as u8);
+            zone.high_velocity = number("hivel").map_or(zone.high_velocity, |velocity| velocity as u8);
+            zone.loop_mode = match opcodes.get("loop_mode").map(String::as_str) {
+                Some("no_loop") => LoopMode::NoLoop,
+                Some("one_shot") => LoopMode::OneShot,
+                Some("loop_continuous") => LoopMode::Continuous,
+                Some("loop_sustain") => LoopMode::Sustain,
+                _ => zone.loop_mode,
+            };
+            // Older files spell the loop points without the underscore.
+            if let Some(start) = number("loop_start").or_else(|| number("loopstart")) {
+                zone.loop_start = start as usize;
+            }
+            if let Some(end) = number("loop_end").or_else(|| number("loopend")) {
+                zone.loop_end = end as usize;
+            }
+            zone.tune_cents = number("tune").unwrap_or(0.0) + number("transpose").unwrap_or(0.0) * 100.0;
+            zone.gain = 10f64.powf(number("volume").unwrap_or(0.0) / 20.0)
+                * number("amplitude").unwrap_or(100.0) / 100.0;
+            zone.pan = (number("pan").unwrap_or(0.0) / 100.0).max(-1.0).min(1.0);
+            zone.validate(map.files[file].audio.num_frames());
+            map.zones.push(zone);
+        }
+
+        if map.zones.is_empty() {
+            return Err("no regions with a sample".to_string());
+        }
+        info!("Loaded SFZ {:?}: {} zone(s), {} file(s)", name, map.zones.len(), map.files.len());
+        Ok(map)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    pub fn file(&self, index: usize) -> &SampleFile {
+        &self.files[index]
+    }
+
+    // The files themselves go in the chunk, along with the zones, so a project still has its
+    // samples on a machine that doesn't have the files.
+    pub fn write_chunk(&self, chunk: &mut ChunkWriter) {
+        let mut payload = Vec::new();
+        write_bytes(&mut payload, self.name.as_bytes());
+        payload.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        for file in &self.files {
+            write_bytes(&mut payload, file.name.as_bytes());
+            write_bytes(&mut payload, &file.source);
+        }
+        payload.extend_from_slice(&(self.zones.len() as u32).to_le_bytes());
+        for zone in &self.zones {
+            payload.extend_from_slice(&(zone.file as u32).to_le_bytes());
+            payload.extend_from_slice(&[
+                zone.low_key,
+                zone.high_key,
+                zone.low_velocity,
+                zone.high_velocity,
+                zone.root_note,
+                zone.loop_mode.id(),
+            ]);
+            payload.extend_from_slice(&(zone.loop_start as u32).to_le_bytes());
+            payload.extend_from_slice(&(zone.loop_end as u32).to_le_bytes());
+            payload.extend_from_slice(&(zone.tune_cents as f32).to_le_bytes());
+            payload.extend_from_slice(&(zone.gain as f32).to_le_bytes());
+            payload.extend_from_slice(&(zone.pan as f32).to_le_bytes());
+        }
+        chunk.section(b"SMAP", &payload);
+    }
+
+    pub fn read_chunk(payload: &[u8]) -> Option<Self> {
+        let mut reader = ByteReader::new(payload);
+        let name = String::from_utf8_lossy(&read_bytes(&mut reader)?).into_owned();
+        let mut files = Vec::new();
+        for _ in 0..reader.u32()? {
+            let file_name = String::from_utf8_lossy(&read_bytes(&mut reader)?).into_owned();
+            let source = read_bytes(&mut reader)?;
+            match audio_file::read(&source) {
+                Ok(audio) => files.push(SampleFile {
+                    name: file_name,
+                    source,
+                    audio,
+                }),
+                Err(error) => {
+                    warn!("Ignoring saved sample map {:?}: {}: {}", name, file_name, error);
+                    return None;
+                }
+            }
+        }
+
+        let mut zones = Vec::new();
+        for _ in 0..reader.u32()? {
+            let file = reader.u32()? as usize;
+            let bytes = reader.bytes(6)?;
+            let mut zone = Zone {
+                file,
+                low_key: bytes[0],
+                high_key: bytes[1],
+                low_velocity: bytes[2],
+                high_velocity: bytes[3],
+                root_note: bytes[4],
+                loop_mode: LoopMode::from_id(bytes[5]),
+                loop_start: reader.u32()? as usize,
+                loop_end: reader.u32()? as usize,
+                tune_cents: reader.f32()? as f64,
+                gain: reader.f32()? as f64,
+                pan: reader.f32()? as f64,
+            };
+            if file < files.len() {
+                zone.validate(files[file].audio.num_frames());
+                zones.push(zone);
+            }
+        }
+
+        Some(Self { name, files, zones })
+    }
+}
+
+fn write_bytes(payload: &mut Vec<u8>, bytes: &[u8]) {
+    payload.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    payload.extend_from_slice(bytes);
+}
+
+fn read_bytes(reader: &mut ByteReader) -> Option<Vec<u8>> {
+    let length = reader.u32()? as usize;
+    Some(reader.bytes(length)?.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(loop_mode: LoopMode, loop_start: usize, loop_end: usize) -> Zone {
+        Zone {
+            file: 0,
+            low_key: 0,
+            high_key: 127,
+            low_velocity: 1,
+            high_velocity: 127,
+            root_note: DEFAULT_ROOT_NOTE,
+            loop_mode,
+            loop_start,
+            loop_end,
+            tune_cents: 0.0,
+            gain: 1.0,
+            pan: 0.0,
+        }
+    }
+
+    #[test]
+    fn loop_inside_the_file_is_kept() {
+        let mut zone = zone(LoopMode::Continuous, 100, 500);
+        zone.validate(1000);
+        assert_eq!((zone.loop_start, zone.loop_end), (100, 500));
+        assert_eq!(zone.loop_mode, LoopMode::Continuous);
+    }
+
+    #[test]
+    fn loop_end_past_the_file_is_clamped() {
+        let mut zone = zone(LoopMode::Sustain, 100, 5000);
+        zone.validate(1000);
+        assert_eq!((zone.loop_start, zone.loop_end), (100, 999));
+        assert_eq!(zone.loop_mode, LoopMode::Sustain);
+    }
+
+    #[test]
+    fn loop_entirely_past_the_file_is_dropped() {
+        let mut zone = zone(LoopMode::Continuous, 5000, 6000);
+        zone.validate(1000);
+        assert!(zone.loop_start <= zone.loop_end);
+        assert_eq!(zone.loop_end, 999);
+        assert_eq!(zone.loop_mode, LoopMode::NoLoop);
+    }
+
+    #[test]
+    fn loop_start_past_the_file_is_clamped_when_not_looping() {
+        let mut zone = zone(LoopMode::NoLoop, 5000, 6000);
+        zone.validate(1000);
+        assert!(zone.loop_start <= zone.loop_end);
+    }
+}
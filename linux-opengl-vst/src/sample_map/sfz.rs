@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+// Just enough SFZ to map samples to keys: the <control>, <global>, <group> and <region> headers,
+// with regions inheriting opcodes from their group and the global header. Which opcodes mean
+// anything is up to `SampleMap`; everything is kept here as text.
+
+pub type Opcodes = HashMap<String, String>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Header {
+    Control,
+    Global,
+    Group,
+    Region,
+    // Headers we don't know about: their opcodes are skipped.
+    Other,
+}
+
+// One set of opcodes per region, with the inherited ones filled in and `default_path` put on
+// the front of `sample`.
+pub fn parse(text: &str) -> Result<Vec<Opcodes>, String> {
+    let mut header = Header::Other;
+    let mut control = Opcodes::new();
+    let mut global = Opcodes::new();
+    let mut group = Opcodes::new();
+    let mut regions: Vec<Opcodes> = Vec::new();
+    // The opcode that a token without an "=" belongs to. Sample paths can have spaces in them.
+    let mut last_opcode: Option<String> = None;
+
+    for line in text.lines() {
+        let line = match line.find("//") {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        for token in line.split_whitespace() {
+            if token.starts_with('<') && token.ends_with('>') {
+                header = match token {
+                    "<control>" => Header::Control,
+                    "<global>" => Header::Global,
+                    "<group>" => Header::Group,
+                    "<region>" => Header::Region,
+                    _ => Header::Other,
+                };
+                match header {
+                    Header::Global => {
+                        global.clear();
+                        group.clear();
+                    }
+                    Header::Group => group.clear(),
+                    // Regions start from their group's opcodes, which start from the global
+                    // ones, and override whichever they set themselves.
+                    Header::Region => {
+                        let mut opcodes = global.clone();
+                        opcodes.extend(group.iter().map(|(name, value)| (name.clone(), value.clone())));
+                        regions.push(opcodes);
+                    }
+                    _ => (),
+                }
+                last_opcode = None;
+                continue;
+            }
+
+            let opcodes = match header {
+                Header::Control => &mut control,
+                Header::Global => &mut global,
+                Header::Group => &mut group,
+                Header::Region => regions.last_mut().unwrap(),
+                Header::Other => continue,
+            };
+            match token.find('=') {
+                Some(equals) => {
+                    let name = token[..equals].to_string();
+                    // `key` sets the key range and root at once, so it replaces any of those
+                    // inherited from further out.
+                    if name == "key" {
+                        for inherited in &["lokey", "hikey", "pitch_keycenter"] {
+                            opcodes.remove(*inherited);
+                        }
+                    }
+                    opcodes.insert(name.clone(), token[equals + 1..].to_string());
+                    last_opcode = Some(name);
+                }
+                None => match &last_opcode {
+                    Some(name) => {
+                        let value = opcodes.entry(name.clone()).or_insert_with(String::new);
+                        value.push(' ');
+                        value.push_str(token);
+                    }
+                    None => return Err(format!("unexpected {:?}", token)),
+                },
+            }
+        }
+        // Values never carry on to the next line.
+        last_opcode = None;
+    }
+
+    // Windows-style paths are common, even in files made elsewhere.
+    let default_path = control.get("default_path").cloned().unwrap_or_default();
+    for opcodes in &mut regions {
+        if let Some(sample) = opcodes.get_mut("sample") {
+            *sample = format!("{}{}", default_path, sample).replace('\\', "/");
+        }
+    }
+    if regions.is_empty() {
+        return Err("no regions".to_string());
+    }
+    Ok(regions)
+}
+
+// A MIDI note number, or a note name like "c4", "f#3" or "eb-1" (where c4 is middle C, 60).
+pub fn parse_note(value: &str) -> Option<u8> {
+    if let Ok(note) = value.parse::<i32>() {
+        return if (0..128).contains(&note) { Some(note as u8) } else { None };
+    }
+
+    let value = value.to_ascii_lowercase();
+    let mut chars = value.chars().peekable();
+    let mut note = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    match chars.peek() {
+        Some('#') => {
+            note += 1;
+            chars.next();
+        }
+        Some('b') => {
+            note -= 1;
+            chars.next();
+        }
+        _ => (),
+    }
+    let octave: i32 = chars.collect::<String>().parse().ok()?;
+    let note = (octave + 1) * 12 + note;
+    if (0..128).contains(&note) {
+        Some(note as u8)
+    } else {
+        None
+    }
+}
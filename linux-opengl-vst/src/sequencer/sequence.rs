@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use crate::chunk::{ByteReader, ChunkWriter};
+use crate::parameters::AtomicFloat;
+
+// The steps themselves. These aren't host parameters (there'd be 128 of them), so they live in
+// `UiState` where the grid editor and the audio thread can both get at them, and are saved in
+// their own section of the state chunk.
+
+pub const MAX_STEPS: usize = 32;
+
+// Everything is 0.0 - 1.0, like parameters, so the grid can edit every lane the same way.
+pub struct Step {
+    pub pitch: AtomicFloat,       // see `step_pitch()`
+    pub gate: AtomicFloat,        // Fraction of the step; 0.0 is a rest
+    pub velocity: AtomicFloat,    // see `step_velocity()`
+    pub pulse_width: AtomicFloat, // 0.0 means no lock: the voice uses the pulse width parameter
+}
+
+pub struct Sequence {
+    steps: Vec<Step>,
+    // The step that's playing, for the editor to highlight. -1 when stopped.
+    current_step: AtomicI32,
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        let mut steps = Vec::with_capacity(MAX_STEPS);
+        for _ in 0..MAX_STEPS {
+            steps.push(Step {
+                pitch: AtomicFloat::new(0.5),
+                gate: AtomicFloat::new(0.5),
+                velocity: AtomicFloat::new(0.8),
+                pulse_width: AtomicFloat::new(0.0),
+            });
+        }
+
+        Self {
+            steps,
+            current_step: AtomicI32::new(-1),
+        }
+    }
+
+    pub fn step(&self, index: usize) -> &Step {
+        &self.steps[index % MAX_STEPS]
+    }
+
+    pub fn current_step(&self) -> Option<usize> {
+        match self.current_step.load(Ordering::Relaxed) {
+            -1 => None,
+            step => Some(step as usize),
+        }
+    }
+
+    pub fn set_current_step(&self, step: Option<usize>) {
+        let step = step.map_or(-1, |step| step as i32);
+        self.current_step.store(step, Ordering::Relaxed);
+    }
+
+    pub fn write_chunk(&self, chunk: &mut ChunkWriter) {
+        let mut payload = Vec::new();
+        for step in &self.steps {
+            for lane in &[&step.pitch, &step.gate, &step.velocity, &step.pulse_width] {
+                payload.extend_from_slice(&lane.get().to_le_bytes());
+            }
+        }
+        chunk.section(b"SEQ ", &payload);
+    }
+
+    pub fn read_chunk(&self, payload: &[u8]) {
+        let mut reader = ByteReader::new(payload);
+        for step in &self.steps {
+            for lane in &[&step.pitch, &step.gate, &step.velocity, &step.pulse_width] {
+                match reader.f32() {
+                    Some(value) => lane.set(value.max(0.0).min(1.0)),
+                    None => return,
+                }
+            }
+        }
+    }
+}
+
+// Semitones from the root note, -24 to +24.
+pub fn step_pitch(value: f32) -> i32 {
+    (value * 48.0).round() as i32 - 24
+}
+
+pub fn step_velocity(value: f32) -> u8 {
+    1 + (value * 126.0).round() as u8
+}
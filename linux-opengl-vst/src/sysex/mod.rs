@@ -0,0 +1,290 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::*;
+
+use crate::chunk::{ChunkReader, ChunkWriter};
+use crate::parameters::Parameters;
+use crate::sample_map::SampleMap;
+use crate::tuning::Tuning;
+use crate::ui_state::UiState;
+use crate::wavetable::Wavetable;
+
+// Patch dumps as System Exclusive messages, so hardware librarians can back patches up and send
+// them back. A dump is a state chunk (see `chunk`) with the whole plugin state in it, the same as
+// the host would save: parameters, MIDI learn, the mod matrix, the step sequence and the tuning.
+// The wavetable and sample files can be far bigger than all of that put together, so they only go
+// in when asked for; loading a dump without them leaves the ones in use as they are.
+//
+// Neither end of it happens on the audio thread. The editor builds dumps and hands them over for
+// sending, and incoming ones are copied into a buffer for `DumpLoader` to decode and load.
+//
+// Layout:
+//   F0 7D            start, "non-commercial" manufacturer ID
+//   47 56 4C         "GVL"
+//   01               command: patch dump
+//   ...              the state chunk, 7-bit packed (see `pack()`)
+//   checksum         two's complement of the sum of the packed bytes, 7 bits
+//   F7               end
+
+const START: u8 = 0xF0;
+const END: u8 = 0xF7;
+const MANUFACTURER_ID: u8 = 0x7D;
+const DEVICE_ID: &[u8; 3] = b"GVL";
+const PATCH_DUMP: u8 = 0x01;
+
+const HEADER_LENGTH: usize = 6;
+
+// The biggest incoming dump we'll take. A patch is a few kilobytes, so this leaves room for a
+// wavetable and a few short samples as well.
+pub const MAX_DUMP_LENGTH: usize = 4 * 1024 * 1024;
+// How often the loader looks for a new dump.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// The sections of the state chunk that make up a patch. These all live in `Parameters` and
+// `UiState`, so any thread can read and write them.
+pub fn write_patch(chunk: &mut ChunkWriter, params: &Parameters, ui_state: &UiState) {
+    params.write_chunk(chunk);
+    ui_state.midi_learn.write_chunk(chunk);
+    ui_state.mod_matrix.write_chunk(chunk);
+    ui_state.sequence.write_chunk(chunk);
+}
+
+// Returns false for sections that aren't part of a patch.
+pub fn read_patch_section(tag: &[u8; 4], payload: &[u8], params: &Parameters, ui_state: &UiState) -> bool {
+    match tag {
+        b"PARM" => params.read_chunk(payload),
+        b"MLRN" => ui_state.midi_learn.read_chunk(payload),
+        b"MODM" => ui_state.mod_matrix.read_chunk(payload),
+        b"SEQ " => ui_state.sequence.read_chunk(payload),
+        _ => return false,
+    }
+    true
+}
+
+// A dump of the current state, ready to send.
+pub fn patch_dump(params: &Parameters, ui_state: &UiState, with_wavetable_and_samples: bool) -> Vec<u8> {
+    let mut chunk = ChunkWriter::new();
+    write_patch(&mut chunk, params, ui_state);
+    ui_state.tuning().write_chunk(&mut chunk);
+    if with_wavetable_and_samples {
+        if let Some(wavetable) = ui_state.wavetable() {
+            wavetable.write_chunk(&mut chunk);
+        }
+        if let Some(map) = ui_state.sample_map() {
+            map.write_chunk(&mut chunk);
+        }
+    }
+
+    let message = encode_patch_dump(&chunk.finish());
+    if message.len() > MAX_DUMP_LENGTH {
+        warn!("This SysEx dump ({} bytes) is too big to load back in", message.len());
+    }
+    message
+}
+
+// Just the header, which is cheap enough to check on the audio thread.
+pub fn is_patch_dump(message: &[u8]) -> bool {
+    message.len() > HEADER_LENGTH
+        && message[0] == START
+        && message[1] == MANUFACTURER_ID
+        && &message[2..5] == DEVICE_ID
+        && message[5] == PATCH_DUMP
+}
+
+fn encode_patch_dump(state: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LENGTH + state.len() * 8 / 7 + 3);
+    message.push(START);
+    message.push(MANUFACTURER_ID);
+    message.extend_from_slice(DEVICE_ID);
+    message.push(PATCH_DUMP);
+    pack(state, &mut message);
+    message.push(checksum(&message[HEADER_LENGTH..]));
+    message.push(END);
+    message
+}
+
+// Returns the state chunk, or None if this isn't one of our patch dumps (or it got mangled on the
+// way).
+fn decode_patch_dump(message: &[u8]) -> Option<Vec<u8>> {
+    // Some hosts leave the end byte off.
+    let message = match message.last() {
+        Some(&END) => &message[..message.len() - 1],
+        _ => message,
+    };
+    if !is_patch_dump(message) {
+        return None;
+    }
+
+    let (packed, checksum_byte) = message[HEADER_LENGTH..].split_at(message.len() - HEADER_LENGTH - 1);
+    if checksum(packed) != checksum_byte[0] || packed.iter().any(|&byte| byte > 0x7F) {
+        return None;
+    }
+    Some(unpack(packed))
+}
+
+// SysEx data bytes only have 7 bits, so every 7 bytes go out as 8: first a byte holding their top
+// bits (bit 0 for the first byte, and so on), then the bytes with the top bit cleared.
+fn pack(data: &[u8], out: &mut Vec<u8>) {
+    for group in data.chunks(7) {
+        let top_bits = group
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (index, byte)| bits | ((byte >> 7) << index));
+        out.push(top_bits);
+        out.extend(group.iter().map(|byte| byte & 0x7F));
+    }
+}
+
+fn unpack(packed: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(packed.len() * 7 / 8);
+    for group in packed.chunks(8) {
+        let top_bits = group[0];
+        for (index, byte) in group[1..].iter().enumerate() {
+            data.push(byte | (((top_bits >> index) & 1) << 7));
+        }
+    }
+    data
+}
+
+fn checksum(packed: &[u8]) -> u8 {
+    let sum = packed.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    sum.wrapping_neg() & 0x7F
+}
+
+fn load_patch_dump(message: &[u8], params: &Parameters, ui_state: &UiState) {
+    let state = match decode_patch_dump(message) {
+        Some(state) => state,
+        None => {
+            warn!("Ignoring a SysEx patch dump that got mangled on the way");
+            return;
+        }
+    };
+    let chunk = match ChunkReader::new(&state) {
+        Some(chunk) => chunk,
+        None => {
+            warn!("Ignoring a SysEx patch dump with a state chunk we don't recognize");
+            return;
+        }
+    };
+
+    info!("Loading a SysEx patch dump ({} bytes)", message.len());
+    // No matrix section means no modulation and no tuning section means 12-TET, like in a saved
+    // project. No wavetable or sample map section just means they weren't sent.
+    let mut tuning = Tuning::new();
+    ui_state.mod_matrix.clear();
+    for (tag, payload) in chunk {
+        if read_patch_section(&tag, payload, params, ui_state) {
+            continue;
+        }
+        match &tag {
+            b"TUNE" => tuning = Tuning::read_chunk(payload),
+            b"WTBL" => {
+                if let Some(wavetable) = Wavetable::read_chunk(payload) {
+                    ui_state.request_wavetable_change(wavetable);
+                }
+            }
+            b"SMAP" => {
+                if let Some(map) = SampleMap::read_chunk(payload) {
+                    ui_state.request_sample_map_change(map);
+                }
+            }
+            _ => info!("Skipping {:?} in a SysEx patch dump", tag),
+        }
+    }
+    ui_state.request_tuning(tuning);
+}
+
+// A thread that loads the dumps the audio thread receives, for as long as the plugin is around.
+pub struct DumpLoader {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DumpLoader {
+    pub fn new(params: Arc<Parameters>, ui_state: Arc<UiState>) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let still_running = running.clone();
+        let thread = thread::spawn(move || {
+            while still_running.load(Ordering::Relaxed) {
+                if let Some(message) = ui_state.take_received_sysex_dump() {
+                    load_patch_dump(&message, &params, &ui_state);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+        Self {
+            running,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for DumpLoader {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tuning::{Scale, TuningChange};
+
+    fn state() -> Vec<u8> {
+        (0..=255).chain(0..20).collect()
+    }
+
+    #[test]
+    fn dump_round_trips() {
+        let message = encode_patch_dump(&state());
+        assert_eq!(message.first(), Some(&START));
+        assert_eq!(message.last(), Some(&END));
+        assert!(message[1..message.len() - 1].iter().all(|byte| byte & 0x80 == 0));
+        assert_eq!(decode_patch_dump(&message), Some(state()));
+    }
+
+    #[test]
+    fn missing_end_byte_is_accepted() {
+        let message = encode_patch_dump(&state());
+        assert_eq!(decode_patch_dump(&message[..message.len() - 1]), Some(state()));
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let mut message = encode_patch_dump(&state());
+        message[HEADER_LENGTH + 3] ^= 0x01;
+        assert_eq!(decode_patch_dump(&message), None);
+    }
+
+    #[test]
+    fn dump_carries_the_tuning() {
+        let params = Parameters::new();
+        let ui_state = UiState::new();
+        let scale = "Pythagorean pentatonic\n5\n9/8\n81/64\n3/2\n27/16\n2/1\n";
+        ui_state.request_tuning_change(TuningChange::Scale(Scale::parse(scale).unwrap()));
+        let message = patch_dump(&params, &ui_state, false);
+
+        let loaded = UiState::new();
+        load_patch_dump(&message, &params, &loaded);
+        let tuning = loaded.take_tuning_change().unwrap();
+        for note in 0..128 {
+            assert_eq!(tuning.frequency(note), ui_state.tuning().frequency(note));
+        }
+        assert_ne!(tuning.frequency(61), Tuning::new().frequency(61));
+    }
+
+    #[test]
+    fn other_sysex_is_rejected() {
+        let mut message = encode_patch_dump(&state());
+        message[1] = 0x41;
+        assert!(!is_patch_dump(&message));
+        assert_eq!(decode_patch_dump(&message), None);
+        assert!(!is_patch_dump(&[START, END]));
+    }
+}
@@ -0,0 +1,203 @@
+use super::scale::Scale;
+
+// A Scala keyboard mapping (.kbm file), which says which MIDI key plays which scale degree and
+// what the scale is tuned to. See http://www.huygens-fokker.org/scala/help.htm#mappings
+//
+// Every line that isn't a "!" comment, in order:
+//   map size (0 means every key plays the next scale degree)
+//   first and last MIDI note to play at all
+//   middle note, where scale degree 0 goes
+//   reference note, and its frequency
+//   the scale degree to treat as the "formal octave" that the mapping repeats at
+//   then one line per key in the map: a scale degree, or "x" for a key that doesn't play
+
+// Scala's default: degree 0 on middle C, tuned the same as 12-TET at A = 440 Hz.
+const DEFAULT_MIDDLE_NOTE: i32 = 60;
+const DEFAULT_REFERENCE_FREQUENCY: f64 = 261.625_565_300_598_6;
+
+#[derive(Clone)]
+pub struct KeyboardMapping {
+    first_note: i32,
+    last_note: i32,
+    middle_note: i32,
+    reference_note: i32,
+    reference_frequency: f64,
+    octave_degree: i32, // 0 means the scale's own period
+    map: Vec<Option<i32>>, // Empty means linear
+    // The file this came from, so it can be saved with the plugin state. None for the default.
+    source: Option<String>,
+}
+
+impl KeyboardMapping {
+    pub fn linear() -> Self {
+        Self {
+            first_note: 0,
+            last_note: 127,
+            middle_note: DEFAULT_MIDDLE_NOTE,
+            reference_note: DEFAULT_MIDDLE_NOTE,
+            reference_frequency: DEFAULT_REFERENCE_FREQUENCY,
+            octave_degree: 0,
+            map: Vec::new(),
+            source: None,
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut tokens = source
+            .lines()
+            .filter(|line| !line.starts_with('!'))
+            .filter_map(|line| line.split_whitespace().next());
+        let mut next = |name: &str| tokens.next().ok_or_else(|| format!("missing {}", name));
+
+        let map_size = parse_int(next("map size")?)?;
+        let first_note = parse_int(next("first note")?)?;
+        let last_note = parse_int(next("last note")?)?;
+        let middle_note = parse_int(next("middle note")?)?;
+        let reference_note = parse_int(next("reference note")?)?;
+        let reference_frequency = next("reference frequency")?;
+        let reference_frequency = reference_frequency
+            .parse::<f64>()
+            .map_err(|_| format!("bad reference frequency {:?}", reference_frequency))?;
+        let octave_degree = parse_int(next("formal octave degree")?)?;
+
+        if map_size < 0 || octave_degree < 0 {
+            return Err(format!("negative map size or octave degree"));
+        }
+        if !reference_frequency.is_finite() || reference_frequency <= 0.0 {
+            return Err(format!("reference frequency must be positive"));
+        }
+
+        // Scala lets the map stop early; the keys it leaves out don't play.
+        let mut map = Vec::with_capacity(map_size as usize);
+        for _ in 0..map_size {
+            map.push(match tokens.next() {
+                None | Some("x") | Some("X") => None,
+                Some(token) => Some(parse_int(token)?),
+            });
+        }
+
+        Ok(Self {
+            first_note,
+            last_note,
+            middle_note,
+            reference_note,
+            reference_frequency,
+            octave_degree,
+            map,
+            source: Some(source.to_string()),
+        })
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_ref().map(|source| source.as_str())
+    }
+
+    // None for keys that aren't mapped to anything.
+    pub fn frequency(&self, note: u8, scale: &Scale) -> Option<f64> {
+        let note = note as i32;
+        if note < self.first_note || note > self.last_note {
+            return None;
+        }
+
+        // The reference note sets the tuning even if it isn't mapped to a key itself.
+        let reference = self
+            .cents(self.reference_note, scale)
+            .unwrap_or_else(|| scale.cents(self.reference_note - self.middle_note));
+        let cents = self.cents(note, scale)?;
+        Some(self.reference_frequency * ((cents - reference) / 1200.0).exp2())
+    }
+
+    // Cents above degree 0 (the middle note).
+    fn cents(&self, note: i32, scale: &Scale) -> Option<f64> {
+        let offset = note - self.middle_note;
+        if self.map.is_empty() {
+            return Some(scale.cents(offset));
+        }
+
+        let map_size = self.map.len() as i32;
+        let degree = self.map[offset.rem_euclid(map_size) as usize]?;
+        let octave_degree = match self.octave_degree {
+            0 => scale.size(),
+            degree => degree,
+        };
+        Some(scale.cents(degree) + offset.div_euclid(map_size) as f64 * scale.cents(octave_degree))
+    }
+}
+
+fn parse_int(token: &str) -> Result<i32, String> {
+    token.parse::<i32>().map_err(|_| format!("bad number {:?}", token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn linear_mapping_is_standard_tuning() {
+        let mapping = KeyboardMapping::linear();
+        let scale = Scale::equal_temperament();
+        assert_close(mapping.frequency(69, &scale), 440.0);
+        assert_close(mapping.frequency(60, &scale), DEFAULT_REFERENCE_FREQUENCY);
+        assert_close(mapping.frequency(81, &scale), 880.0);
+    }
+
+    #[test]
+    fn reference_note_and_frequency() {
+        // Linear, with A4 at 432 Hz.
+        let source = "! 432.kbm\n0\n0\n127\n60\n69\n432.0\n0\n";
+        let mapping = KeyboardMapping::parse(source).unwrap();
+        let scale = Scale::equal_temperament();
+        assert_close(mapping.frequency(69, &scale), 432.0);
+        assert_close(mapping.frequency(57, &scale), 216.0);
+        assert_close(mapping.frequency(72, &scale), 432.0 * (3.0f64 / 12.0).exp2());
+    }
+
+    #[test]
+    fn unmapped_keys_dont_play() {
+        // White keys only: the black keys are "x", and the 7 white keys per octave play a
+        // 7-note scale.
+        let source = "12\n0\n127\n60\n60\n261.625565\n7\n0\nx\n1\nx\n2\n3\nx\n4\nx\n5\nx\n6\n";
+        let mapping = KeyboardMapping::parse(source).unwrap();
+        let scale = Scale::parse("Major\n7\n9/8\n5/4\n4/3\n3/2\n5/3\n15/8\n2/1\n").unwrap();
+        assert_close(mapping.frequency(60, &scale), 261.625565);
+        assert_eq!(mapping.frequency(61, &scale), None);
+        assert_close(mapping.frequency(62, &scale), 261.625565 * 9.0 / 8.0);
+        assert_close(mapping.frequency(71, &scale), 261.625565 * 15.0 / 8.0);
+        assert_close(mapping.frequency(72, &scale), 261.625565 * 2.0);
+        assert_eq!(mapping.frequency(49, &scale), None);
+    }
+
+    #[test]
+    fn keys_outside_the_range_dont_play() {
+        let mapping = KeyboardMapping::parse("0\n48\n72\n60\n69\n440.0\n0\n").unwrap();
+        let scale = Scale::equal_temperament();
+        assert_eq!(mapping.frequency(47, &scale), None);
+        assert_close(mapping.frequency(48, &scale), 110.0 * (3.0f64 / 12.0).exp2());
+        assert_eq!(mapping.frequency(73, &scale), None);
+    }
+
+    #[test]
+    fn short_map_leaves_the_rest_unmapped() {
+        let mapping = KeyboardMapping::parse("3\n0\n127\n60\n60\n261.625565\n0\n0\n").unwrap();
+        let scale = Scale::equal_temperament();
+        assert_close(mapping.frequency(60, &scale), 261.625565);
+        assert_eq!(mapping.frequency(61, &scale), None);
+        assert_eq!(mapping.frequency(62, &scale), None);
+    }
+
+    #[test]
+    fn malformed_mappings_are_rejected() {
+        assert!(KeyboardMapping::parse("").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n127\n60\n69\n").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n127\n60\n69\nfast\n0\n").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n127\n60\n69\n0.0\n0\n").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n127\n60\n69\n-440.0\n0\n").is_err());
+        assert!(KeyboardMapping::parse("-1\n0\n127\n60\n69\n440.0\n0\n").is_err());
+        assert!(KeyboardMapping::parse("1\n0\n127\n60\n69\n440.0\n0\nq\n").is_err());
+    }
+}
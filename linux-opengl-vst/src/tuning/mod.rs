@@ -0,0 +1,206 @@
+use log::*;
+
+mod keyboard_mapping;
+mod scale;
+pub use self::keyboard_mapping::KeyboardMapping;
+pub use self::scale::Scale;
+
+use crate::chunk::{ByteReader, ChunkWriter};
+
+// Which frequency each MIDI note plays. Defaults to 12-TET with A4 = 440 Hz, and can be changed
+// by loading Scala .scl/.kbm files. The reference pitch parameter is applied on top of this by
+// the audio engine.
+
+// A change the editor makes to the tuning.
+pub enum TuningChange {
+    Scale(Scale),
+    KeyboardMapping(KeyboardMapping),
+    // Back to 12-TET, with the default mapping.
+    Reset,
+}
+
+#[derive(Clone)]
+pub struct Tuning {
+    scale: Scale,
+    keyboard_mapping: KeyboardMapping,
+    frequencies: [Option<f64>; 128], // None for keys that don't play
+}
+
+impl Tuning {
+    pub fn new() -> Self {
+        let mut tuning = Self {
+            scale: Scale::equal_temperament(),
+            keyboard_mapping: KeyboardMapping::linear(),
+            frequencies: [None; 128],
+        };
+        tuning.update_frequencies();
+        tuning
+    }
+
+    pub fn frequency(&self, note: u8) -> Option<f64> {
+        self.frequencies[note as usize & 0x7F]
+    }
+
+    pub fn apply(&mut self, change: TuningChange) {
+        match change {
+            TuningChange::Scale(scale) => {
+                info!("Tuning: scale \"{}\" ({} notes)", scale.description(), scale.size());
+                self.scale = scale;
+            }
+            TuningChange::KeyboardMapping(keyboard_mapping) => {
+                info!("Tuning: new keyboard mapping");
+                self.keyboard_mapping = keyboard_mapping;
+            }
+            TuningChange::Reset => {
+                info!("Tuning: back to 12-TET");
+                self.scale = Scale::equal_temperament();
+                self.keyboard_mapping = KeyboardMapping::linear();
+            }
+        }
+        self.update_frequencies();
+    }
+
+    fn update_frequencies(&mut self) {
+        if self.scale.source().is_none() && self.keyboard_mapping.source().is_none() {
+            for note in 0..128 {
+                self.frequencies[note] = Some(midi_pitch_to_freq(note as u8));
+            }
+            return;
+        }
+
+        for note in 0..128 {
+            self.frequencies[note] = self.keyboard_mapping.frequency(note as u8, &self.scale);
+        }
+    }
+
+    // The files themselves go in the chunk, so a project still sounds right on a machine that
+    // doesn't have them. Nothing is written for 12-TET.
+    pub fn write_chunk(&self, chunk: &mut ChunkWriter) {
+        let scale = self.scale.source().unwrap_or("");
+        let keyboard_mapping = self.keyboard_mapping.source().unwrap_or("");
+        if scale.is_empty() && keyboard_mapping.is_empty() {
+            return;
+        }
+
+        let mut payload = Vec::new();
+        for text in &[scale, keyboard_mapping] {
+            payload.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            payload.extend_from_slice(text.as_bytes());
+        }
+        chunk.section(b"TUNE", &payload);
+    }
+
+    // An empty file means the default for that half.
+    pub fn read_chunk(payload: &[u8]) -> Self {
+        let mut tuning = Self::new();
+        let mut reader = ByteReader::new(payload);
+        let mut next_text = || -> Option<String> {
+            let length = reader.u32()? as usize;
+            Some(String::from_utf8_lossy(reader.bytes(length)?).into_owned())
+        };
+
+        let scale = next_text().unwrap_or_default();
+        let keyboard_mapping = next_text().unwrap_or_default();
+
+        if !scale.is_empty() {
+            match Scale::parse(&scale) {
+                Ok(scale) => tuning.scale = scale,
+                Err(error) => warn!("Ignoring saved scale: {}", error),
+            }
+        }
+        if !keyboard_mapping.is_empty() {
+            match KeyboardMapping::parse(&keyboard_mapping) {
+                Ok(keyboard_mapping) => tuning.keyboard_mapping = keyboard_mapping,
+                Err(error) => warn!("Ignoring saved keyboard mapping: {}", error),
+            }
+        }
+
+        tuning.update_frequencies();
+        tuning
+    }
+}
+
+// The reference pitch parameter: what A4 would be in 12-TET. Everything is scaled by this over
+// 440 Hz, whatever tuning is loaded.
+pub fn reference_pitch_hz(value: f32) -> f64 {
+    const LOWEST: f64 = 400.0;
+    const HIGHEST: f64 = 480.0;
+
+    LOWEST + value as f64 * (HIGHEST - LOWEST)
+}
+
+fn midi_pitch_to_freq(pitch: u8) -> f64 {
+    const A4_PITCH: i8 = 69;
+    const A4_FREQ: f64 = 440.0;
+
+    (((pitch as i8 - A4_PITCH) as f64) / 12.).exp2() * A4_FREQ
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::ChunkReader;
+
+    const SCALE: &str = "Pythagorean pentatonic\n5\n9/8\n81/64\n3/2\n27/16\n2/1\n";
+    const KEYBOARD_MAPPING: &str = "0\n0\n127\n60\n69\n432.0\n0\n";
+
+    fn saved(tuning: &Tuning) -> Vec<u8> {
+        let mut chunk = ChunkWriter::new();
+        tuning.write_chunk(&mut chunk);
+        chunk.finish()
+    }
+
+    fn tune_section(data: &[u8]) -> Option<Vec<u8>> {
+        ChunkReader::new(data)?
+            .find(|(tag, _)| tag == b"TUNE")
+            .map(|(_, payload)| payload.to_vec())
+    }
+
+    #[test]
+    fn defaults_to_twelve_tone_equal_temperament() {
+        let tuning = Tuning::new();
+        assert_eq!(tuning.frequency(69), Some(440.0));
+        assert_eq!(tuning.frequency(57), Some(220.0));
+        assert!((tuning.frequency(60).unwrap() - 261.625_565_300_598_6).abs() < 1e-9);
+        assert_eq!(tune_section(&saved(&tuning)), None);
+    }
+
+    #[test]
+    fn loaded_files_round_trip_through_the_chunk() {
+        let mut tuning = Tuning::new();
+        tuning.apply(TuningChange::Scale(Scale::parse(SCALE).unwrap()));
+        tuning.apply(TuningChange::KeyboardMapping(KeyboardMapping::parse(KEYBOARD_MAPPING).unwrap()));
+        assert_eq!(tuning.frequency(69), Some(432.0));
+
+        let loaded = Tuning::read_chunk(&tune_section(&saved(&tuning)).unwrap());
+        for note in 0..128 {
+            assert_eq!(loaded.frequency(note), tuning.frequency(note));
+        }
+    }
+
+    #[test]
+    fn reset_goes_back_to_the_default() {
+        let mut tuning = Tuning::new();
+        tuning.apply(TuningChange::Scale(Scale::parse(SCALE).unwrap()));
+        tuning.apply(TuningChange::Reset);
+        for note in 0..128 {
+            assert_eq!(tuning.frequency(note), Tuning::new().frequency(note));
+        }
+    }
+
+    #[test]
+    fn broken_saved_files_fall_back_to_the_default() {
+        let mut payload = Vec::new();
+        for text in &["Not a scale\n", KEYBOARD_MAPPING] {
+            payload.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            payload.extend_from_slice(text.as_bytes());
+        }
+        let tuning = Tuning::read_chunk(&payload);
+        assert_eq!(tuning.frequency(69), Some(432.0));
+        assert_eq!(tuning.frequency(57), Some(216.0));
+
+        // Cut off partway through.
+        let tuning = Tuning::read_chunk(&payload[..6]);
+        assert_eq!(tuning.frequency(69), Some(440.0));
+    }
+}
@@ -0,0 +1,96 @@
+use std::f64::consts::PI;
+
+// A plain in-place radix-2 FFT, just enough for building the mip-maps. `real.len()` has to be a
+// power of two. The inverse isn't scaled, so a round trip comes back `len` times bigger.
+pub fn fft(real: &mut [f64], imaginary: &mut [f64], inverse: bool) {
+    let size = real.len();
+    debug_assert!(size.is_power_of_two() && imaginary.len() == size);
+
+    // Bit-reversed order, so the butterflies can work in place.
+    let mut j = 0;
+    for i in 1..size {
+        let mut bit = size >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut length = 2;
+    while length <= size {
+        let angle = sign * 2.0 * PI / length as f64;
+        for start in (0..size).step_by(length) {
+            for k in 0..length / 2 {
+                let (twiddle_imaginary, twiddle_real) = (angle * k as f64).sin_cos();
+                let even = start + k;
+                let odd = even + length / 2;
+                let odd_real = real[odd] * twiddle_real - imaginary[odd] * twiddle_imaginary;
+                let odd_imaginary = real[odd] * twiddle_imaginary + imaginary[odd] * twiddle_real;
+                real[odd] = real[even] - odd_real;
+                imaginary[odd] = imaginary[even] - odd_imaginary;
+                real[even] += odd_real;
+                imaginary[even] += odd_imaginary;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(size: usize) -> (Vec<f64>, Vec<f64>) {
+        let real = (0..size).map(|i| ((i * 7 + 3) % 11) as f64 - 5.0).collect();
+        let imaginary = (0..size).map(|i| ((i * 5 + 1) % 13) as f64 * 0.25).collect();
+        (real, imaginary)
+    }
+
+    #[test]
+    fn round_trip_comes_back_scaled_by_the_size() {
+        for &size in &[1, 2, 8, 64, 2048] {
+            let (original_real, original_imaginary) = signal(size);
+            let (mut real, mut imaginary) = (original_real.clone(), original_imaginary.clone());
+            fft(&mut real, &mut imaginary, false);
+            fft(&mut real, &mut imaginary, true);
+            for i in 0..size {
+                assert!((real[i] / size as f64 - original_real[i]).abs() < 1e-9);
+                assert!((imaginary[i] / size as f64 - original_imaginary[i]).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn sine_lands_in_its_bin() {
+        let size = 64;
+        let mut real: Vec<f64> = (0..size).map(|i| (2.0 * PI * 5.0 * i as f64 / size as f64).sin()).collect();
+        let mut imaginary = vec![0.0; size];
+        fft(&mut real, &mut imaginary, false);
+        for bin in 0..size {
+            // A sine is -i/2 in its bin and +i/2 in the mirrored one, each scaled by the size.
+            let expected = match bin {
+                5 => -(size as f64) / 2.0,
+                59 => size as f64 / 2.0,
+                _ => 0.0,
+            };
+            assert!(real[bin].abs() < 1e-9, "bin {}: {}", bin, real[bin]);
+            assert!((imaginary[bin] - expected).abs() < 1e-9, "bin {}: {}", bin, imaginary[bin]);
+        }
+    }
+
+    #[test]
+    fn impulse_is_flat() {
+        let mut real = vec![0.0; 16];
+        let mut imaginary = vec![0.0; 16];
+        real[0] = 1.0;
+        fft(&mut real, &mut imaginary, false);
+        assert!(real.iter().all(|&value| (value - 1.0).abs() < 1e-12));
+        assert!(imaginary.iter().all(|&value| value.abs() < 1e-12));
+    }
+}
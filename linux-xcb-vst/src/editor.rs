@@ -9,6 +9,7 @@ use std::sync::Mutex;
 
 use crate::x_handle::XHandle;
 use crate::parameters::Parameters;
+use crate::ui_state::UiState;
 
 pub struct Editor {
     is_open: bool,
@@ -20,11 +21,12 @@ pub struct Editor {
     window_handle: u32,
     draw_context: u32,
     parameters: Arc<Parameters>,
+    ui_state: Arc<UiState>,
     host_callback: Arc<Mutex<HostCallback>>,
 }
 
 impl Editor {
-    pub fn new(x_handle: Box<XHandle>, parameters: Arc<Parameters>, host_callback: Arc<Mutex<HostCallback>>) -> Self {
+    pub fn new(x_handle: Box<XHandle>, parameters: Arc<Parameters>, ui_state: Arc<UiState>, host_callback: Arc<Mutex<HostCallback>>) -> Self {
         info!("GuiVstEditor::new()");
 
         Self {
@@ -37,6 +39,7 @@ impl Editor {
             window_handle: 0,
             draw_context: 0,
             parameters,
+            ui_state,
             host_callback,
         }
     }
@@ -93,9 +96,10 @@ impl Editor {
 
         // Start handling events on this connection.
         let arc_parameters = self.parameters.clone();
+        let arc_ui_state = self.ui_state.clone();
         let arc_host_callback = self.host_callback.clone();
         thread::spawn(move || {
-            Editor::handle_events(conn, arc_parameters, arc_host_callback);
+            Editor::handle_events(conn, arc_parameters, arc_ui_state, arc_host_callback);
         });
     }
 
@@ -136,6 +140,8 @@ impl Editor {
         let rectangle_borders = vec!(
             xcb::Rectangle::new(50, 300, 900, 100),
             xcb::Rectangle::new(50, 600, 900, 100),
+            // Panic button
+            xcb::Rectangle::new(50, 850, 100, 100),
         );
         let rectangle_values = vec!(
             xcb::Rectangle::new(50, 300, (self.parameters.param1.get() * 900.0) as u16, 100),
@@ -158,7 +164,7 @@ impl Editor {
         conn.flush();
     }
 
-    fn handle_events(conn: Arc<xcb::Connection>, parameters: Arc<Parameters>, host_callback: Arc<Mutex<HostCallback>>) {
+    fn handle_events(conn: Arc<xcb::Connection>, parameters: Arc<Parameters>, ui_state: Arc<UiState>, host_callback: Arc<Mutex<HostCallback>>) {
         let mut active_element = ActiveElement::None;
         loop {
             let wait = conn.wait_for_event();
@@ -184,6 +190,11 @@ impl Editor {
                                 active_element = ActiveElement::Param2;
                                 host_callback.lock().unwrap().automate(1, parameters.param2.get());
                             }
+                            else if event.event_y() >= 850 && event.event_y() <= 950 && event.event_x() >= 50 && event.event_x() <= 150 {
+                                info!("Panic!");
+                                ui_state.request_panic();
+                                active_element = ActiveElement::None;
+                            }
                             else {
                                 active_element = ActiveElement::None;
                             }
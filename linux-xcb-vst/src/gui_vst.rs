@@ -17,17 +17,28 @@ use std::sync::Mutex;
 
 use crate::x_handle::XHandle;
 use crate::editor::Editor;
+use crate::note_stack::{NotePriority, NoteStack};
 use crate::parameters::Parameters;
 use crate::square_oscillator::SquareOscillator;
+use crate::ui_state::UiState;
+
+// How long the output takes to fade out when the host bypasses us, and back in again after.
+const BYPASS_FADE_SECONDS: f64 = 0.005;
 
 pub struct GuiVst {
     host: HostCallback,
     editor: Editor,
     parameters: Arc<Parameters>,
+    ui_state: Arc<UiState>,
     square_oscillator: SquareOscillator,
     sample_rate: f64,
     note_duration: f64,
     note: Option<u8>,
+    // Every key that's down, oldest first. `note` is whichever one of these wins.
+    note_stack: NoteStack,
+    // Soft bypass only fades the output; notes keep being tracked underneath.
+    bypassed: bool,
+    output_gain: f64, // 0.0 - 1.0
 }
 
 fn midi_pitch_to_freq(pitch: u8) -> f64 {
@@ -41,21 +52,60 @@ impl GuiVst {
     fn process_midi_event(&mut self, data: [u8; 3]) {
         match data[0] {
             128 => self.note_off(data[1]),
+            // A note on with zero velocity is a note off.
+            144 if data[2] == 0 => self.note_off(data[1]),
             144 => self.note_on(data[1]),
+            176 => self.control_change(data[1]),
+            _ => ()
+        }
+    }
+
+    fn control_change(&mut self, controller: u8) {
+        match controller {
+            // All sound off: silence right away.
+            120 => {
+                self.note_stack.clear();
+                self.note = None;
+            },
+            // Reset all controllers: none of them do anything here, so there's nothing to put back.
+            121 => (),
+            // All notes off: release whatever is playing, same as letting go of every key.
+            123 => {
+                self.note_stack.clear();
+                self.update_note();
+            },
             _ => ()
         }
     }
 
     fn note_on(&mut self, note: u8) {
-        self.note_duration = 0.0;
-        self.note = Some(note);
-        self.square_oscillator.change_frequency(midi_pitch_to_freq(note));
+        self.note_stack.push(note);
+        self.update_note();
     }
 
     fn note_off(&mut self, note: u8) {
-        if self.note == Some(note) {
-            self.note = None;
+        self.note_stack.remove(note);
+        self.update_note();
+    }
+
+    // Work out which of the held keys should be sounding, and switch to it if it changed.
+    fn update_note(&mut self) {
+        let priority = NotePriority::from_parameter(self.parameters.note_priority.get());
+        let next_note = self.note_stack.pick(priority);
+        if next_note == self.note {
+            return;
         }
+
+        if let Some(note) = next_note {
+            // Legato just changes the pitch; retrigger starts the note over.
+            let retrigger = self.parameters.retrigger.get() >= 0.5;
+            if self.note.is_none() || retrigger {
+                self.note_duration = 0.0;
+                self.square_oscillator.reset();
+            }
+            self.square_oscillator.change_frequency(midi_pitch_to_freq(note));
+        }
+        self.note = next_note;
     }
 }
 
@@ -84,18 +134,23 @@ impl Plugin for GuiVst {
         let x_handle = Box::new(XHandle::new());
         let parameters = Arc::new(Parameters::new());
         let host_callback = Arc::new(Mutex::new(host));
+        let ui_state = Arc::new(UiState::new());
 
         // Set up an Editor that uses this connection.
         let editor_parameters = parameters.clone();
         let square_oscillator_parameters = parameters.clone();
         Self {
             host,
-            editor: Editor::new(x_handle, editor_parameters.clone(), host_callback),
+            editor: Editor::new(x_handle, editor_parameters.clone(), ui_state.clone(), host_callback),
             parameters,
+            ui_state,
             square_oscillator: SquareOscillator::new(square_oscillator_parameters.clone()),
             sample_rate: 44000.0,
             note_duration: 0.0,
             note: None,
+            note_stack: NoteStack::new(),
+            bypassed: false,
+            output_gain: 1.0,
         }
     }
 
@@ -108,7 +163,7 @@ impl Plugin for GuiVst {
             inputs: 0,
             midi_inputs: 1,
             outputs: 2,
-            parameters: 2,
+            parameters: 4,
             initial_delay: 0,
             ..Info::default()
         }
@@ -132,6 +187,8 @@ impl Plugin for GuiVst {
         match index {
             0 => self.parameters.param1.get(),
             1 => self.parameters.param2.get(),
+            2 => self.parameters.note_priority.get(),
+            3 => self.parameters.retrigger.get(),
             _ => 0.0,
         }
     }
@@ -140,6 +197,12 @@ impl Plugin for GuiVst {
         match index {
             0 => format!("{:.1}%", self.parameters.param1.get() * 100.0),
             1 => format!("{:.1}%", self.parameters.param2.get() * 100.0),
+            2 => match NotePriority::from_parameter(self.parameters.note_priority.get()) {
+                NotePriority::Last => "Last",
+                NotePriority::Low => "Low",
+                NotePriority::High => "High",
+            }.to_string(),
+            3 => if self.parameters.retrigger.get() >= 0.5 { "Retrigger" } else { "Legato" }.to_string(),
             _ => "".to_string(),
         }
     }
@@ -148,6 +211,8 @@ impl Plugin for GuiVst {
         match index {
             0 => "Parameter 1",
             1 => "Parameter 2",
+            2 => "Note priority",
+            3 => "Retrigger",
             _ => "",
         }.to_string()
     }
@@ -161,6 +226,12 @@ impl Plugin for GuiVst {
             1 => {
                 self.parameters.param2.set(val);
             },
+            2 => {
+                self.parameters.note_priority.set(val);
+            },
+            3 => {
+                self.parameters.retrigger.set(val);
+            },
             _ => (),
         }
     }
@@ -177,12 +248,29 @@ impl Plugin for GuiVst {
 
     fn can_do(&self, can_do: CanDo) -> Supported {
         match can_do {
-            CanDo::ReceiveMidiEvent => Supported::Yes,
+            CanDo::ReceiveMidiEvent | CanDo::Bypass => Supported::Yes,
             _ => Supported::Maybe
         }
     }
 
+    // Soft bypass: the host keeps calling `process`, and the output fades out. Hosts don't always
+    // pass MIDI on to a bypassed plugin, so any key still held when it comes back is let go rather
+    // than left hanging.
+    fn set_bypass(&mut self, bypass: bool) {
+        info!("set_bypass({})", bypass);
+        if self.bypassed && !bypass {
+            self.note_stack.clear();
+            self.update_note();
+        }
+        self.bypassed = bypass;
+    }
+
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        if self.ui_state.take_panic_request() {
+            self.note_stack.clear();
+            self.note = None;
+        }
+
         let output_channels = buffer.output_count();
         let num_samples = buffer.samples();
         let (_, output_buffer) = buffer.split();
@@ -203,6 +291,16 @@ impl Plugin for GuiVst {
                 }
             }
 
+        let step = 1.0 / (BYPASS_FADE_SECONDS * self.sample_rate);
+        for sample in samples.iter_mut() {
+            self.output_gain = if self.bypassed {
+                (self.output_gain - step).max(0.0)
+            } else {
+                (self.output_gain + step).min(1.0)
+            };
+            *sample *= self.output_gain;
+        }
+
         // Write the output to each channel.
         for channel in 0..output_channels {
             let output_channel = output_buffer.get_mut(channel);
@@ -6,7 +6,9 @@ extern crate xcb;
 mod x_handle;
 mod editor;
 mod atomic_float;
+mod note_stack;
 mod parameters;
+mod ui_state;
 mod square_oscillator;
 mod gui_vst;
 
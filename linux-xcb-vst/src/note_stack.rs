@@ -0,0 +1,55 @@
+// Keeps track of which keys are held down, in the order they were pressed, so a monophonic voice
+// can fall back to an older note when the newest one is released.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotePriority {
+    Last,
+    Low,
+    High,
+}
+
+impl NotePriority {
+    pub fn from_parameter(value: f32) -> Self {
+        match (value * 2.0).round() as i32 {
+            0 => NotePriority::Last,
+            1 => NotePriority::Low,
+            _ => NotePriority::High,
+        }
+    }
+}
+
+pub struct NoteStack {
+    notes: Vec<u8>, // Oldest first
+}
+
+impl NoteStack {
+    pub fn new() -> Self {
+        Self {
+            // Room for every MIDI note, so pushing never allocates on the audio thread.
+            notes: Vec::with_capacity(128),
+        }
+    }
+
+    pub fn push(&mut self, note: u8) {
+        // A key that's pressed again (without a note off in between) counts as the newest one.
+        self.remove(note);
+        self.notes.push(note);
+    }
+
+    pub fn remove(&mut self, note: u8) {
+        self.notes.retain(|&held| held != note);
+    }
+
+    pub fn clear(&mut self) {
+        self.notes.clear();
+    }
+
+    // The note a monophonic voice should be playing right now, if any.
+    pub fn pick(&self, priority: NotePriority) -> Option<u8> {
+        match priority {
+            NotePriority::Last => self.notes.last().cloned(),
+            NotePriority::Low => self.notes.iter().min().cloned(),
+            NotePriority::High => self.notes.iter().max().cloned(),
+        }
+    }
+}
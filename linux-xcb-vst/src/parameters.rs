@@ -3,6 +3,8 @@ use crate::atomic_float::AtomicFloat;
 pub struct Parameters {
     pub param1: AtomicFloat,
     pub param2: AtomicFloat,
+    pub note_priority: AtomicFloat,
+    pub retrigger: AtomicFloat,
 }
 
 impl Parameters {
@@ -10,6 +12,8 @@ impl Parameters {
         Self {
             param1: AtomicFloat::new(1.0),
             param2: AtomicFloat::new(0.5),
+            note_priority: AtomicFloat::new(0.0),
+            retrigger: AtomicFloat::new(1.0),
         }
     }
 }
\ No newline at end of file
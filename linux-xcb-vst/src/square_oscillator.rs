@@ -22,6 +22,10 @@ impl SquareOscillator {
         self.frequency = frequency;
     }
 
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
     pub fn next_sample(&mut self, sample_rate: f64) -> f64 {
         let mut output = 1.0;
 
@@ -0,0 +1,24 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Things the editor and the audio thread need to tell each other that aren't host-automatable
+// parameters (those live in `Parameters`).
+pub struct UiState {
+    panic_requested: AtomicBool,
+}
+
+impl UiState {
+    pub fn new() -> Self {
+        Self {
+            panic_requested: AtomicBool::new(false),
+        }
+    }
+
+    pub fn request_panic(&self) {
+        self.panic_requested.store(true, Ordering::Relaxed);
+    }
+
+    // Returns true once per request, so the audio thread only handles each panic once.
+    pub fn take_panic_request(&self) -> bool {
+        self.panic_requested.swap(false, Ordering::Relaxed)
+    }
+}
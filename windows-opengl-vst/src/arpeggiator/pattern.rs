@@ -0,0 +1,103 @@
+use crate::parameters::choice_index;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeldNote {
+    pub channel: u8,
+    pub note: u8,
+    pub velocity: u8,
+}
+
+impl HeldNote {
+    pub fn is(&self, channel: u8, note: u8) -> bool {
+        self.channel == channel && self.note == note
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArpMode {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+impl ArpMode {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 5) {
+            0 => ArpMode::Up,
+            1 => ArpMode::Down,
+            2 => ArpMode::UpDown,
+            3 => ArpMode::Random,
+            _ => ArpMode::AsPlayed,
+        }
+    }
+}
+
+// Fills `pattern` with one cycle of the arpeggio: the held notes in the mode's order, repeated
+// up each octave. Random mode picks from the "up" pattern at each step. `held` is in the order
+// the keys were pressed.
+pub fn build(held: &[HeldNote], mode: ArpMode, octaves: usize, pattern: &mut Vec<HeldNote>) {
+    pattern.clear();
+    for octave in 0..octaves {
+        for held_note in held {
+            let note = held_note.note as usize + octave * 12;
+            if note <= 127 {
+                pattern.push(HeldNote {
+                    note: note as u8,
+                    ..*held_note
+                });
+            }
+        }
+    }
+
+    match mode {
+        ArpMode::AsPlayed => {
+            // Keep the pressed order within each octave, which is how it was built.
+        }
+        ArpMode::Up | ArpMode::Random => pattern.sort_unstable_by_key(|held_note| held_note.note),
+        ArpMode::Down => pattern.sort_unstable_by(|a, b| b.note.cmp(&a.note)),
+        ArpMode::UpDown => {
+            pattern.sort_unstable_by_key(|held_note| held_note.note);
+            // Back down again, without playing the top and bottom notes twice in a row.
+            let len = pattern.len();
+            if len > 2 {
+                for index in (1..len - 1).rev() {
+                    let held_note = pattern[index];
+                    pattern.push(held_note);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(notes: &[u8]) -> Vec<HeldNote> {
+        notes
+            .iter()
+            .map(|&note| HeldNote { channel: 0, note, velocity: 100 })
+            .collect()
+    }
+
+    fn notes(held: &[u8], mode: ArpMode, octaves: usize) -> Vec<u8> {
+        let mut pattern = Vec::new();
+        build(&self::held(held), mode, octaves, &mut pattern);
+        pattern.iter().map(|held_note| held_note.note).collect()
+    }
+
+    #[test]
+    fn modes_order_the_pattern() {
+        assert_eq!(notes(&[64, 60, 67], ArpMode::Up, 1), vec![60, 64, 67]);
+        assert_eq!(notes(&[64, 60, 67], ArpMode::Down, 1), vec![67, 64, 60]);
+        assert_eq!(notes(&[64, 60, 67], ArpMode::UpDown, 1), vec![60, 64, 67, 64]);
+        assert_eq!(notes(&[64, 60, 67], ArpMode::AsPlayed, 2), vec![64, 60, 67, 76, 72, 79]);
+    }
+
+    #[test]
+    fn octaves_stop_at_the_top_of_the_keyboard() {
+        assert_eq!(notes(&[60, 110], ArpMode::Up, 3), vec![60, 72, 84, 110, 122]);
+    }
+}
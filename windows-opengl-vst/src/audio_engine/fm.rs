@@ -0,0 +1,273 @@
+use std::f64::consts::PI;
+
+use super::filter::envelope_time_seconds;
+use super::voice::{Lanes, VOICE_LANES};
+use crate::parameters::{choice_index, Parameters};
+
+// Four sine operators, wired up by one of a few fixed algorithms. Like the DX synths this is
+// really phase modulation: modulators push the phase of the operators they feed around, rather
+// than their frequency.
+//
+// Every algorithm only ever has higher-numbered operators modulating lower-numbered ones, so the
+// operators can be run from 4 down to 1 in a single pass. Operator 4 can also modulate itself.
+
+pub const NUM_OPERATORS: usize = 4;
+// Modulation index, in radians, at full level.
+const MAX_INDEX: f64 = 8.0;
+const MAX_FEEDBACK: f64 = 1.5;
+
+// Operator frequencies relative to the note. Mostly harmonic, with a few in-between ones for bells
+// and other clangy things.
+const RATIOS: [f64; 19] = [
+    0.5, 0.71, 1.0, 1.41, 2.0, 2.82, 3.0, 3.5, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0, 14.0, 16.0,
+];
+
+struct Algorithm {
+    // For each operator, a bit per operator that modulates it (bit 0 for operator 1, and so on).
+    modulators: [u8; NUM_OPERATORS],
+    // The operators that are heard, one bit each.
+    carriers: u8,
+}
+
+// In the order of the algorithm parameter's choices.
+const ALGORITHMS: [Algorithm; 8] = [
+    // 4 > 3 > 2 > 1
+    Algorithm { modulators: [0b0010, 0b0100, 0b1000, 0], carriers: 0b0001 },
+    // (3 + 4) > 2 > 1
+    Algorithm { modulators: [0b0010, 0b1100, 0, 0], carriers: 0b0001 },
+    // ((4 > 3) + 2) > 1
+    Algorithm { modulators: [0b0110, 0, 0b1000, 0], carriers: 0b0001 },
+    // 4 > 3, 2 > 1
+    Algorithm { modulators: [0b0010, 0, 0b1000, 0], carriers: 0b0101 },
+    // 4 > (1 + 2 + 3)
+    Algorithm { modulators: [0b1000, 0b1000, 0b1000, 0], carriers: 0b0111 },
+    // 4 > 3, 2, 1
+    Algorithm { modulators: [0, 0, 0b1000, 0], carriers: 0b0111 },
+    // 1 + 2 + 3 + 4
+    Algorithm { modulators: [0, 0, 0, 0], carriers: 0b1111 },
+    // 2 > 1, with 3 and 4 off
+    Algorithm { modulators: [0b0010, 0, 0, 0], carriers: 0b0001 },
+];
+pub const ALGORITHM_NAMES: [&str; 8] = [
+    "4>3>2>1", "(3+4)>2>1", "(4>3+2)>1", "4>3, 2>1", "4>(1+2+3)", "4>3, 2, 1", "1+2+3+4", "2>1",
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct OperatorSettings {
+    pub ratio: f64,
+    // Modulation index for a modulator, output level for a carrier. 0.0 - 1.0.
+    pub level: f64,
+    // Attack, decay, sustain, release, like `Envelope::set_adsr()`.
+    pub envelope: (f64, f64, f64, f64),
+}
+
+// Everything the operators need from the parameters, read once a block.
+#[derive(Clone, Copy, Debug)]
+pub struct FmSettings {
+    algorithm: usize,
+    pub operators: [OperatorSettings; NUM_OPERATORS],
+    feedback: f64,
+}
+
+impl FmSettings {
+    pub fn from_parameters(params: &Parameters) -> Self {
+        let operator = |ratio: f32, level: f32, attack: f32, decay: f32, sustain: f32, release: f32| {
+            OperatorSettings {
+                ratio: fm_ratio(ratio),
+                level: level as f64,
+                envelope: (
+                    envelope_time_seconds(attack),
+                    envelope_time_seconds(decay),
+                    sustain as f64,
+                    envelope_time_seconds(release),
+                ),
+            }
+        };
+
+        Self {
+            algorithm: choice_index(params.fm_algorithm.get(), ALGORITHMS.len()),
+            operators: [
+                operator(
+                    params.op1_ratio.get(),
+                    params.op1_index.get(),
+                    params.op1_attack.get(),
+                    params.op1_decay.get(),
+                    params.op1_sustain.get(),
+                    params.op1_release.get(),
+                ),
+                operator(
+                    params.op2_ratio.get(),
+                    params.op2_index.get(),
+                    params.op2_attack.get(),
+                    params.op2_decay.get(),
+                    params.op2_sustain.get(),
+                    params.op2_release.get(),
+                ),
+                operator(
+                    params.op3_ratio.get(),
+                    params.op3_index.get(),
+                    params.op3_attack.get(),
+                    params.op3_decay.get(),
+                    params.op3_sustain.get(),
+                    params.op3_release.get(),
+                ),
+                operator(
+                    params.op4_ratio.get(),
+                    params.op4_index.get(),
+                    params.op4_attack.get(),
+                    params.op4_decay.get(),
+                    params.op4_sustain.get(),
+                    params.op4_release.get(),
+                ),
+            ],
+            feedback: params.fm_feedback.get() as f64,
+        }
+    }
+
+    pub fn is_carrier(&self, operator: usize) -> bool {
+        ALGORITHMS[self.algorithm].carriers & (1 << operator) != 0
+    }
+
+    // How long the voice has to keep going after the key is let go, for the carriers' releases.
+    pub fn longest_release(&self) -> f64 {
+        (0..NUM_OPERATORS)
+            .filter(|&operator| self.is_carrier(operator))
+            .map(|operator| self.operators[operator].envelope.3)
+            .fold(0.0, f64::max)
+    }
+}
+
+// One unison copy of a group's operators, with a lane per voice.
+pub struct FmOperators {
+    phases: [Lanes; NUM_OPERATORS],
+    // Operator 4's last two outputs. Feeding back their average keeps it from buzzing at high
+    // feedback.
+    feedback: [Lanes; 2],
+}
+
+impl FmOperators {
+    pub fn new() -> Self {
+        Self {
+            phases: [[0.0; VOICE_LANES]; NUM_OPERATORS],
+            feedback: [[0.0; VOICE_LANES]; 2],
+        }
+    }
+
+    pub fn reset(&mut self, lane: usize) {
+        for phases in &mut self.phases {
+            phases[lane] = 0.0;
+        }
+        for feedback in &mut self.feedback {
+            feedback[lane] = 0.0;
+        }
+    }
+
+    // `levels` is each operator's level with its envelope (and any modulation) applied, per lane.
+    pub fn next_sample(
+        &mut self,
+        settings: &FmSettings,
+        levels: &[Lanes; NUM_OPERATORS],
+        frequencies: &Lanes,
+        sample_rate: f32,
+    ) -> Lanes {
+        let algorithm = &ALGORITHMS[settings.algorithm];
+        let mut outputs = [[0.0; VOICE_LANES]; NUM_OPERATORS];
+        for operator in (0..NUM_OPERATORS).rev() {
+            let mut modulation = [0.0; VOICE_LANES];
+            for source in 0..NUM_OPERATORS {
+                if algorithm.modulators[operator] & (1 << source) != 0 {
+                    let sources = outputs[source].iter().zip(&levels[source]);
+                    for (lane, (output, level)) in modulation.iter_mut().zip(sources) {
+                        *lane += output * level * MAX_INDEX;
+                    }
+                }
+            }
+            if operator == NUM_OPERATORS - 1 {
+                let [previous, before] = &self.feedback;
+                for (lane, modulation) in modulation.iter_mut().enumerate() {
+                    *modulation += (previous[lane] + before[lane]) / 2.0 * settings.feedback * MAX_FEEDBACK;
+                }
+            }
+
+            let ratio = settings.operators[operator].ratio;
+            let phases = self.phases[operator].iter_mut().zip(&modulation).zip(frequencies);
+            for (output, ((phase, modulation), frequency)) in outputs[operator].iter_mut().zip(phases) {
+                *output = (2.0 * PI * *phase + modulation).sin();
+                *phase = (*phase + frequency * ratio / sample_rate as f64).fract();
+            }
+        }
+        self.feedback = [outputs[NUM_OPERATORS - 1], self.feedback[0]];
+
+        // Divided between the carriers, so switching algorithms doesn't jump in volume.
+        let carriers = algorithm.carriers.count_ones() as f64;
+        let mut output = [0.0; VOICE_LANES];
+        for (operator, values) in outputs.iter().enumerate() {
+            if settings.is_carrier(operator) {
+                for (lane, (value, level)) in output.iter_mut().zip(values.iter().zip(&levels[operator])) {
+                    *lane += value * level;
+                }
+            }
+        }
+        for lane in &mut output {
+            *lane /= carriers;
+        }
+        output
+    }
+}
+
+pub fn fm_ratio(value: f32) -> f64 {
+    RATIOS[choice_index(value, RATIOS.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 1000.0;
+    const FREQUENCY: f64 = 100.0;
+
+    fn settings(algorithm: usize, feedback: f64) -> FmSettings {
+        let operator = OperatorSettings {
+            ratio: 1.0,
+            level: 1.0,
+            envelope: (0.0, 0.0, 1.0, 0.0),
+        };
+        FmSettings {
+            algorithm,
+            operators: [operator; NUM_OPERATORS],
+            feedback,
+        }
+    }
+
+    fn render(settings: &FmSettings, levels: [f64; NUM_OPERATORS], samples: usize) -> Vec<f64> {
+        let mut levels_lanes = [[0.0; VOICE_LANES]; NUM_OPERATORS];
+        for (lanes, level) in levels_lanes.iter_mut().zip(&levels) {
+            *lanes = [*level; VOICE_LANES];
+        }
+        let mut operators = FmOperators::new();
+        (0..samples)
+            .map(|_| operators.next_sample(settings, &levels_lanes, &[FREQUENCY; VOICE_LANES], SAMPLE_RATE)[0])
+            .collect()
+    }
+
+    #[test]
+    fn two_into_one_only_hears_operator_2_through_operator_1() {
+        let algorithm = ALGORITHM_NAMES.iter().position(|&name| name == "2>1").unwrap();
+        let settings = settings(algorithm, 1.0);
+        assert!(settings.is_carrier(0));
+        assert!((1..NUM_OPERATORS).all(|operator| !settings.is_carrier(operator)));
+
+        let output = render(&settings, [0.8, 0.5, 1.0, 1.0], 20);
+        for (index, &sample) in output.iter().enumerate() {
+            // Every operator is at the note's frequency, so they're all at the same phase.
+            let phase = 2.0 * PI * (index as f64 * FREQUENCY / SAMPLE_RATE as f64).fract();
+            let modulator = phase.sin();
+            let expected = (phase + modulator * 0.5 * MAX_INDEX).sin() * 0.8;
+            assert!((sample - expected).abs() < 1e-9, "sample {}: {} {}", index, sample, expected);
+        }
+
+        // Operators 3 and 4 (and 4's feedback) aren't wired to anything.
+        let silent = render(&settings, [0.8, 0.5, 0.0, 0.0], 20);
+        assert_eq!(output, silent);
+    }
+}
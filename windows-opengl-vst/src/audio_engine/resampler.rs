@@ -0,0 +1,70 @@
+use std::f64::consts::PI;
+
+// Windowed sinc interpolation, for playing samples back at any pitch. Going faster than the
+// sample's own rate lowers the cutoff to match (with more taps to keep the same steepness), so
+// pitching up doesn't alias.
+
+// Taps either side of the read position at the sample's own rate or slower.
+const HALF_TAPS: usize = 8;
+// Pitching up further than this still works, but the cutoff stops following, to cap the cost.
+const MAX_STEP: f64 = 4.0;
+// Table entries per unit of distance.
+const RESOLUTION: usize = 256;
+
+pub struct Resampler {
+    // sinc(x) for x from 0 to HALF_TAPS.
+    sinc: Vec<f64>,
+    // Half a Blackman window, from the middle (1.0) to the edge (0.0).
+    window: Vec<f64>,
+}
+
+impl Resampler {
+    pub fn new() -> Self {
+        let sinc = (0..=HALF_TAPS * RESOLUTION + 1)
+            .map(|i| {
+                let x = PI * i as f64 / RESOLUTION as f64;
+                if i == 0 { 1.0 } else { x.sin() / x }
+            })
+            .collect();
+        let window = (0..=RESOLUTION + 1)
+            .map(|i| {
+                let t = (i as f64 / RESOLUTION as f64).min(1.0);
+                0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos()
+            })
+            .collect();
+        Self { sinc, window }
+    }
+
+    // `position` is in the sample's frames, and `step` is how many of them go by per output
+    // sample. `frame(i)` returns frame `i` as (left, right), and should return silence for frames
+    // outside the sample.
+    pub fn interpolate<F>(&self, position: f64, step: f64, frame: F) -> (f64, f64)
+    where
+        F: Fn(isize) -> (f32, f32),
+    {
+        let cutoff = 1.0 / step.max(1.0).min(MAX_STEP);
+        let half_width = HALF_TAPS as f64 / cutoff;
+        let first = (position - half_width).floor() as isize + 1;
+        let last = (position + half_width).floor() as isize;
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for i in first..=last {
+            let distance = (i as f64 - position).abs();
+            let weight =
+                cutoff * lookup(&self.sinc, distance * cutoff) * lookup(&self.window, distance / half_width);
+            let (frame_left, frame_right) = frame(i);
+            left += weight * frame_left as f64;
+            right += weight * frame_right as f64;
+        }
+        (left, right)
+    }
+}
+
+fn lookup(table: &[f64], x: f64) -> f64 {
+    let index = x * RESOLUTION as f64;
+    let i = index as usize;
+    if i + 1 >= table.len() {
+        return 0.0;
+    }
+    table[i] + (table[i + 1] - table[i]) * index.fract()
+}
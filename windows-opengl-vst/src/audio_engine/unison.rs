@@ -0,0 +1,258 @@
+use std::f64::consts::FRAC_PI_4;
+
+use super::fm::{FmOperators, FmSettings, NUM_OPERATORS};
+use super::oscillator_stack::{OscillatorStack, StackSettings};
+use super::voice::{Lanes, VOICE_LANES};
+use crate::parameters::choice_index;
+use crate::wavetable::Wavetable;
+
+// A stack of detuned copies of the oscillators, spread across the stereo field. The copies are
+// spaced evenly from the most detuned down to the most detuned up, and from one side to the other
+// in the same order, so the width parameter pulls them in towards the voice's own pan position.
+//
+// Each copy plays every voice in a group at once, a lane per voice.
+
+pub const MAX_UNISON: usize = 8;
+// How far apart the outermost copies are at full detune.
+const MAX_DETUNE_CENTS: f64 = 100.0;
+
+// Where the copies sit, which only changes with the parameters, so it's worked out once a block.
+#[derive(Clone, Copy, Debug)]
+pub struct UnisonSettings {
+    count: usize,
+    // -1.0 for the first copy to 1.0 for the last.
+    spreads: [f64; MAX_UNISON],
+    width: f64, // 0.0 - 1.0
+}
+
+impl UnisonSettings {
+    pub fn new(count: usize, width: f64) -> Self {
+        let count = count.max(1).min(MAX_UNISON);
+        let mut spreads = [0.0; MAX_UNISON];
+        for (index, spread) in spreads.iter_mut().enumerate().take(count) {
+            *spread = if count == 1 {
+                0.0
+            } else {
+                index as f64 / (count - 1) as f64 * 2.0 - 1.0
+            };
+        }
+        Self { count, spreads, width }
+    }
+}
+
+// What each voice lane gives the copies for one sample. The pulse width and wavetable position are
+// only used by the oscillators.
+#[derive(Clone, Copy, Debug)]
+pub struct UnisonInputs {
+    pub frequency: Lanes,
+    pub detune: Lanes, // 0.0 - 1.0
+    pub pan: Lanes,    // -1.0 (left) - 1.0 (right)
+    pub pulse_width: Lanes,
+    pub wavetable_position: Lanes, // 0.0 - 1.0
+}
+
+pub struct Unison {
+    oscillators: Vec<OscillatorStack>,
+    fm_copies: Vec<FmOperators>,
+}
+
+impl Unison {
+    pub fn new() -> Self {
+        let mut oscillators = Vec::with_capacity(MAX_UNISON);
+        let mut fm_copies = Vec::with_capacity(MAX_UNISON);
+        for _ in 0..MAX_UNISON {
+            oscillators.push(OscillatorStack::new());
+            fm_copies.push(FmOperators::new());
+        }
+        Self { oscillators, fm_copies }
+    }
+
+    pub fn reset(&mut self, lane: usize) {
+        for copy in &mut self.oscillators {
+            copy.reset(lane);
+        }
+        for copy in &mut self.fm_copies {
+            copy.reset(lane);
+        }
+    }
+
+    // Returns (left, right) for every lane.
+    pub fn next_oscillator_sample(
+        &mut self,
+        settings: &UnisonSettings,
+        inputs: &UnisonInputs,
+        oscillators: &StackSettings,
+        wavetable: Option<&Wavetable>,
+        sample_rate: f32,
+    ) -> (Lanes, Lanes) {
+        let mut mix = Mix::new();
+        for (copy, stack) in self.oscillators.iter_mut().enumerate().take(settings.count) {
+            let samples = stack.next_sample(
+                oscillators,
+                wavetable,
+                &inputs.wavetable_position,
+                &copy_frequencies(settings, inputs, copy),
+                &inputs.pulse_width,
+                sample_rate,
+            );
+            mix.add(settings, inputs, copy, &samples);
+        }
+        mix.finish(settings)
+    }
+
+    // `levels` is each operator's level with its envelope included. The envelopes belong to the
+    // voice, so every copy shares them.
+    pub fn next_fm_sample(
+        &mut self,
+        settings: &UnisonSettings,
+        inputs: &UnisonInputs,
+        fm: &FmSettings,
+        levels: &[Lanes; NUM_OPERATORS],
+        sample_rate: f32,
+    ) -> (Lanes, Lanes) {
+        let mut mix = Mix::new();
+        for (copy, operators) in self.fm_copies.iter_mut().enumerate().take(settings.count) {
+            let samples = operators.next_sample(fm, levels, &copy_frequencies(settings, inputs, copy), sample_rate);
+            mix.add(settings, inputs, copy, &samples);
+        }
+        mix.finish(settings)
+    }
+}
+
+fn copy_frequencies(settings: &UnisonSettings, inputs: &UnisonInputs, copy: usize) -> Lanes {
+    let spread = settings.spreads[copy];
+    // The middle copy (or the only one) isn't detuned at all.
+    if spread == 0.0 {
+        return inputs.frequency;
+    }
+    let mut frequencies = [0.0; VOICE_LANES];
+    let lanes = frequencies.iter_mut().zip(&inputs.frequency).zip(&inputs.detune);
+    for ((lane, frequency), detune) in lanes {
+        let cents = spread * detune.max(0.0).min(1.0) * MAX_DETUNE_CENTS / 2.0;
+        *lane = frequency * (cents / 1200.0).exp2();
+    }
+    frequencies
+}
+
+// The copies panned and added up, in copy order.
+struct Mix {
+    left: Lanes,
+    right: Lanes,
+}
+
+impl Mix {
+    fn new() -> Self {
+        Self {
+            left: [0.0; VOICE_LANES],
+            right: [0.0; VOICE_LANES],
+        }
+    }
+
+    fn add(&mut self, settings: &UnisonSettings, inputs: &UnisonInputs, copy: usize, samples: &Lanes) {
+        let offset = settings.spreads[copy] * settings.width;
+        for (lane, sample) in samples.iter().enumerate() {
+            let (left_gain, right_gain) = pan_gains(inputs.pan[lane] + offset);
+            self.left[lane] += sample * left_gain;
+            self.right[lane] += sample * right_gain;
+        }
+    }
+
+    fn finish(mut self, settings: &UnisonSettings) -> (Lanes, Lanes) {
+        // Keep the loudness about the same however many copies there are.
+        let gain = 1.0 / (settings.count as f64).sqrt();
+        for (left, right) in self.left.iter_mut().zip(self.right.iter_mut()) {
+            *left *= gain;
+            *right *= gain;
+        }
+        (self.left, self.right)
+    }
+}
+
+// Constant power, scaled so the center is full level in both channels (like the mono output
+// used to be).
+pub fn pan_gains(pan: f64) -> (f64, f64) {
+    let angle = (pan.max(-1.0).min(1.0) + 1.0) * FRAC_PI_4;
+    (angle.cos() * 2f64.sqrt(), angle.sin() * 2f64.sqrt())
+}
+
+pub fn unison_count(value: f32) -> usize {
+    choice_index(value, MAX_UNISON) + 1
+}
+
+pub fn unison_detune_cents(value: f32) -> f64 {
+    value as f64 * MAX_DETUNE_CENTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unison_inputs(detune: f64, pan: f64) -> UnisonInputs {
+        UnisonInputs {
+            frequency: [440.0; VOICE_LANES],
+            detune: [detune; VOICE_LANES],
+            pan: [pan; VOICE_LANES],
+            pulse_width: [0.5; VOICE_LANES],
+            wavetable_position: [0.0; VOICE_LANES],
+        }
+    }
+
+    // Adds up one copy playing full scale in every lane.
+    fn pan_copy(settings: &UnisonSettings, inputs: &UnisonInputs, copy: usize) -> (f64, f64) {
+        let mut mix = Mix::new();
+        mix.add(settings, inputs, copy, &[1.0; VOICE_LANES]);
+        (mix.left[0], mix.right[0])
+    }
+
+    #[test]
+    fn count_is_kept_in_range() {
+        assert_eq!(UnisonSettings::new(0, 1.0).count, 1);
+        assert_eq!(UnisonSettings::new(MAX_UNISON + 3, 1.0).count, MAX_UNISON);
+    }
+
+    #[test]
+    fn a_single_copy_isnt_detuned_or_spread() {
+        let settings = UnisonSettings::new(1, 1.0);
+        let inputs = unison_inputs(1.0, 0.0);
+        assert_eq!(copy_frequencies(&settings, &inputs, 0), inputs.frequency);
+        let (left, right) = pan_copy(&settings, &inputs, 0);
+        assert!((left - right).abs() < 1e-12);
+    }
+
+    #[test]
+    fn the_most_copies_spread_to_the_full_detune_and_width() {
+        let settings = UnisonSettings::new(MAX_UNISON, 1.0);
+        let spreads = settings.spreads;
+        assert_eq!((spreads[0], spreads[MAX_UNISON - 1]), (-1.0, 1.0));
+        for pair in spreads.windows(2) {
+            assert!((pair[1] - pair[0] - 2.0 / (MAX_UNISON - 1) as f64).abs() < 1e-12);
+        }
+
+        // The outermost copies are half the full detune either side of the note. Detune past
+        // the end is held there.
+        let inputs = unison_inputs(1.0, 0.0);
+        let half = MAX_DETUNE_CENTS / 2.0 / 1200.0;
+        let lowest = copy_frequencies(&settings, &inputs, 0)[0];
+        let highest = copy_frequencies(&settings, &inputs, MAX_UNISON - 1)[0];
+        assert!((lowest - 440.0 * (-half).exp2()).abs() < 1e-9);
+        assert!((highest - 440.0 * half.exp2()).abs() < 1e-9);
+        let too_far = unison_inputs(2.0, 0.0);
+        assert_eq!(copy_frequencies(&settings, &too_far, 0), copy_frequencies(&settings, &inputs, 0));
+
+        // And hard left and right.
+        let (left, right) = pan_copy(&settings, &inputs, 0);
+        assert!(left > 1.4 && right.abs() < 1e-12);
+        let (left, right) = pan_copy(&settings, &inputs, MAX_UNISON - 1);
+        assert!(left.abs() < 1e-12 && right > 1.4);
+    }
+
+    #[test]
+    fn no_width_keeps_every_copy_at_the_voices_pan() {
+        let settings = UnisonSettings::new(MAX_UNISON, 0.0);
+        let inputs = unison_inputs(1.0, -0.5);
+        let expected = pan_gains(-0.5);
+        for copy in 0..MAX_UNISON {
+            assert_eq!(pan_copy(&settings, &inputs, copy), expected);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use super::{decode_samples, AudioFile, SampleFormat};
+use crate::chunk::ByteReader;
+
+// Integer PCM (8, 16, 24 or 32 bit) and float (32 or 64 bit) WAV, including the extensible
+// variety.
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+struct Format {
+    sample_format: SampleFormat,
+    channels: usize,
+    sample_rate: u32,
+}
+
+pub fn parse(data: &[u8]) -> Result<AudioFile, String> {
+    let mut reader = ByteReader::new(data);
+    if reader.bytes(4) != Some(&b"RIFF"[..]) {
+        return Err("not a RIFF file".to_string());
+    }
+    let _riff_length = reader.u32();
+    if reader.bytes(4) != Some(&b"WAVE"[..]) {
+        return Err("not a WAVE file".to_string());
+    }
+
+    let mut format = None;
+    let mut sample_data = None;
+    let mut root_note = None;
+    let mut loop_points = None;
+    let mut frame_size = None;
+    while let Some(tag) = reader.bytes(4) {
+        let length = reader.u32().ok_or("truncated chunk header")? as usize;
+        let payload = match reader.bytes(length) {
+            Some(payload) => payload,
+            // Whatever was cut off, we might already have what we need.
+            None => break,
+        };
+        // Chunks are padded to an even length.
+        if length % 2 == 1 {
+            reader.bytes(1);
+        }
+
+        match tag {
+            b"fmt " => format = Some(parse_format(payload)?),
+            b"data" => sample_data = Some(payload),
+            b"smpl" => {
+                let (root, points) = parse_sampler(payload);
+                root_note = root;
+                loop_points = points;
+            }
+            b"clm " => frame_size = parse_frame_size(payload),
+            _ => (),
+        }
+    }
+
+    let format = format.ok_or("no fmt chunk")?;
+    let sample_data = sample_data.ok_or("no data chunk")?;
+    let unsigned_8_bit = true;
+    Ok(AudioFile {
+        channels: decode_samples(sample_data, format.sample_format, format.channels, false, unsigned_8_bit)?,
+        sample_rate: format.sample_rate as f64,
+        root_note,
+        loop_points,
+        frame_size,
+    })
+}
+
+fn parse_format(payload: &[u8]) -> Result<Format, String> {
+    let mut reader = ByteReader::new(payload);
+    let truncated = || "truncated fmt chunk".to_string();
+
+    let mut code = reader.u16().ok_or_else(truncated)?;
+    let channels = reader.u16().ok_or_else(truncated)? as usize;
+    let sample_rate = reader.u32().ok_or_else(truncated)?;
+    let _byte_rate = reader.u32();
+    let _block_align = reader.u16();
+    let bits = reader.u16().ok_or_else(truncated)? as usize;
+    if code == FORMAT_EXTENSIBLE {
+        // The real format is the start of the sub-format GUID, after the extension's size, valid
+        // bits and channel mask.
+        let _extension_size = reader.u16();
+        let _valid_bits = reader.u16();
+        let _channel_mask = reader.u32();
+        code = reader.u16().ok_or_else(truncated)?;
+    }
+
+    if channels == 0 {
+        return Err("no channels".to_string());
+    }
+    let sample_format = match code {
+        FORMAT_PCM => SampleFormat::Int(bits),
+        FORMAT_FLOAT => SampleFormat::Float(bits),
+        _ => return Err(format!("unsupported sample format {}", code)),
+    };
+    if !sample_format.is_supported() {
+        return Err(format!("unsupported sample format {} ({} bits)", code, bits));
+    }
+    Ok(Format {
+        sample_format,
+        channels,
+        sample_rate,
+    })
+}
+
+// The root note, and the first loop if there is one. The loop's end is inclusive.
+fn parse_sampler(payload: &[u8]) -> (Option<u8>, Option<(usize, usize)>) {
+    let mut reader = ByteReader::new(payload);
+    // Manufacturer, product, sample period.
+    reader.bytes(12);
+    let root_note = reader.u32().filter(|&note| note < 128).map(|note| note as u8);
+    // Pitch fraction, SMPTE format and offset.
+    reader.bytes(12);
+    let num_loops = reader.u32().unwrap_or(0);
+    let _sampler_data = reader.u32();
+
+    let mut loop_points = None;
+    if num_loops > 0 {
+        let _cue_point = reader.u32();
+        let _loop_type = reader.u32();
+        if let (Some(start), Some(end)) = (reader.u32(), reader.u32()) {
+            if end > start {
+                loop_points = Some((start as usize, end as usize));
+            }
+        }
+    }
+    (root_note, loop_points)
+}
+
+// Serum's looks like "<!>2048 10000000 wavetable (www.xferrecords.com)".
+fn parse_frame_size(payload: &[u8]) -> Option<usize> {
+    let text = String::from_utf8_lossy(payload);
+    let digits: String = text
+        .strip_prefix("<!>")?
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok().filter(|&size| size > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(tag: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = tag.to_vec();
+        chunk.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn fmt(code: u16, channels: u16, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&code.to_le_bytes());
+        payload.extend_from_slice(&channels.to_le_bytes());
+        payload.extend_from_slice(&48000u32.to_le_bytes());
+        payload.extend_from_slice(&(48000 * block_align as u32).to_le_bytes());
+        payload.extend_from_slice(&block_align.to_le_bytes());
+        payload.extend_from_slice(&bits.to_le_bytes());
+        chunk(b"fmt ", &payload)
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        file.extend_from_slice(&body);
+        file
+    }
+
+    fn le_bytes(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|sample| sample.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn sixteen_bit_stereo() {
+        let data = le_bytes(&[0, 16384, -32768, -16384]);
+        let file = parse(&wav(&[fmt(FORMAT_PCM, 2, 16), chunk(b"data", &data)])).unwrap();
+        assert_eq!(file.sample_rate, 48000.0);
+        assert_eq!(file.channels, vec![vec![0.0, -1.0], vec![0.5, -0.5]]);
+        assert_eq!(file.root_note, None);
+        assert_eq!(file.loop_points, None);
+    }
+
+    #[test]
+    fn other_sample_formats() {
+        let file = parse(&wav(&[fmt(FORMAT_PCM, 1, 8), chunk(b"data", &[128, 192, 0])])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.0, 0.5, -1.0]]);
+
+        let data = [0x00, 0x00, 0x40, 0x00, 0x00, 0xC0];
+        let file = parse(&wav(&[fmt(FORMAT_PCM, 1, 24), chunk(b"data", &data)])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.5, -0.5]]);
+
+        let data: Vec<u8> = [0.25f32, -0.75].iter().flat_map(|sample| sample.to_le_bytes().to_vec()).collect();
+        let file = parse(&wav(&[fmt(FORMAT_FLOAT, 1, 32), chunk(b"data", &data)])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.25, -0.75]]);
+
+        let data: Vec<u8> = [0.25f64, -0.75].iter().flat_map(|sample| sample.to_le_bytes().to_vec()).collect();
+        let file = parse(&wav(&[fmt(FORMAT_FLOAT, 1, 64), chunk(b"data", &data)])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.25, -0.75]]);
+    }
+
+    #[test]
+    fn extensible_format() {
+        let mut payload = fmt(FORMAT_EXTENSIBLE, 1, 32)[8..].to_vec();
+        payload.extend_from_slice(&22u16.to_le_bytes());
+        payload.extend_from_slice(&32u16.to_le_bytes());
+        payload.extend_from_slice(&4u32.to_le_bytes());
+        payload.extend_from_slice(&FORMAT_FLOAT.to_le_bytes());
+        payload.extend_from_slice(&[0; 14]);
+        let data = 0.5f32.to_le_bytes();
+        let file = parse(&wav(&[chunk(b"fmt ", &payload), chunk(b"data", &data)])).unwrap();
+        assert_eq!(file.channels, vec![vec![0.5]]);
+    }
+
+    #[test]
+    fn sampler_and_wavetable_chunks() {
+        let mut smpl = vec![0; 12];
+        smpl.extend_from_slice(&60u32.to_le_bytes());
+        smpl.extend_from_slice(&[0; 12]);
+        for value in &[1u32, 0, 0, 0, 100, 199, 0, 0] {
+            smpl.extend_from_slice(&value.to_le_bytes());
+        }
+        let clm = b"<!>2048 10000000 wavetable (www.xferrecords.com)";
+        // An odd-length chunk in between, to check the padding gets skipped.
+        let chunks = [
+            fmt(FORMAT_PCM, 1, 16),
+            chunk(b"junk", &[1, 2, 3]),
+            chunk(b"smpl", &smpl),
+            chunk(b"clm ", clm),
+            chunk(b"data", &le_bytes(&[0; 256])),
+        ];
+        let file = parse(&wav(&chunks)).unwrap();
+        assert_eq!(file.root_note, Some(60));
+        assert_eq!(file.loop_points, Some((100, 199)));
+        assert_eq!(file.frame_size, Some(2048));
+        assert_eq!(file.num_frames(), 256);
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let data = chunk(b"data", &le_bytes(&[0, 1]));
+        assert!(parse(b"RIFX").is_err());
+        assert!(parse(&b"RIFF\x04\x00\x00\x00AVI "[..]).is_err());
+        assert!(parse(&wav(std::slice::from_ref(&data))).is_err());
+        assert!(parse(&wav(&[fmt(FORMAT_PCM, 1, 16)])).is_err());
+        assert!(parse(&wav(&[fmt(FORMAT_PCM, 0, 16), data.clone()])).is_err());
+        assert!(parse(&wav(&[fmt(FORMAT_PCM, 1, 12), data.clone()])).is_err());
+        assert!(parse(&wav(&[fmt(2, 1, 4), data.clone()])).is_err());
+        assert!(parse(&wav(&[fmt(FORMAT_PCM, 1, 16), chunk(b"data", &[])])).is_err());
+        assert!(parse(&wav(&[chunk(b"fmt ", &fmt(FORMAT_PCM, 1, 16)[8..12]), data])).is_err());
+    }
+}
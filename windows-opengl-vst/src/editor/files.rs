@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::Path;
+
+use log::*;
+
+use crate::sample_map::SampleMap;
+use crate::tuning::{KeyboardMapping, Scale, TuningChange};
+use crate::ui_state::UiState;
+use crate::wavetable::Wavetable;
+
+// Loading Scala files, wavetables and samples from the editor. Picking the file is up to each platform's
+// window; this just reads, parses and hands the result to the audio thread.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileKind {
+    Scale,
+    KeyboardMapping,
+    Wavetable,
+    // A single WAV/AIFF, or an SFZ that maps several across the keyboard.
+    Samples,
+}
+
+impl FileKind {
+    pub fn description(self) -> &'static str {
+        match self {
+            FileKind::Scale => "Scala scale",
+            FileKind::KeyboardMapping => "Scala keyboard mapping",
+            FileKind::Wavetable => "Wavetable",
+            FileKind::Samples => "Samples",
+        }
+    }
+
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            FileKind::Scale => &["scl"],
+            FileKind::KeyboardMapping => &["kbm"],
+            FileKind::Wavetable => &["wav", "aif", "aiff"],
+            FileKind::Samples => &["sfz", "wav", "aif", "aiff"],
+        }
+    }
+}
+
+pub fn load(file: FileKind, path: &Path, ui_state: &UiState) {
+    info!("Loading {} {:?}", file.description(), path);
+
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            warn!("Couldn't read {:?}: {}", path, error);
+            return;
+        }
+    };
+
+    let name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let result = match file {
+        FileKind::Wavetable => {
+            Wavetable::from_file(&name, bytes).map(|wavetable| ui_state.request_wavetable_change(wavetable))
+        }
+        FileKind::Samples => load_samples(&name, path, bytes).map(|map| ui_state.request_sample_map_change(map)),
+        _ => {
+            // Scala files are meant to be ASCII, but plenty of them have Latin-1 in the comments.
+            let text = String::from_utf8_lossy(&bytes);
+            let change = match file {
+                FileKind::KeyboardMapping => KeyboardMapping::parse(&text).map(TuningChange::KeyboardMapping),
+                _ => Scale::parse(&text).map(TuningChange::Scale),
+            };
+            change.map(|change| ui_state.request_tuning_change(change))
+        }
+    };
+    if let Err(error) = result {
+        warn!("Couldn't load {:?}: {}", path, error);
+    }
+}
+
+fn load_samples(name: &str, path: &Path, bytes: Vec<u8>) -> Result<SampleMap, String> {
+    let is_sfz = path.extension().map_or(false, |extension| extension.eq_ignore_ascii_case("sfz"));
+    if !is_sfz {
+        return SampleMap::from_audio_file(name, bytes);
+    }
+
+    // The samples an SFZ names are relative to the SFZ itself.
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    SampleMap::from_sfz(name, &String::from_utf8_lossy(&bytes), |sample| {
+        let sample_path = directory.join(sample);
+        fs::read(&sample_path).map_err(|error| format!("couldn't read {:?}: {}", sample_path, error))
+    })
+}
+
+pub fn reset_tuning(ui_state: &UiState) {
+    info!("Resetting tuning to 12-TET");
+    ui_state.request_tuning_change(TuningChange::Reset);
+}
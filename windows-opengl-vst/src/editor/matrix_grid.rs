@@ -0,0 +1,133 @@
+// The modulation matrix, under the step grid: one row per slot. Each row has a cell for every
+// source, then a cell for every destination (both in the order of `SOURCES` and `DESTINATIONS`,
+// starting with "off"), then a slider for the amount that goes both ways from the middle. Click
+// or drag along the cells to pick one.
+
+use crate::mod_matrix::{ModMatrix, DESTINATIONS, MAX_SLOTS, NUM_DESTINATIONS, NUM_SOURCES, SOURCES};
+use crate::ui_state::UiState;
+use super::controls::{fill_rect, Rect, TRACK_COLOR, VALUE_COLOR};
+
+const MATRIX_X: i32 = 20;
+const MATRIX_Y: i32 = 750;
+const ROW_HEIGHT: i32 = 22;
+const ROW_SPACING: i32 = 9;
+const CELL_WIDTH: i32 = 24;
+const CELL_SPACING: i32 = 2;
+const GROUP_SPACING: i32 = 20;
+const AMOUNT_WIDTH: i32 = 230;
+
+// The "off" cells are dimmer, so it's easy to see which slots are doing something.
+const OFF_COLOR: [f32; 3] = [0.15, 0.15, 0.3];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatrixControl {
+    Source,
+    Destination,
+    Amount,
+}
+
+fn cell_rect(slot: usize, first_x: i32, cell: usize) -> Rect {
+    Rect {
+        x: first_x + cell as i32 * (CELL_WIDTH + CELL_SPACING),
+        y: MATRIX_Y + slot as i32 * (ROW_HEIGHT + ROW_SPACING),
+        width: CELL_WIDTH,
+        height: ROW_HEIGHT,
+    }
+}
+
+fn group_width(cells: usize) -> i32 {
+    cells as i32 * (CELL_WIDTH + CELL_SPACING)
+}
+
+fn destinations_x() -> i32 {
+    MATRIX_X + group_width(NUM_SOURCES) + GROUP_SPACING
+}
+
+fn amount_rect(slot: usize) -> Rect {
+    Rect {
+        x: destinations_x() + group_width(NUM_DESTINATIONS) + GROUP_SPACING,
+        width: AMOUNT_WIDTH,
+        ..cell_rect(slot, 0, 0)
+    }
+}
+
+pub fn control_at(x: i32, y: i32) -> Option<(usize, MatrixControl)> {
+    (0..MAX_SLOTS).find_map(|slot| {
+        let sources = Rect {
+            width: group_width(NUM_SOURCES),
+            ..cell_rect(slot, MATRIX_X, 0)
+        };
+        let destinations = Rect {
+            width: group_width(NUM_DESTINATIONS),
+            ..cell_rect(slot, destinations_x(), 0)
+        };
+        if sources.contains(x, y) {
+            Some((slot, MatrixControl::Source))
+        } else if destinations.contains(x, y) {
+            Some((slot, MatrixControl::Destination))
+        } else if amount_rect(slot).contains(x, y) {
+            Some((slot, MatrixControl::Amount))
+        } else {
+            None
+        }
+    })
+}
+
+fn cell_at(first_x: i32, cells: usize, x: i32) -> usize {
+    let cell = (x - first_x) / (CELL_WIDTH + CELL_SPACING);
+    cell.max(0).min(cells as i32 - 1) as usize
+}
+
+// Sets `control` in `slot` from the mouse's x. It can be outside the control while dragging.
+pub fn set(slot: usize, control: MatrixControl, x: i32, matrix: &ModMatrix) {
+    match control {
+        MatrixControl::Source => matrix.set_source(slot, SOURCES[cell_at(MATRIX_X, NUM_SOURCES, x)]),
+        MatrixControl::Destination => {
+            matrix.set_destination(slot, DESTINATIONS[cell_at(destinations_x(), NUM_DESTINATIONS, x)])
+        }
+        MatrixControl::Amount => {
+            let rect = amount_rect(slot);
+            let value = (x - rect.x) as f32 / rect.width as f32;
+            matrix.set_amount(slot, value * 2.0 - 1.0);
+        }
+    }
+}
+
+// Expects the window's GL context to be current, with the scissor test on.
+pub unsafe fn draw(ui_state: &UiState, window_height: i32) {
+    for index in 0..MAX_SLOTS {
+        let slot = ui_state.mod_matrix.slot(index);
+
+        for (cell, &source) in SOURCES.iter().enumerate() {
+            let color = if source == slot.source {
+                VALUE_COLOR
+            } else if cell == 0 {
+                OFF_COLOR
+            } else {
+                TRACK_COLOR
+            };
+            fill_rect(cell_rect(index, MATRIX_X, cell), color, window_height);
+        }
+        for (cell, &destination) in DESTINATIONS.iter().enumerate() {
+            let color = if destination == slot.destination {
+                VALUE_COLOR
+            } else if cell == 0 {
+                OFF_COLOR
+            } else {
+                TRACK_COLOR
+            };
+            fill_rect(cell_rect(index, destinations_x(), cell), color, window_height);
+        }
+
+        let track = amount_rect(index);
+        fill_rect(track, TRACK_COLOR, window_height);
+        let middle = track.x + track.width / 2;
+        let end = middle + (slot.amount * track.width as f32 / 2.0) as i32;
+        let bar = Rect {
+            x: middle.min(end),
+            width: (end - middle).abs().max(1),
+            ..track
+        };
+        fill_rect(bar, VALUE_COLOR, window_height);
+    }
+}
@@ -0,0 +1,117 @@
+// The step sequencer's grid, below the sliders: one row ("lane") per thing a step has, and a
+// column per step. Click or drag in a lane to set the steps under the mouse; dragging along a
+// lane paints across steps.
+//
+// Pulling a gate all the way down makes the step a rest, and pulling a pulse width all the way
+// down removes its lock.
+
+use crate::parameters::{choice_index, AtomicFloat, Parameters};
+use crate::sequencer::{Sequence, MAX_STEPS};
+use crate::ui_state::UiState;
+use super::controls::{fill_rect, Rect, LEARNING_COLOR, TRACK_COLOR, VALUE_COLOR};
+
+const GRID_X: i32 = 20;
+const GRID_Y: i32 = 470;
+const STEP_WIDTH: i32 = 30;
+const STEP_SPACING: i32 = 1;
+const LANE_HEIGHT: i32 = 56;
+const LANE_SPACING: i32 = 8;
+
+// Pitch, gate, velocity, pulse width lock.
+const LANES: usize = 4;
+const PITCH_LANE: usize = 0;
+const GATE_LANE: usize = 1;
+const PULSE_WIDTH_LANE: usize = 3;
+
+// Values below this snap to 0 in the lanes where 0 means "off".
+const OFF_THRESHOLD: f32 = 0.05;
+
+const UNUSED_STEP_COLOR: [f32; 3] = [0.15, 0.15, 0.3];
+
+fn step_rect(lane: usize, step: usize) -> Rect {
+    Rect {
+        x: GRID_X + step as i32 * (STEP_WIDTH + STEP_SPACING),
+        y: GRID_Y + lane as i32 * (LANE_HEIGHT + LANE_SPACING),
+        width: STEP_WIDTH,
+        height: LANE_HEIGHT,
+    }
+}
+
+fn step_at(x: i32) -> usize {
+    let step = (x - GRID_X) / (STEP_WIDTH + STEP_SPACING);
+    step.max(0).min(MAX_STEPS as i32 - 1) as usize
+}
+
+pub fn lane_at(x: i32, y: i32) -> Option<usize> {
+    let grid_width = MAX_STEPS as i32 * (STEP_WIDTH + STEP_SPACING);
+    if x < GRID_X || x >= GRID_X + grid_width {
+        return None;
+    }
+    (0..LANES).find(|&lane| {
+        let rect = step_rect(lane, 0);
+        y >= rect.y && y < rect.y + rect.height
+    })
+}
+
+fn lane_value(sequence: &Sequence, lane: usize, step: usize) -> &AtomicFloat {
+    let step = sequence.step(step);
+    match lane {
+        PITCH_LANE => &step.pitch,
+        GATE_LANE => &step.gate,
+        PULSE_WIDTH_LANE => &step.pulse_width,
+        _ => &step.velocity,
+    }
+}
+
+// Sets the step under (x, y) in `lane`. `y` can be outside the lane while dragging.
+pub fn set_step(lane: usize, x: i32, y: i32, sequence: &Sequence) {
+    let rect = step_rect(lane, 0);
+    let mut value = (1.0 - (y - rect.y) as f32 / rect.height as f32).max(0.0).min(1.0);
+    if (lane == GATE_LANE || lane == PULSE_WIDTH_LANE) && value < OFF_THRESHOLD {
+        value = 0.0;
+    }
+    lane_value(sequence, lane, step_at(x)).set(value);
+}
+
+// Expects the window's GL context to be current, with the scissor test on.
+pub unsafe fn draw(params: &Parameters, ui_state: &UiState, window_height: i32) {
+    let length = if choice_index(params.seq_length.get(), 2) == 0 { 16 } else { MAX_STEPS };
+    let current_step = ui_state.sequence.current_step();
+
+    for lane in 0..LANES {
+        for step in 0..MAX_STEPS {
+            let rect = step_rect(lane, step);
+            let color = if step >= length {
+                UNUSED_STEP_COLOR
+            } else if current_step == Some(step) {
+                LEARNING_COLOR
+            } else {
+                TRACK_COLOR
+            };
+            fill_rect(rect, color, window_height);
+
+            let value = lane_value(&ui_state.sequence, lane, step).get();
+            let bar_height = (rect.height as f32 * value) as i32;
+            let bar = if lane == PITCH_LANE {
+                // Pitch goes up or down from the middle (no transposition).
+                let middle = rect.y + rect.height / 2;
+                let top = middle.min(rect.y + rect.height - bar_height);
+                let bottom = middle.max(rect.y + rect.height - bar_height);
+                Rect {
+                    y: top,
+                    height: (bottom - top).max(1),
+                    ..rect
+                }
+            } else {
+                Rect {
+                    y: rect.y + rect.height - bar_height,
+                    height: bar_height,
+                    ..rect
+                }
+            };
+            if bar.height > 0 {
+                fill_rect(bar, VALUE_COLOR, window_height);
+            }
+        }
+    }
+}
@@ -12,14 +12,16 @@
 //  - https://github.com/wrl/rutabaga/blob/master/src/platform/win/window.c#L126-L151
 
 use std::mem;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::ffi::CString;
-use std::ffi::c_void;
+use std::ffi::{c_void, OsString};
+use std::os::windows::ffi::OsStringExt;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 use vst::plugin::HostCallback;
 use vst::host::Host;
-use user32::{DefWindowProcW, DispatchMessageW, GetMessageW, TranslateMessage};
+use user32::{DefWindowProcW, DispatchMessageW, GetKeyState, GetMessageW, TranslateMessage};
 use winapi::HWND;
 use winapi::winuser::MSG;
 use log::*;
@@ -27,6 +29,10 @@ use rand::Rng;
 use winapi::LONG_PTR;
 
 use crate::parameters::Parameters;
+use crate::sysex::patch_dump;
+use crate::ui_state::UiState;
+use super::controls::{self, ContextAction, Controls};
+use super::files::{self, FileKind};
 
 mod pixel_format;
 mod util;
@@ -36,10 +42,24 @@ mod window_class;
 use win32_window::Win32Window;
 use window_class::WindowClass;
 
-fn draw_window(hwnd: HWND) {
+const WINDOW_HEIGHT: i32 = 768;
+
+// Parameters can change without us getting any window messages (host automation, MIDI learn),
+// so we also redraw on a timer.
+const REDRAW_TIMER_ID: winapi::UINT_PTR = 1;
+const REDRAW_INTERVAL_MS: winapi::UINT = 30;
+
+// Context menu command IDs. Zero means "nothing picked", so don't use it.
+const MENU_LEARN: i32 = 1;
+const MENU_FORGET: i32 = 2;
+const MENU_SET_MIN: i32 = 3;
+const MENU_SET_MAX: i32 = 4;
+
+fn draw_window(hwnd: HWND, state: &WindowState) {
     unsafe {
         gl::ClearColor(0.5f32, 0.5f32, 1.0f32, 1.0f32);
         gl::Clear(gl::COLOR_BUFFER_BIT);
+        controls::draw(&state.params, &state.ui_state, WINDOW_HEIGHT);
         gl::Flush();
         let hdc = user32::GetDC(hwnd);
         gdi32::SwapBuffers(hdc);
@@ -47,6 +67,77 @@ fn draw_window(hwnd: HWND) {
     }
 }
 
+// Mouse coordinates come packed into the LPARAM as two signed 16-bit values.
+fn mouse_position(lparam: winapi::LPARAM) -> (i32, i32) {
+    ((lparam & 0xFFFF) as i16 as i32, ((lparam >> 16) & 0xFFFF) as i16 as i32)
+}
+
+unsafe fn append_menu_item(menu: winapi::HMENU, id: i32, text: &str, enabled: bool) {
+    let text = util::win32_string(text);
+    let mut flags = winapi::MF_STRING;
+    if !enabled {
+        flags |= winapi::MF_GRAYED;
+    }
+    user32::AppendMenuW(menu, flags, id as winapi::UINT_PTR, text.as_ptr());
+}
+
+unsafe fn show_context_menu(hwnd: HWND, x: i32, y: i32, learning: bool, learned: bool) -> Option<ContextAction> {
+    let menu = user32::CreatePopupMenu();
+    append_menu_item(menu, MENU_LEARN, if learning { "Cancel MIDI learn" } else { "MIDI learn" }, true);
+    append_menu_item(menu, MENU_FORGET, "Forget MIDI CC", learned);
+    append_menu_item(menu, MENU_SET_MIN, "Set MIDI minimum to current value", learned);
+    append_menu_item(menu, MENU_SET_MAX, "Set MIDI maximum to current value", learned);
+
+    let mut point = winapi::POINT { x, y };
+    user32::ClientToScreen(hwnd, &mut point);
+    let command = user32::TrackPopupMenu(
+        menu,
+        winapi::TPM_RETURNCMD | winapi::TPM_RIGHTBUTTON,
+        point.x,
+        point.y,
+        0,
+        hwnd,
+        null(),
+    );
+    user32::DestroyMenu(menu);
+
+    match command {
+        MENU_LEARN => Some(ContextAction::Learn),
+        MENU_FORGET => Some(ContextAction::Forget),
+        MENU_SET_MIN => Some(ContextAction::SetMin),
+        MENU_SET_MAX => Some(ContextAction::SetMax),
+        _ => None,
+    }
+}
+
+// Blocks until the user picks a file or cancels; the dialog runs its own message loop.
+unsafe fn open_file_dialog(hwnd: HWND, file: FileKind) -> Option<PathBuf> {
+    let patterns: Vec<String> = file.extensions().iter().map(|extension| format!("*.{}", extension)).collect();
+    let filter = util::win32_string(&format!(
+        "{} ({})\0{}\0",
+        file.description(),
+        patterns.join(";"),
+        patterns.join(";")
+    ));
+    let title = util::win32_string(&format!("Load {}", file.description()));
+    let mut path = [0u16; 1024];
+
+    let mut open_file_name: winapi::OPENFILENAMEW = mem::zeroed();
+    open_file_name.lStructSize = mem::size_of::<winapi::OPENFILENAMEW>() as winapi::DWORD;
+    open_file_name.hwndOwner = hwnd;
+    open_file_name.lpstrFilter = filter.as_ptr();
+    open_file_name.lpstrFile = path.as_mut_ptr();
+    open_file_name.nMaxFile = path.len() as winapi::DWORD;
+    open_file_name.lpstrTitle = title.as_ptr();
+    open_file_name.Flags = winapi::OFN_FILEMUSTEXIST | winapi::OFN_PATHMUSTEXIST | winapi::OFN_NOCHANGEDIR;
+
+    if comdlg32::GetOpenFileNameW(&mut open_file_name) == 0 {
+        return None;
+    }
+    let length = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+    Some(PathBuf::from(OsString::from_wide(&path[..length])))
+}
+
 unsafe extern "system" fn wnd_proc(
     hwnd: HWND,
     msg: winapi::UINT,
@@ -56,7 +147,12 @@ unsafe extern "system" fn wnd_proc(
     // TODO: This was a static in C/C++. Does it need to be? Maybe put it into the WindowLongPtr?
     let mut paint_struct = mem::zeroed();
 
-    let mut state = unsafe { &mut *(user32::GetWindowLongPtrW(hwnd, winapi::winuser::GWLP_USERDATA) as *mut WindowState) };
+    // Messages can show up before `Window::new()` has handed us our state.
+    let state_ptr = user32::GetWindowLongPtrW(hwnd, winapi::winuser::GWLP_USERDATA) as *mut WindowState;
+    if state_ptr.is_null() {
+        return DefWindowProcW(hwnd, msg, wparam, lparam);
+    }
+    let state = &mut *state_ptr;
 
     match msg {
         winapi::WM_DESTROY => {
@@ -69,18 +165,95 @@ unsafe extern "system" fn wnd_proc(
             // TODO: This came after the draw_window() function in example code I found. Should it be?
             user32::BeginPaint(hwnd, &mut paint_struct);
 
-            draw_window(hwnd);
+            draw_window(hwnd, state);
             user32::EndPaint(hwnd, &mut paint_struct);
 
             DefWindowProcW(hwnd, msg, wparam, lparam)
         },
+        winapi::WM_TIMER => {
+            if wparam == REDRAW_TIMER_ID {
+                user32::InvalidateRect(hwnd, null(), 0);
+            }
+            0
+        },
         winapi::WM_LBUTTONDOWN => {
             info!("wnd_proc: CLICK!");
-            let new_param_val = state.rng.gen_range(0.0, 1.0);
-            state.params.pulse_width.set(new_param_val);
-            state.host_callback.lock().unwrap().automate(1, new_param_val);
+            // Child windows don't get keyboard focus on their own, and we want key presses.
+            user32::SetFocus(hwnd);
+            let (x, y) = mouse_position(lparam);
+            let change = state.controls.mouse_down(x, y, &state.params, &state.ui_state);
+            let (index, new_param_val) = if state.controls.is_dragging() {
+                // Keep getting mouse moves while dragging, even outside the window.
+                user32::SetCapture(hwnd);
+                match change {
+                    Some(change) => change,
+                    None => return 0,
+                }
+            } else {
+                let new_param_val = state.rng.gen_range(0.0, 1.0);
+                state.params.pulse_width.set(new_param_val);
+                (1, new_param_val)
+            };
+            state.host_callback.lock().unwrap().automate(index, new_param_val);
+            0
+        },
+        winapi::WM_MOUSEMOVE => {
+            let (x, y) = mouse_position(lparam);
+            if let Some((index, value)) = state.controls.mouse_drag(x, y, &state.params, &state.ui_state) {
+                state.host_callback.lock().unwrap().automate(index, value);
+            }
+            0
+        },
+        winapi::WM_LBUTTONUP => {
+            state.controls.mouse_up();
+            user32::ReleaseCapture();
+            0
+        },
+        winapi::WM_RBUTTONDOWN => {
+            let (x, y) = mouse_position(lparam);
+            if let Some(index) = controls::slider_at(x, y) {
+                let learning = state.ui_state.midi_learn.learning() == Some(index);
+                let learned = state.ui_state.midi_learn.controller_for(index).is_some();
+                if let Some(action) = show_context_menu(hwnd, x, y, learning, learned) {
+                    controls::apply_context_action(index, action, &state.params, &state.ui_state);
+                }
+            }
             0
         },
+        winapi::WM_KEYDOWN => {
+            // Letter keys come through as their upper case ASCII codes.
+            match wparam as i32 {
+                winapi::VK_ESCAPE => {
+                    info!("wnd_proc: Panic!");
+                    state.ui_state.request_panic();
+                    0
+                },
+                0x53 /* S */ | 0x4B /* K */ | 0x57 /* W */ | 0x4C /* L */ => {
+                    let file = match wparam {
+                        0x53 => FileKind::Scale,
+                        0x4B => FileKind::KeyboardMapping,
+                        0x57 => FileKind::Wavetable,
+                        _ => FileKind::Samples,
+                    };
+                    if let Some(path) = open_file_dialog(hwnd, file) {
+                        files::load(file, &path, &state.ui_state);
+                    }
+                    0
+                },
+                0x52 /* R */ => {
+                    files::reset_tuning(&state.ui_state);
+                    0
+                },
+                0x44 /* D */ => {
+                    // Shift puts the wavetable and samples in too.
+                    let with_wavetable_and_samples = GetKeyState(winapi::VK_SHIFT) < 0;
+                    let dump = patch_dump(&state.params, &state.ui_state, with_wavetable_and_samples);
+                    state.ui_state.request_sysex_dump(dump);
+                    0
+                },
+                _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+            }
+        },
         _ => {
             DefWindowProcW(hwnd, msg, wparam, lparam)
         }
@@ -259,6 +432,8 @@ struct WindowState {
     rng: rand::rngs::ThreadRng,
     host_callback: Arc<Mutex<HostCallback>>,
     params: Arc<Parameters>,
+    ui_state: Arc<UiState>,
+    controls: Controls,
 }
 
 pub struct Window {
@@ -268,11 +443,18 @@ pub struct Window {
 }
 
 impl Window {
-    pub fn new(host_callback: Arc<Mutex<HostCallback>>, params: Arc<Parameters>, parent: *mut c_void) -> Self {
+    pub fn new(
+        host_callback: Arc<Mutex<HostCallback>>,
+        params: Arc<Parameters>,
+        ui_state: Arc<UiState>,
+        parent: *mut c_void,
+    ) -> Self {
         let window_state = Box::new(WindowState {
             rng: rand::thread_rng(),
             host_callback,
-            params
+            params,
+            ui_state,
+            controls: Controls::new(),
         });
 
         let proc_address_loader = util::ProcAddressLoader::new();
@@ -288,16 +470,17 @@ impl Window {
             proc_address_loader.get_proc_address(s)
         });
 
-        unsafe {
-            user32::ShowWindow(window.handle(), 1);
-        }
-
         unsafe {
             // TODO: make a const for that magic number
             let window_state_cvoid = Box::into_raw(window_state) as *mut c_void;
             user32::SetWindowLongPtrW(window.handle(), winapi::winuser::GWLP_USERDATA, window_state_cvoid as LONG_PTR);
         }
 
+        unsafe {
+            user32::ShowWindow(window.handle(), 1);
+            user32::SetTimer(window.handle(), REDRAW_TIMER_ID, REDRAW_INTERVAL_MS, None);
+        }
+
         Self {
             win32_window: window,
             hdc,
@@ -317,6 +500,14 @@ impl Window {
 impl Drop for Window {
     fn drop(&mut self) {
         unsafe {
+            user32::KillTimer(self.win32_window.handle(), REDRAW_TIMER_ID);
+
+            // Take the window state back so it gets freed, and so `wnd_proc` stops using it.
+            let window_state = user32::SetWindowLongPtrW(self.win32_window.handle(), winapi::winuser::GWLP_USERDATA, 0);
+            if window_state != 0 {
+                drop(Box::from_raw(window_state as *mut WindowState));
+            }
+
             opengl32::wglMakeCurrent(self.hdc, null_mut());
             opengl32::wglDeleteContext(self.context);
             user32::ReleaseDC(self.win32_window.handle(), self.hdc);
@@ -0,0 +1,200 @@
+use crate::parameters::choice_index;
+
+// A stereo delay, with its time in seconds or synced to a note length, and an optional ping-pong
+// mode where the echoes bounce from side to side. The feedback path is gently lowpassed, so each
+// repeat gets a bit darker like an analog delay.
+
+pub const MAX_DELAY_SECONDS: f64 = 4.0;
+const MIN_DELAY_SECONDS: f64 = 0.001;
+// Longest free (unsynced) time, at the top of the parameter.
+const MAX_FREE_SECONDS: f64 = 2.0;
+const MAX_FEEDBACK: f64 = 0.95;
+// How much of the feedback's high end is lost on each repeat.
+const FEEDBACK_DAMPING: f64 = 0.3;
+// How quickly the delay time moves to a new setting, per sample. Jumping straight there would
+// click; gliding there sounds like tape.
+const TIME_SMOOTHING: f64 = 0.0005;
+
+// Delay times in quarter notes, in the order of the time parameter's choices when synced.
+const SYNC_TIMES: [f64; 13] = [
+    0.125,
+    1.0 / 6.0,
+    0.25,
+    1.0 / 3.0,
+    0.375,
+    0.5,
+    2.0 / 3.0,
+    0.75,
+    1.0,
+    4.0 / 3.0,
+    1.5,
+    2.0,
+    4.0,
+];
+const SYNC_TIME_NAMES: [&str; 13] = [
+    "1/32", "1/16T", "1/16", "1/8T", "1/16.", "1/8", "1/4T", "1/8.", "1/4", "1/2T", "1/4.", "1/2", "1 bar",
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct DelaySettings {
+    pub seconds: f64,
+    pub feedback: f64,
+    pub ping_pong: bool,
+}
+
+impl DelaySettings {
+    pub fn new(time: f32, sync: f32, feedback: f32, ping_pong: f32, tempo: f64) -> Self {
+        Self {
+            seconds: delay_seconds(time, sync, tempo),
+            feedback: feedback as f64 * MAX_FEEDBACK,
+            ping_pong: choice_index(ping_pong, 2) == 1,
+        }
+    }
+
+    // Until the echoes are 60 dB down.
+    pub fn tail_seconds(&self) -> f64 {
+        if self.feedback <= 0.0 {
+            return self.seconds;
+        }
+        self.seconds * (1.0 + 0.001f64.ln() / self.feedback.ln())
+    }
+}
+
+pub struct Delay {
+    // Left, then right.
+    lines: [Vec<f64>; 2],
+    write: usize,
+    // In samples, gliding towards the setting.
+    delay: f64,
+    damping: [f64; 2],
+}
+
+impl Delay {
+    pub fn new(sample_rate: f32) -> Self {
+        let length = (MAX_DELAY_SECONDS * sample_rate as f64) as usize + 2;
+        Self {
+            lines: [vec![0.0; length], vec![0.0; length]],
+            write: 0,
+            delay: 0.0,
+            damping: [0.0; 2],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for line in &mut self.lines {
+            for sample in line.iter_mut() {
+                *sample = 0.0;
+            }
+        }
+        self.damping = [0.0; 2];
+    }
+
+    // Returns the echoes alone, without the input.
+    pub fn process(&mut self, input: (f64, f64), settings: &DelaySettings, sample_rate: f32) -> (f64, f64) {
+        let length = self.lines[0].len();
+        let target = (settings.seconds * sample_rate as f64).max(1.0).min((length - 2) as f64);
+        if self.delay <= 0.0 {
+            self.delay = target;
+        }
+        self.delay += (target - self.delay) * TIME_SMOOTHING;
+
+        let mut delayed = [0.0; 2];
+        for (side, line) in self.lines.iter().enumerate() {
+            let position = self.write as f64 + length as f64 - self.delay;
+            let index = position as usize;
+            let a = line[index % length];
+            let b = line[(index + 1) % length];
+            delayed[side] = a + (b - a) * position.fract();
+        }
+
+        for (side, damping) in self.damping.iter_mut().enumerate() {
+            *damping += (delayed[side] - *damping) * (1.0 - FEEDBACK_DAMPING);
+        }
+        let feedback = [self.damping[0] * settings.feedback, self.damping[1] * settings.feedback];
+        if settings.ping_pong {
+            // Everything goes in on the left, and each side feeds the other.
+            self.lines[0][self.write] = (input.0 + input.1) * 0.5 + feedback[1];
+            self.lines[1][self.write] = feedback[0];
+        } else {
+            self.lines[0][self.write] = input.0 + feedback[0];
+            self.lines[1][self.write] = input.1 + feedback[1];
+        }
+        self.write = (self.write + 1) % length;
+
+        (delayed[0], delayed[1])
+    }
+}
+
+fn delay_seconds(time: f32, sync: f32, tempo: f64) -> f64 {
+    let seconds = if choice_index(sync, 2) == 1 {
+        SYNC_TIMES[choice_index(time, SYNC_TIMES.len())] * 60.0 / tempo
+    } else {
+        time as f64 * time as f64 * MAX_FREE_SECONDS
+    };
+    seconds.max(MIN_DELAY_SECONDS).min(MAX_DELAY_SECONDS)
+}
+
+pub fn delay_time_text(time: f32, sync: f32) -> String {
+    if choice_index(sync, 2) == 1 {
+        SYNC_TIME_NAMES[choice_index(time, SYNC_TIMES.len())].to_string()
+    } else {
+        let seconds = delay_seconds(time, sync, 120.0);
+        if seconds < 1.0 {
+            format!("{:0.0} ms", seconds * 1000.0)
+        } else {
+            format!("{:0.2} s", seconds)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    // The left output, for an impulse in on the left.
+    fn impulse_response(settings: &DelaySettings, length: usize) -> Vec<f64> {
+        let mut delay = Delay::new(SAMPLE_RATE);
+        (0..length)
+            .map(|i| delay.process((if i == 0 { 1.0 } else { 0.0 }, 0.0), settings, SAMPLE_RATE).0)
+            .collect()
+    }
+
+    #[test]
+    fn tail_gets_longer_with_more_feedback() {
+        let tails: Vec<f64> = [0.0, 0.3, 0.6, 1.0]
+            .iter()
+            .map(|&feedback| DelaySettings::new(0.5, 0.0, feedback, 0.0, 120.0).tail_seconds())
+            .collect();
+        assert_eq!(tails[0], delay_seconds(0.5, 0.0, 120.0));
+        assert!(tails.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", tails);
+    }
+
+    #[test]
+    fn echoes_are_60_db_down_by_the_end_of_the_tail() {
+        let settings = DelaySettings::new(0.2, 0.0, 0.8, 0.0, 120.0);
+        let tail = (settings.tail_seconds() * SAMPLE_RATE as f64) as usize;
+        let response = impulse_response(&settings, tail * 2);
+
+        let first_echo = response.iter().fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+        let after_tail = response[tail..].iter().fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+        assert!(first_echo > 0.5);
+        assert!(after_tail <= first_echo * 0.001, "{} {}", first_echo, after_tail);
+    }
+
+    #[test]
+    fn synced_time_follows_the_tempo() {
+        // A quarter note at 120 BPM, and a dotted eighth at 90.
+        let quarter = DelaySettings::new(8.0 / 12.0, 1.0, 0.0, 0.0, 120.0);
+        let dotted_eighth = DelaySettings::new(7.0 / 12.0, 1.0, 0.0, 0.0, 90.0);
+        assert_eq!(delay_time_text(8.0 / 12.0, 1.0), "1/4");
+        assert_eq!(delay_time_text(7.0 / 12.0, 1.0), "1/8.");
+        assert_eq!(quarter.seconds, 0.5);
+        assert_eq!(dotted_eighth.seconds, 0.5);
+
+        let response = impulse_response(&quarter, 30000);
+        let peak = (0..response.len()).max_by(|&a, &b| response[a].abs().partial_cmp(&response[b].abs()).unwrap());
+        assert_eq!(peak, Some(24000));
+    }
+}
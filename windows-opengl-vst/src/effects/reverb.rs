@@ -0,0 +1,135 @@
+// An algorithmic reverb in the style of Freeverb: eight lowpassed comb filters in parallel, then
+// four allpasses in series, for each side. The right side's delays are a little longer than the
+// left's, which is what makes it stereo.
+
+// Delay lengths in samples at 44.1 kHz, scaled for other rates.
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const TUNING_RATE: f64 = 44100.0;
+
+const INPUT_GAIN: f64 = 0.015;
+// Makes up for the input gain, so a full mix is about as loud as the dry signal.
+const OUTPUT_GAIN: f64 = 3.0;
+const ALLPASS_FEEDBACK: f64 = 0.5;
+// Room size maps onto the combs' feedback.
+const MIN_FEEDBACK: f64 = 0.7;
+const MAX_FEEDBACK: f64 = 0.98;
+const MAX_DAMPING: f64 = 0.4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReverbSettings {
+    pub feedback: f64,
+    pub damping: f64,
+}
+
+impl ReverbSettings {
+    pub fn new(size: f32, damping: f32) -> Self {
+        Self {
+            feedback: MIN_FEEDBACK + size as f64 * (MAX_FEEDBACK - MIN_FEEDBACK),
+            damping: damping as f64 * MAX_DAMPING,
+        }
+    }
+
+    // Until the longest comb has died away by 60 dB.
+    pub fn tail_seconds(&self) -> f64 {
+        let longest = (COMB_TUNING[COMB_TUNING.len() - 1] + STEREO_SPREAD) as f64 / TUNING_RATE;
+        3.0 * longest / -self.feedback.log10()
+    }
+}
+
+struct Comb {
+    buffer: Vec<f64>,
+    index: usize,
+    filter_state: f64,
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length.max(1)],
+            index: 0,
+            filter_state: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f64, feedback: f64, damping: f64) -> f64 {
+        let output = self.buffer[self.index];
+        self.filter_state = output * (1.0 - damping) + self.filter_state * damping;
+        self.buffer[self.index] = input + self.filter_state * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f64>,
+    index: usize,
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length.max(1)],
+            index: 0,
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = input + delayed * ALLPASS_FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+pub struct Reverb {
+    // Left, then right.
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+}
+
+impl Reverb {
+    pub fn new(sample_rate: f32) -> Self {
+        let scale = |length: usize| (length as f64 * sample_rate as f64 / TUNING_RATE) as usize;
+        let side = |spread: usize| -> (Vec<Comb>, Vec<Allpass>) {
+            (
+                COMB_TUNING.iter().map(|&length| Comb::new(scale(length + spread))).collect(),
+                ALLPASS_TUNING.iter().map(|&length| Allpass::new(scale(length + spread))).collect(),
+            )
+        };
+        let (left_combs, left_allpasses) = side(0);
+        let (right_combs, right_allpasses) = side(STEREO_SPREAD);
+        Self {
+            combs: [left_combs, right_combs],
+            allpasses: [left_allpasses, right_allpasses],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for comb in self.combs.iter_mut().flatten() {
+            comb.buffer.iter_mut().for_each(|sample| *sample = 0.0);
+            comb.filter_state = 0.0;
+        }
+        for allpass in self.allpasses.iter_mut().flatten() {
+            allpass.buffer.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+    }
+
+    // Returns the reverb alone. Both sides are fed the same mono input.
+    pub fn process(&mut self, input: (f64, f64), settings: &ReverbSettings) -> (f64, f64) {
+        let input = (input.0 + input.1) * INPUT_GAIN;
+        let mut output = [0.0; 2];
+        for side in 0..2 {
+            let mut sum = 0.0;
+            for comb in &mut self.combs[side] {
+                sum += comb.process(input, settings.feedback, settings.damping);
+            }
+            for allpass in &mut self.allpasses[side] {
+                sum = allpass.process(sum);
+            }
+            output[side] = sum * OUTPUT_GAIN;
+        }
+        (output[0], output[1])
+    }
+}
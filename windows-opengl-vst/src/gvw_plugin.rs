@@ -1,7 +1,9 @@
 use std::fs::File;
+use std::ptr;
 use std::sync::{Arc, Mutex};
 
 use log::*;
+use num_traits::Float;
 use vst::editor::Editor as VstEditor;
 use vst::{
     api::{Events, Supported},
@@ -10,19 +12,121 @@ use vst::{
     plugin::{CanDo, Category, HostCallback, Info, Plugin},
 };
 
+use crate::arpeggiator::Arpeggiator;
 use crate::audio_engine::AudioEngine;
+use crate::chunk::{ChunkReader, ChunkWriter};
+use crate::denormals::DenormalGuard;
 use crate::editor::Editor;
+use crate::master::LOOKAHEAD_SAMPLES;
 use crate::midi_input_processor::MidiInputProcessor;
-use crate::parameters::Parameters;
+use crate::midi_output::MidiOutput;
+use crate::parameters::{Parameters, NUM_PARAMETERS};
+use crate::sample_map::SampleMap;
+use crate::sequencer::Sequencer;
+use crate::sysex::{is_patch_dump, read_patch_section, write_patch, DumpLoader};
+use crate::transport::Transport;
+use crate::tuning::Tuning;
+use crate::ui_state::UiState;
+use crate::wavetable::Wavetable;
 
 pub struct GvlPlugin {
     host: HostCallback,
     audio_engine: AudioEngine,
     midi_input_processor: MidiInputProcessor,
+    arpeggiator: Arpeggiator,
+    sequencer: Sequencer,
+    midi_output: MidiOutput,
+    // Never touched, just kept so the loader thread runs as long as the plugin does.
+    _dump_loader: DumpLoader,
     params: Arc<Parameters>,
+    ui_state: Arc<UiState>,
     editor: Box<dyn VstEditor>,
 }
 
+impl GvlPlugin {
+    fn save_state(&self) -> Vec<u8> {
+        let mut chunk = ChunkWriter::new();
+        write_patch(&mut chunk, &self.params, &self.ui_state);
+        self.audio_engine.tuning().write_chunk(&mut chunk);
+        if let Some(wavetable) = self.audio_engine.wavetable() {
+            wavetable.write_chunk(&mut chunk);
+        }
+        if let Some(map) = self.audio_engine.sample_map() {
+            map.write_chunk(&mut chunk);
+        }
+        chunk.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) {
+        let chunk = match ChunkReader::new(data) {
+            Some(chunk) => chunk,
+            None => {
+                warn!("Ignoring state chunk we don't recognize ({} bytes)", data.len());
+                return;
+            }
+        };
+
+        // No tuning section means 12-TET, no matrix section means no modulation, and no
+        // wavetable or sample map section means no wavetable or samples.
+        let mut tuning = Tuning::new();
+        let mut wavetable = None;
+        let mut sample_map = None;
+        self.ui_state.mod_matrix.clear();
+        for (tag, payload) in chunk {
+            if read_patch_section(&tag, payload, &self.params, &self.ui_state) {
+                continue;
+            }
+            match &tag {
+                b"TUNE" => tuning = Tuning::read_chunk(payload),
+                b"WTBL" => wavetable = Wavetable::read_chunk(payload).map(Arc::new),
+                b"SMAP" => sample_map = SampleMap::read_chunk(payload).map(Arc::new),
+                _ => info!("Skipping unknown state section {:?}", tag),
+            }
+        }
+        self.ui_state.set_files(tuning.clone(), wavetable.clone(), sample_map.clone());
+        self.audio_engine.set_tuning(tuning);
+        self.audio_engine.set_wavetable(wavetable);
+        self.audio_engine.set_sample_map(sample_map);
+    }
+
+    // Both of the host's sample types end up here.
+    fn process_block<T: Float>(&mut self, buffer: &mut AudioBuffer<T>) {
+        let _denormals = DenormalGuard::new();
+        if self.ui_state.take_panic_request() {
+            self.midi_input_processor.panic();
+        }
+        if let Some(tuning) = self.ui_state.take_tuning_change() {
+            self.audio_engine.set_tuning(tuning);
+        }
+        if let Some(wavetable) = self.ui_state.take_wavetable_change() {
+            info!("Switching to wavetable {:?}", wavetable.name());
+            self.audio_engine.set_wavetable(Some(wavetable));
+        }
+        if let Some(map) = self.ui_state.take_sample_map_change() {
+            info!("Switching to samples {:?}", map.name());
+            self.audio_engine.set_sample_map(Some(map));
+        }
+
+        if let Some(dump) = self.ui_state.take_sysex_dump() {
+            info!("Sending a SysEx patch dump ({} bytes)", dump.len());
+            self.midi_output.sysex(dump);
+        }
+
+        let transport = Transport::from_host(&self.host);
+        self.audio_engine.set_offline(is_offline(&self.host));
+        self.arpeggiator
+            .process(self.midi_input_processor.events(), buffer.samples(), &transport);
+        self.midi_input_processor.clear_events();
+        self.sequencer
+            .process(self.arpeggiator.events(), buffer.samples(), &transport);
+
+        self.midi_output.notes(self.sequencer.events());
+        self.midi_output.send(&self.host);
+
+        self.audio_engine.process(buffer, self.sequencer.events(), &transport);
+    }
+}
+
 impl vst::plugin::Plugin for GvlPlugin {
     fn new(host: HostCallback) -> Self {
         // Set up a logger so we can see what's going on in the VST
@@ -40,12 +144,18 @@ impl vst::plugin::Plugin for GvlPlugin {
         // Create the plugin itself
         let host_callback = Arc::new(Mutex::new(host));
         let params = Arc::new(Parameters::new());
+        let ui_state = Arc::new(UiState::new());
         Self {
             host,
-            audio_engine: AudioEngine::new(params.clone()),
-            midi_input_processor: MidiInputProcessor::new(),
+            audio_engine: AudioEngine::new(params.clone(), ui_state.clone()),
+            midi_input_processor: MidiInputProcessor::new(params.clone()),
+            arpeggiator: Arpeggiator::new(params.clone()),
+            sequencer: Sequencer::new(params.clone(), ui_state.clone()),
+            midi_output: MidiOutput::new(params.clone()),
+            _dump_loader: DumpLoader::new(params.clone(), ui_state.clone()),
             params: params.clone(),
-            editor: Box::new(Editor::new(host_callback, params.clone())),
+            ui_state: ui_state.clone(),
+            editor: Box::new(Editor::new(host_callback, params.clone(), ui_state.clone())),
         }
     }
 
@@ -58,9 +168,12 @@ impl vst::plugin::Plugin for GvlPlugin {
             category: Category::Synth,
             inputs: 0,
             midi_inputs: 1,
+            midi_outputs: 1,
             outputs: 2,
-            parameters: 2,
-            initial_delay: 0,
+            parameters: NUM_PARAMETERS,
+            initial_delay: (LOOKAHEAD_SAMPLES + self.audio_engine.latency()) as i32,
+            preset_chunks: true,
+            f64_precision: true,
             ..Info::default()
         }
     }
@@ -71,21 +184,75 @@ impl vst::plugin::Plugin for GvlPlugin {
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        self.audio_engine
-            .process(buffer, self.midi_input_processor.get_active_notes());
+        self.process_block(buffer);
+    }
+
+    fn process_f64(&mut self, buffer: &mut AudioBuffer<f64>) {
+        self.process_block(buffer);
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        info!("set_sample_rate({})", rate);
+        self.audio_engine.set_sample_rate(rate);
+        self.arpeggiator.set_sample_rate(rate);
+        self.sequencer.set_sample_rate(rate);
+    }
+
+    fn set_block_size(&mut self, size: i64) {
+        info!("set_block_size({})", size);
+        self.audio_engine.set_block_size(size.max(0) as usize);
+    }
+
+    // Just the effects' tail. 0 (when they're all bypassed) leaves it up to the host.
+    fn get_tail_size(&self) -> isize {
+        self.audio_engine.tail_size() as isize
     }
 
     fn can_do(&self, can_do: CanDo) -> Supported {
         match can_do {
-            CanDo::ReceiveMidiEvent => Supported::Yes,
+            CanDo::ReceiveMidiEvent | CanDo::SendEvents | CanDo::SendMidiEvent | CanDo::Bypass => Supported::Yes,
             _ => Supported::Maybe,
         }
     }
 
+    // Soft bypass: the host keeps calling `process`, and the output fades out. Hosts don't always
+    // pass MIDI on to a bypassed plugin, so any note still held when it comes back is let go
+    // rather than left hanging, latched arpeggios and the sequencer's note included.
+    fn set_bypass(&mut self, bypass: bool) {
+        info!("set_bypass({})", bypass);
+        if self.audio_engine.is_bypassed() && !bypass {
+            self.midi_input_processor.release_all();
+            self.arpeggiator.release_all();
+            self.sequencer.release_all();
+        }
+        self.audio_engine.set_bypass(bypass);
+    }
+
     fn process_events(&mut self, events: &Events) {
         for event in events.events() {
             match event {
-                Event::Midi(ev) => self.midi_input_processor.process_midi_event(ev.data),
+                Event::Midi(ev) => {
+                    if ev.data[0] & 0xF0 == 0xB0 {
+                        let learned = self.ui_state.midi_learn.control_change(ev.data[1], ev.data[2]);
+                        // A learned controller drives its parameter and nothing else.
+                        if let Some((index, value)) = learned {
+                            self.set_parameter(index, value);
+                            continue;
+                        }
+                    }
+                    self.midi_output.thru(ev.data, ev.delta_frames);
+                    self.midi_input_processor
+                        .process_midi_event(ev.data, ev.delta_frames.max(0) as usize);
+                },
+                Event::SysEx(ev) => {
+                    if !is_patch_dump(ev.payload) {
+                        info!("Ignoring SysEx that isn't one of our patch dumps");
+                    } else if self.ui_state.receive_sysex_dump(ev.payload) {
+                        info!("Received a SysEx patch dump ({} bytes)", ev.payload.len());
+                    } else {
+                        warn!("Dropping a SysEx patch dump ({} bytes)", ev.payload.len());
+                    }
+                },
                 // More events can be handled here.
                 _ => (),
             }
@@ -94,30 +261,18 @@ impl vst::plugin::Plugin for GvlPlugin {
 
     fn get_parameter(&self, index: i32) -> f32 {
         info!("get_parameter({})", index);
-        match index {
-            0 => self.params.amplitude.get(),
-            1 => self.params.pulse_width.get(),
-            _ => 0.0,
-        }
+        self.params.get_parameter(index)
     }
 
     fn set_parameter(&mut self, index: i32, value: f32) {
         info!("set_parameter()");
-        match index {
-            0 => self.params.amplitude.set(value),
-            1 => self.params.pulse_width.set(value),
-            _ => (),
-        }
+        self.params.set_parameter(index, value);
     }
 
     // "Amplitude", "Pulse width", etc.
     fn get_parameter_name(&self, index: i32) -> String {
         info!("get_parameter_name({})", index);
-        match index {
-            0 => format!("Amplitude"),
-            1 => format!("Pulse width"),
-            _ => format!(""),
-        }
+        self.params.get_parameter_name(index)
     }
 
     // Ignored by Bitwig, so I just put this in `get_parameter_text` instead.
@@ -130,20 +285,32 @@ impl vst::plugin::Plugin for GvlPlugin {
     // "1.0", "150", "Plate", etc.
     fn get_parameter_text(&self, index: i32) -> String {
         info!("get_parameter_text({})", index);
-        match index {
-            0 => format!("{:0.2} %", self.params.amplitude.get() * 100.0), // Amplitude
-            1 => format!("{:0.2} %", self.params.pulse_width.get() * 100.0), // Pulse width
-            _ => format!(""),
-        }
+        self.params.get_parameter_text(index)
     }
 
     fn can_be_automated(&self, index: i32) -> bool {
         info!("can_be_automated({})", index);
-        match index {
-            0 => true, // Amplitude
-            1 => true, // Pulse width
-            _ => false,
-        }
+        index >= 0 && index < NUM_PARAMETERS
+    }
+
+    fn get_preset_data(&mut self) -> Vec<u8> {
+        info!("get_preset_data()");
+        self.save_state()
+    }
+
+    fn get_bank_data(&mut self) -> Vec<u8> {
+        info!("get_bank_data()");
+        self.save_state()
+    }
+
+    fn load_preset_data(&mut self, data: &[u8]) {
+        info!("load_preset_data()");
+        self.load_state(data);
+    }
+
+    fn load_bank_data(&mut self, data: &[u8]) {
+        info!("load_bank_data()");
+        self.load_state(data);
     }
 
     fn get_editor(&mut self) -> Option<&mut vst::editor::Editor> {
@@ -157,3 +324,18 @@ impl Default for GvlPlugin {
         Plugin::new(HostCallback::default())
     }
 }
+
+// Whether the host is rendering offline (faster or slower than realtime). The `Host` trait doesn't
+// wrap this opcode, so it goes through the raw callback.
+fn is_offline(host: &HostCallback) -> bool {
+    const GET_CURRENT_PROCESS_LEVEL: i32 = 23;
+    const PROCESS_LEVEL_OFFLINE: isize = 4;
+
+    match host.raw_callback() {
+        Some(callback) => {
+            let level = callback(ptr::null_mut(), GET_CURRENT_PROCESS_LEVEL, 0, 0, ptr::null_mut(), 0.0);
+            level == PROCESS_LEVEL_OFFLINE
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,63 @@
+// A lookahead brickwall limiter. The gain it needs for each sample is held for the length of the
+// lookahead, then smoothed with a moving average over the same length, so it's already all the
+// way down by the time a peak comes out of the delay line and never lets anything over the ceiling.
+
+pub const LOOKAHEAD_SAMPLES: usize = 64;
+const WINDOW: usize = LOOKAHEAD_SAMPLES + 1;
+// Just under full scale, to leave room for intersample peaks.
+const CEILING: f64 = 0.97;
+const RELEASE_SECONDS: f64 = 0.1;
+
+pub struct Limiter {
+    // Left, then right, delayed by the lookahead.
+    delay: [[f64; WINDOW]; 2],
+    // The gain each of the last few samples needs, after the release.
+    held: [f64; WINDOW],
+    // The gain after holding, for the moving average.
+    smoothing: [f64; WINDOW],
+    smoothing_sum: f64,
+    position: usize,
+    release: f64,
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        Self {
+            delay: [[0.0; WINDOW]; 2],
+            held: [1.0; WINDOW],
+            smoothing: [1.0; WINDOW],
+            smoothing_sum: WINDOW as f64,
+            position: 0,
+            release: 1.0,
+        }
+    }
+
+    // Always delays by the lookahead, even when it isn't limiting, so the latency the host
+    // compensates for doesn't change when it's switched on or off.
+    pub fn process(&mut self, input: (f64, f64), enabled: bool, sample_rate: f32) -> (f64, f64) {
+        let peak = input.0.abs().max(input.1.abs());
+        let needed = if peak > CEILING { CEILING / peak } else { 1.0 };
+
+        // Recover slowly from the last gain reduction, but never to more than this sample allows.
+        let release_step = 1.0 - (-1.0 / (RELEASE_SECONDS * sample_rate as f64)).exp();
+        self.release = (self.release + (1.0 - self.release) * release_step).min(needed);
+        self.held[self.position] = self.release;
+        let hold = self.held.iter().cloned().fold(1.0, f64::min);
+
+        self.smoothing_sum += hold - self.smoothing[self.position];
+        self.smoothing[self.position] = hold;
+        let gain = (self.smoothing_sum / WINDOW as f64).min(1.0);
+
+        self.delay[0][self.position] = input.0;
+        self.delay[1][self.position] = input.1;
+        self.position = (self.position + 1) % WINDOW;
+        // The oldest sample, which is next to be overwritten.
+        let output = (self.delay[0][self.position], self.delay[1][self.position]);
+
+        if enabled {
+            (output.0 * gain, output.1 * gain)
+        } else {
+            output
+        }
+    }
+}
@@ -1,37 +1,385 @@
 use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use log::*;
+
+use crate::parameters::{choice_index, Parameters};
+
+mod mpe_zones;
+use self::mpe_zones::MpeZones;
+
+// Status bytes, with the channel nibble masked off.
+pub const NOTE_OFF: u8 = 0x80;
+pub const NOTE_ON: u8 = 0x90;
+const POLY_PRESSURE: u8 = 0xA0;
+pub const CONTROL_CHANGE: u8 = 0xB0;
+const CHANNEL_PRESSURE: u8 = 0xD0;
+const PITCH_BEND: u8 = 0xE0;
+
+// Controller numbers we care about.
+const MOD_WHEEL: u8 = 1;
+const DATA_ENTRY: u8 = 6;
+const SLIDE: u8 = 74; // MPE's "third dimension" (Y axis on most controllers)
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+const ALL_SOUND_OFF: u8 = 120;
+const RESET_ALL_CONTROLLERS: u8 = 121;
+pub const ALL_NOTES_OFF: u8 = 123;
+
+// The registered parameter an MPE controller sends to set up its zones.
+const MPE_CONFIGURATION_RPN: u16 = 6;
+const NULL_RPN: u16 = 0x3FFF;
+
+// Pitch bend range in semitones for ordinary channels and MPE master channels. MPE member
+// channels use the "MPE bend range" parameter instead.
+const MASTER_BEND_RANGE: f32 = 2.0;
+const DEFAULT_SLIDE: f32 = 0.5;
+
+// What the audio engine gets to see of the incoming MIDI, once the channel mode messages have been
+// dealt with.
+//
+// Outside of MPE mode every channel is treated as one (channel 0), so the engine can always key
+// voices by (channel, note).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoteEvent {
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8 },
+    // Cut every voice right now, without a release.
+    AllSoundOff,
+    // The total bend for notes on this channel, including any zone-wide bend from an MPE master
+    // channel.
+    PitchBend { channel: u8, semitones: f32 },
+    Pressure { channel: u8, value: f32 }, // 0.0 - 1.0
+    Slide { channel: u8, value: f32 },    // 0.0 - 1.0
+    // The mod wheel is for every note, whichever channel it comes in on.
+    ModWheel { value: f32 }, // 0.0 - 1.0
+    // Polyphonic aftertouch: pressure for one key only.
+    KeyPressure { channel: u8, note: u8, value: f32 },
+    // From the step sequencer: this note plays at a fixed pulse width instead of the parameter.
+    PulseWidthLock { channel: u8, note: u8, value: f32 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TimedEvent {
+    pub delta_frames: usize,
+    pub event: NoteEvent,
+}
 
 pub struct MidiInputProcessor {
-    notes: BTreeSet<u8>,
+    params: Arc<Parameters>,
+    notes: BTreeSet<(u8, u8)>, // (channel, note)
+    // Per channel, so each MPE note keeps its own expression.
+    pitch_bend: [f32; 16], // -1.0 - 1.0
+    pressure: [f32; 16],
+    slide: [f32; 16],
+    mod_wheel: f32,
+    rpn: [u16; 16],
+    // Zones set up by an MPE configuration message, along with the MPE parameters as they were
+    // then. These stay in here rather than going into the parameters, since the host never hears
+    // about parameter changes made from the audio thread. They're dropped again as soon as
+    // someone changes the parameters.
+    configured_zones: Option<(MpeZones, [f32; 3])>,
+    events: Vec<TimedEvent>,
+    delta_frames: usize,
 }
 
 impl MidiInputProcessor {
-    pub fn new() -> Self {
+    pub fn new(params: Arc<Parameters>) -> Self {
         Self {
+            params,
             notes: BTreeSet::new(),
+            pitch_bend: [0.0; 16],
+            pressure: [0.0; 16],
+            slide: [DEFAULT_SLIDE; 16],
+            mod_wheel: 0.0,
+            rpn: [NULL_RPN; 16],
+            configured_zones: None,
+            events: Vec::with_capacity(1024),
+            delta_frames: 0,
         }
     }
 
-    pub fn process_midi_event(&mut self, event_data: [u8; 3]) {
-        match event_data[0] {
-            128 => self.note_off(event_data[1]),
-            144 => self.note_on(event_data[1]),
+    pub fn process_midi_event(&mut self, event_data: [u8; 3], delta_frames: usize) {
+        self.delta_frames = delta_frames;
+        if let Some((_, parameters)) = self.configured_zones {
+            if parameters != self.mpe_parameters() {
+                info!("MPE parameters changed, going back to their zones");
+                self.configured_zones = None;
+            }
+        }
+
+        let channel = if self.mpe_zones().is_some() {
+            event_data[0] & 0x0F
+        } else {
+            0
+        };
+
+        match event_data[0] & 0xF0 {
+            NOTE_OFF => self.note_off(channel, event_data[1]),
+            NOTE_ON if event_data[2] == 0 => self.note_off(channel, event_data[1]),
+            NOTE_ON => self.note_on(channel, event_data[1], event_data[2]),
+            POLY_PRESSURE => self.push_event(NoteEvent::KeyPressure {
+                channel,
+                note: event_data[1],
+                value: event_data[2] as f32 / 127.0,
+            }),
+            CONTROL_CHANGE => self.control_change(event_data[0] & 0x0F, channel, event_data[1], event_data[2]),
+            CHANNEL_PRESSURE => self.set_pressure(channel, event_data[1] as f32 / 127.0),
+            PITCH_BEND => {
+                let value = (event_data[1] as u16 | (event_data[2] as u16) << 7) as f32;
+                self.set_pitch_bend(channel, (value - 8192.0) / 8192.0);
+            }
             _ => (),
         }
     }
 
-    fn note_on(&mut self, index: u8) {
-        self.notes.insert(index);
+    // Events for the current block, in order. Call `clear_events()` once they've been processed.
+    pub fn events(&self) -> &[TimedEvent] {
+        &self.events
+    }
+
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+
+    fn push_event(&mut self, event: NoteEvent) {
+        self.events.push(TimedEvent {
+            delta_frames: self.delta_frames,
+            event,
+        });
     }
 
-    fn note_off(&mut self, index: u8) {
-        self.notes.remove(&index);
+    fn mpe_parameters(&self) -> [f32; 3] {
+        [
+            self.params.mpe_mode.get(),
+            self.params.mpe_lower_zone.get(),
+            self.params.mpe_upper_zone.get(),
+        ]
     }
 
-    pub fn get_active_notes(&self) -> Vec<u8> {
-        let mut active_notes = Vec::new(); // TODO: Might not want to use Vec
-        for note in &self.notes {
-            active_notes.push(*note);
+    // The zone layout in use, or `None` outside of MPE mode.
+    fn mpe_zones(&self) -> Option<MpeZones> {
+        match self.configured_zones {
+            Some((zones, _)) if zones.is_empty() => None,
+            Some((zones, _)) => Some(zones),
+            None if choice_index(self.params.mpe_mode.get(), 2) == 1 => Some(MpeZones::from_parameters(&self.params)),
+            None => None,
         }
-        active_notes
+    }
+
+    fn note_on(&mut self, channel: u8, index: u8, velocity: u8) {
+        self.notes.insert((channel, index));
+        self.push_event(NoteEvent::NoteOn { channel, note: index, velocity });
+    }
+
+    fn note_off(&mut self, channel: u8, index: u8) {
+        if self.notes.remove(&(channel, index)) {
+            self.push_event(NoteEvent::NoteOff { channel, note: index });
+        }
+    }
+
+    // `midi_channel` is the channel the message actually came in on, which the MPE configuration
+    // message needs even when we're not in MPE mode yet.
+    fn control_change(&mut self, midi_channel: u8, channel: u8, controller: u8, value: u8) {
+        match controller {
+            MOD_WHEEL => self.set_mod_wheel(value as f32 / 127.0),
+            SLIDE => self.set_slide(channel, value as f32 / 127.0),
+            RPN_MSB => {
+                let rpn = &mut self.rpn[midi_channel as usize];
+                *rpn = (*rpn & 0x7F) | (value as u16) << 7;
+            }
+            RPN_LSB => {
+                let rpn = &mut self.rpn[midi_channel as usize];
+                *rpn = (*rpn & !0x7F) | value as u16;
+            }
+            DATA_ENTRY => {
+                if self.rpn[midi_channel as usize] == MPE_CONFIGURATION_RPN {
+                    self.configure_mpe_zone(midi_channel, value);
+                }
+            }
+            ALL_SOUND_OFF => self.all_sound_off(),
+            RESET_ALL_CONTROLLERS => self.reset_all_controllers(midi_channel, channel),
+            ALL_NOTES_OFF => self.all_notes_off(),
+            _ => (),
+        }
+    }
+
+    // An MPE configuration message: sent on channel 1 for the lower zone or channel 16 for the
+    // upper zone, with the number of member channels. It also switches MPE mode on (or off, if
+    // both zones end up empty).
+    fn configure_mpe_zone(&mut self, midi_channel: u8, member_channels: u8) {
+        if midi_channel != 0 && midi_channel != 15 {
+            return;
+        }
+        info!("MPE configuration: channel {} has {} member channels", midi_channel + 1, member_channels);
+        let mut zones = match self.configured_zones {
+            Some((zones, _)) => zones,
+            None => MpeZones::from_parameters(&self.params),
+        };
+        zones.set_members(midi_channel, member_channels);
+        self.configured_zones = Some((zones, self.mpe_parameters()));
+    }
+
+    fn set_pitch_bend(&mut self, channel: u8, bend: f32) {
+        self.pitch_bend[channel as usize] = bend;
+        self.push_pitch_bend(channel);
+
+        // A master channel bends every note in its zone.
+        if let Some(zones) = self.mpe_zones() {
+            if zones.is_master(channel) {
+                for member in zones.members(channel) {
+                    self.push_pitch_bend(member);
+                }
+            }
+        }
+    }
+
+    fn push_pitch_bend(&mut self, channel: u8) {
+        let own_bend = self.pitch_bend[channel as usize];
+        let mut semitones = own_bend * MASTER_BEND_RANGE;
+
+        if let Some(zones) = self.mpe_zones() {
+            if let Some(master) = zones.master_of(channel) {
+                let member_range = mpe_bend_range(self.params.mpe_bend_range.get());
+                semitones = own_bend * member_range + self.pitch_bend[master as usize] * MASTER_BEND_RANGE;
+            }
+        }
+
+        self.push_event(NoteEvent::PitchBend { channel, semitones });
+    }
+
+    fn set_pressure(&mut self, channel: u8, value: f32) {
+        self.pressure[channel as usize] = value;
+        self.push_event(NoteEvent::Pressure { channel, value });
+    }
+
+    fn set_slide(&mut self, channel: u8, value: f32) {
+        self.slide[channel as usize] = value;
+        self.push_event(NoteEvent::Slide { channel, value });
+    }
+
+    fn set_mod_wheel(&mut self, value: f32) {
+        self.mod_wheel = value;
+        self.push_event(NoteEvent::ModWheel { value });
+    }
+
+    // Like letting go of every key: notes still go through the normal note-off path.
+    fn all_notes_off(&mut self) {
+        let held: Vec<(u8, u8)> = self.notes.iter().cloned().collect();
+        for (channel, note) in held {
+            self.note_off(channel, note);
+        }
+    }
+
+    // Silence right now.
+    fn all_sound_off(&mut self) {
+        self.notes.clear();
+        self.push_event(NoteEvent::AllSoundOff);
+    }
+
+    // The expression is kept per mapped channel, but the RPN is selected per MIDI channel.
+    fn reset_all_controllers(&mut self, midi_channel: u8, channel: u8) {
+        if self.pitch_bend[channel as usize] != 0.0 {
+            self.set_pitch_bend(channel, 0.0);
+        }
+        if self.pressure[channel as usize] != 0.0 {
+            self.set_pressure(channel, 0.0);
+        }
+        if self.slide[channel as usize] != DEFAULT_SLIDE {
+            self.set_slide(channel, DEFAULT_SLIDE);
+        }
+        if self.mod_wheel != 0.0 {
+            self.set_mod_wheel(0.0);
+        }
+        self.rpn[midi_channel as usize] = NULL_RPN;
+    }
+
+    // Lets go of every note through the normal note-off path, so they get their release. For when
+    // the host may have dropped some note-offs, like while the plugin was bypassed.
+    pub fn release_all(&mut self) {
+        self.delta_frames = 0;
+        self.all_notes_off();
+    }
+
+    // What the editor's "panic" action does: kill every sound and put the controllers back
+    // to their defaults so nothing gets stuck on again.
+    pub fn panic(&mut self) {
+        // Anything else that came in this block would only start notes back up.
+        self.events.clear();
+        self.delta_frames = 0;
+        self.all_sound_off();
+        for channel in 0..16 {
+            self.reset_all_controllers(channel, channel);
+        }
+    }
+}
+
+// Semitones of bend for MPE member channels. MPE's default is 48.
+pub fn mpe_bend_range(value: f32) -> f32 {
+    const MAX_BEND_RANGE: f32 = 96.0;
+
+    (value * MAX_BEND_RANGE).round()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configure_lower_zone(processor: &mut MidiInputProcessor, member_channels: u8) {
+        processor.process_midi_event([CONTROL_CHANGE, RPN_MSB, 0], 0);
+        processor.process_midi_event([CONTROL_CHANGE, RPN_LSB, MPE_CONFIGURATION_RPN as u8], 0);
+        processor.process_midi_event([CONTROL_CHANGE, DATA_ENTRY, member_channels], 0);
+    }
+
+    #[test]
+    fn mpe_configuration_switches_mpe_on_without_touching_parameters() {
+        let params = Arc::new(Parameters::new());
+        let mut processor = MidiInputProcessor::new(params.clone());
+        configure_lower_zone(&mut processor, 15);
+        processor.process_midi_event([NOTE_ON | 3, 60, 100], 0);
+
+        assert_eq!(params.mpe_mode.get(), 0.0);
+        assert_eq!(
+            processor.events().last().unwrap().event,
+            NoteEvent::NoteOn { channel: 3, note: 60, velocity: 100 }
+        );
+    }
+
+    #[test]
+    fn changing_mpe_parameters_drops_configured_zones() {
+        let params = Arc::new(Parameters::new());
+        let mut processor = MidiInputProcessor::new(params.clone());
+        configure_lower_zone(&mut processor, 15);
+        params.mpe_bend_range.set(1.0);
+        processor.process_midi_event([NOTE_ON | 3, 60, 100], 0);
+        params.mpe_lower_zone.set(0.5);
+        processor.process_midi_event([NOTE_ON | 4, 62, 100], 0);
+
+        let channels: Vec<u8> = processor
+            .events()
+            .iter()
+            .filter_map(|event| match event.event {
+                NoteEvent::NoteOn { channel, .. } => Some(channel),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(channels, vec![3, 0]);
+    }
+
+    #[test]
+    fn reset_all_controllers_deselects_the_rpn_on_its_own_channel() {
+        let params = Arc::new(Parameters::new());
+        let mut processor = MidiInputProcessor::new(params);
+        let upper_zone = CONTROL_CHANGE | 15;
+        processor.process_midi_event([upper_zone, RPN_MSB, 0], 0);
+        processor.process_midi_event([upper_zone, RPN_LSB, MPE_CONFIGURATION_RPN as u8], 0);
+        processor.process_midi_event([upper_zone, RESET_ALL_CONTROLLERS, 0], 0);
+        processor.process_midi_event([upper_zone, DATA_ENTRY, 15], 0);
+        processor.process_midi_event([NOTE_ON | 3, 60, 100], 0);
+
+        assert_eq!(
+            processor.events().last().unwrap().event,
+            NoteEvent::NoteOn { channel: 0, note: 60, velocity: 100 }
+        );
     }
 }
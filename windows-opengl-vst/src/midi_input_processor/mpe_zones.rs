@@ -0,0 +1,65 @@
+use std::ops::RangeInclusive;
+
+use crate::parameters::{choice_index, Parameters};
+
+// Which channels belong to which MPE zone. The lower zone's master channel is channel 1 (0 here)
+// with member channels counting up from 2; the upper zone's master is channel 16 (15 here) with
+// member channels counting down from 15. Channels outside both zones act like ordinary channels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MpeZones {
+    lower_members: u8,
+    upper_members: u8,
+}
+
+impl MpeZones {
+    pub fn from_parameters(params: &Parameters) -> Self {
+        let lower_members = choice_index(params.mpe_lower_zone.get(), 16) as u8;
+        let upper_members = choice_index(params.mpe_upper_zone.get(), 16) as u8;
+
+        // If the zones would overlap, the lower zone wins.
+        Self {
+            lower_members,
+            upper_members: upper_members.min(14u8.saturating_sub(lower_members)),
+        }
+    }
+
+    // For MPE configuration messages. Like the spec says, a zone that grows into the other one
+    // shrinks the other one to make room.
+    pub fn set_members(&mut self, master: u8, members: u8) {
+        match master {
+            0 => {
+                self.lower_members = members.min(15);
+                self.upper_members = self.upper_members.min(14u8.saturating_sub(self.lower_members));
+            }
+            _ => {
+                self.upper_members = members.min(14);
+                self.lower_members = self.lower_members.min(14 - self.upper_members);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lower_members == 0 && self.upper_members == 0
+    }
+
+    pub fn is_master(&self, channel: u8) -> bool {
+        (channel == 0 && self.lower_members > 0) || (channel == 15 && self.upper_members > 0)
+    }
+
+    pub fn master_of(&self, channel: u8) -> Option<u8> {
+        if channel >= 1 && channel <= self.lower_members {
+            Some(0)
+        } else if channel < 15 && channel >= 15 - self.upper_members {
+            Some(15)
+        } else {
+            None
+        }
+    }
+
+    pub fn members(&self, master: u8) -> RangeInclusive<u8> {
+        match master {
+            0 => 1..=self.lower_members,
+            _ => 15 - self.upper_members..=14,
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use vst::buffer::SendEventBuffer;
+use vst::event::{Event, MidiEvent, SysExEvent};
+use vst::host::Host;
+
+use crate::midi_input_processor::{NoteEvent, TimedEvent, ALL_NOTES_OFF, CONTROL_CHANGE, NOTE_OFF, NOTE_ON};
+use crate::parameters::{choice_index, Parameters};
+
+// MIDI going back out to the host, so other tracks can play along with the arpeggiator and the
+// sequencer. Depending on the "MIDI out" parameter, that's either:
+//  - Thru: the incoming MIDI, exactly as it came in, or
+//  - Arp/Seq: the notes the synth ends up playing (so the arpeggiator and sequencer output, plus
+//    whatever's played straight through them). Only notes: expression stays with the synth.
+//
+// SysEx patch dumps go out whatever the mode is, since they're only sent when asked for.
+
+const MAX_EVENTS: usize = 1024;
+const NOTE_OFF_VELOCITY: u8 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MidiOutMode {
+    Off,
+    Thru,
+    Notes,
+}
+
+impl MidiOutMode {
+    pub fn from_parameter(value: f32) -> Self {
+        match choice_index(value, 3) {
+            0 => MidiOutMode::Off,
+            1 => MidiOutMode::Thru,
+            _ => MidiOutMode::Notes,
+        }
+    }
+}
+
+pub struct MidiOutput {
+    params: Arc<Parameters>,
+    events: Vec<MidiEvent>,
+    sysex: Option<Vec<u8>>,
+    // The host can still be reading the last SysEx sent until the next block, so it's kept until
+    // then.
+    sent_sysex: Option<Vec<u8>>,
+    send_buffer: SendEventBuffer,
+}
+
+impl MidiOutput {
+    pub fn new(params: Arc<Parameters>) -> Self {
+        Self {
+            params,
+            events: Vec::with_capacity(MAX_EVENTS),
+            sysex: None,
+            sent_sysex: None,
+            send_buffer: SendEventBuffer::new(MAX_EVENTS),
+        }
+    }
+
+    fn mode(&self) -> MidiOutMode {
+        MidiOutMode::from_parameter(self.params.midi_out.get())
+    }
+
+    // Incoming MIDI, as the host gave it to us.
+    pub fn thru(&mut self, data: [u8; 3], delta_frames: i32) {
+        if self.mode() == MidiOutMode::Thru {
+            self.push(data, delta_frames);
+        }
+    }
+
+    // The events going into the audio engine.
+    pub fn notes(&mut self, events: &[TimedEvent]) {
+        if self.mode() != MidiOutMode::Notes {
+            return;
+        }
+
+        for event in events {
+            let delta_frames = event.delta_frames as i32;
+            match event.event {
+                NoteEvent::NoteOn { channel, note, velocity } => {
+                    self.push([NOTE_ON | channel, note, velocity], delta_frames);
+                }
+                NoteEvent::NoteOff { channel, note } => {
+                    self.push([NOTE_OFF | channel, note, NOTE_OFF_VELOCITY], delta_frames);
+                }
+                NoteEvent::AllSoundOff => {
+                    for channel in 0..16 {
+                        self.push([CONTROL_CHANGE | channel, ALL_NOTES_OFF, 0], delta_frames);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Goes out at the start of the next block that's sent.
+    pub fn sysex(&mut self, message: Vec<u8>) {
+        self.sysex = Some(message);
+    }
+
+    // Sends everything that's been collected since the last call.
+    pub fn send<H: Host>(&mut self, host: &H) {
+        self.sent_sysex = self.sysex.take();
+        if self.events.is_empty() && self.sent_sysex.is_none() {
+            return;
+        }
+
+        let sysex = self
+            .sent_sysex
+            .as_ref()
+            .map(|payload| Event::SysEx(SysExEvent { payload, delta_frames: 0 }));
+        let midi = self.events.iter().map(|&event| Event::Midi(event));
+        self.send_buffer.store_events(sysex.into_iter().chain(midi));
+        host.process_events(self.send_buffer.events());
+
+        self.events.clear();
+    }
+
+    fn push(&mut self, data: [u8; 3], delta_frames: i32) {
+        if self.events.len() == MAX_EVENTS {
+            return;
+        }
+        self.events.push(MidiEvent {
+            data,
+            delta_frames,
+            live: false,
+            note_length: None,
+            note_offset: None,
+            detune: 0,
+            note_off_velocity: 0,
+        });
+    }
+}
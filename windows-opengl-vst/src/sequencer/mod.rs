@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+mod sequence;
+pub use self::sequence::{Sequence, MAX_STEPS};
+use self::sequence::{step_pitch, step_velocity};
+
+use crate::arpeggiator::RATES;
+use crate::midi_input_processor::{NoteEvent, TimedEvent};
+use crate::parameters::{choice_index, Parameters};
+use crate::transport::Transport;
+use crate::ui_state::UiState;
+
+// Plays the step sequence while the host is playing. Its notes are added to whatever comes in
+// (so you can still play along), on channel 1.
+//
+// Which step plays comes straight from the host's position, so it starts and stops with the host,
+// and jumps back with it at loop points.
+
+const CHANNEL: u8 = 0;
+
+pub struct Sequencer {
+    params: Arc<Parameters>,
+    ui_state: Arc<UiState>,
+    // The step we last started, counting from the start of the song.
+    last_step: Option<i64>,
+    playing: Option<u8>,
+    note_off_beat: f64,
+    sample_rate: f32,
+    // Set by `release_all()`, for the next block to pick up.
+    release_requested: bool,
+    events: Vec<TimedEvent>,
+}
+
+impl Sequencer {
+    pub fn new(params: Arc<Parameters>, ui_state: Arc<UiState>) -> Self {
+        Self {
+            params,
+            ui_state,
+            last_step: None,
+            playing: None,
+            note_off_beat: 0.0,
+            sample_rate: 44100.0,
+            release_requested: false,
+            events: Vec::with_capacity(1024),
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    // Events for the audio engine, for the block that was last processed.
+    pub fn events(&self) -> &[TimedEvent] {
+        &self.events
+    }
+
+    // Stops the note that's playing at the start of the next block, and starts the step over. For
+    // when the host may have dropped some blocks, like while the plugin was bypassed.
+    pub fn release_all(&mut self) {
+        self.release_requested = true;
+    }
+
+    pub fn process(&mut self, input: &[TimedEvent], num_samples: usize, transport: &Transport) {
+        self.events.clear();
+        if self.release_requested {
+            self.release_requested = false;
+            self.stop_note(0);
+            self.last_step = None;
+        }
+
+        let enabled = choice_index(self.params.seq_enabled.get(), 2) == 1;
+        let start = match transport.position {
+            Some(position) if enabled => position,
+            _ => {
+                self.stop_note(0);
+                self.last_step = None;
+                self.ui_state.sequence.set_current_step(None);
+                self.events.extend_from_slice(input);
+                return;
+            }
+        };
+
+        let beats_per_sample = transport.beats_per_sample(self.sample_rate);
+        let step_length = RATES[choice_index(self.params.seq_rate.get(), RATES.len())];
+
+        let mut input = input.iter().peekable();
+        for sample in 0..num_samples {
+            while let Some(event) = input.peek() {
+                if event.delta_frames > sample {
+                    break;
+                }
+                self.events.push(**event);
+                input.next();
+            }
+
+            let beat = start + sample as f64 * beats_per_sample;
+            let step = (beat / step_length).floor() as i64;
+            if self.last_step != Some(step) {
+                self.last_step = Some(step);
+                self.stop_note(sample);
+                self.start_step(sample, step, step_length);
+            } else if self.playing.is_some() && beat >= self.note_off_beat {
+                self.stop_note(sample);
+            }
+        }
+
+        // Anything the host put past the end of the block still counts.
+        self.events.extend(input);
+    }
+
+    fn start_step(&mut self, sample: usize, step: i64, step_length: f64) {
+        let length = if choice_index(self.params.seq_length.get(), 2) == 0 { 16 } else { MAX_STEPS };
+        let index = step.rem_euclid(length as i64) as usize;
+        self.ui_state.sequence.set_current_step(Some(index));
+
+        let sequence_step = self.ui_state.sequence.step(index);
+        let gate = sequence_step.gate.get();
+        if gate <= 0.0 {
+            return;
+        }
+
+        let root = seq_root_note(self.params.seq_root.get()) as i32;
+        let note = (root + step_pitch(sequence_step.pitch.get())).max(0).min(127) as u8;
+        let velocity = step_velocity(sequence_step.velocity.get());
+        let pulse_width = sequence_step.pulse_width.get();
+
+        self.push_event(sample, NoteEvent::NoteOn { channel: CHANNEL, note, velocity });
+        if pulse_width > 0.0 {
+            self.push_event(sample, NoteEvent::PulseWidthLock {
+                channel: CHANNEL,
+                note,
+                value: pulse_width,
+            });
+        }
+        self.playing = Some(note);
+        self.note_off_beat = (step as f64 + gate as f64) * step_length;
+    }
+
+    fn stop_note(&mut self, sample: usize) {
+        if let Some(note) = self.playing.take() {
+            self.push_event(sample, NoteEvent::NoteOff { channel: CHANNEL, note });
+        }
+    }
+
+    fn push_event(&mut self, delta_frames: usize, event: NoteEvent) {
+        self.events.push(TimedEvent { delta_frames, event });
+    }
+}
+
+pub fn seq_root_note(value: f32) -> u8 {
+    (value * 127.0).round() as u8
+}
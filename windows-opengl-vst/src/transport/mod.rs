@@ -0,0 +1,52 @@
+use vst::api::{TimeInfo, TimeInfoFlags};
+use vst::host::Host;
+
+// Tempo used when the host doesn't tell us one.
+const DEFAULT_TEMPO: f64 = 120.0;
+
+// Where the host's transport is at the start of a block, for anything that syncs to tempo.
+#[derive(Clone, Copy, Debug)]
+pub struct Transport {
+    pub tempo: f64, // BPM
+    // In quarter notes. None when the host isn't playing (or won't say), in which case tempo-synced
+    // things run on their own clock.
+    pub position: Option<f64>,
+}
+
+impl Transport {
+    pub fn stopped() -> Self {
+        Self {
+            tempo: DEFAULT_TEMPO,
+            position: None,
+        }
+    }
+
+    pub fn from_host<H: Host>(host: &H) -> Self {
+        let mask = TimeInfoFlags::TEMPO_VALID | TimeInfoFlags::PPQ_POS_VALID;
+        match host.get_time_info(mask.bits()) {
+            Some(time_info) => Self::from_time_info(&time_info),
+            None => Self::stopped(),
+        }
+    }
+
+    pub fn from_time_info(time_info: &TimeInfo) -> Self {
+        let flags = TimeInfoFlags::from_bits_truncate(time_info.flags);
+
+        let tempo = if flags.contains(TimeInfoFlags::TEMPO_VALID) && time_info.tempo > 0.0 {
+            time_info.tempo
+        } else {
+            DEFAULT_TEMPO
+        };
+        let position = if flags.contains(TimeInfoFlags::TRANSPORT_PLAYING | TimeInfoFlags::PPQ_POS_VALID) {
+            Some(time_info.ppq_pos)
+        } else {
+            None
+        };
+
+        Self { tempo, position }
+    }
+
+    pub fn beats_per_sample(&self, sample_rate: f32) -> f64 {
+        self.tempo / 60.0 / sample_rate as f64
+    }
+}
@@ -0,0 +1,174 @@
+// A Scala scale (.scl file). See http://www.huygens-fokker.org/scala/scl_format.html
+//
+//   ! Lines starting with "!" are comments
+//   The first line that isn't a comment is a description
+//    the next is how many notes there are
+//    then one line per note: cents if there's a ".", otherwise a ratio ("3/2", or just "2")
+//
+// The first note of the scale (1/1) is implied, and the last one is the period that the scale
+// repeats at (usually 2/1, an octave).
+
+#[derive(Clone)]
+pub struct Scale {
+    description: String,
+    cents: Vec<f64>, // Degrees 1 and up. The last one is the period.
+    // The file this came from, so it can be saved with the plugin state. None for 12-TET.
+    source: Option<String>,
+}
+
+impl Scale {
+    // Standard 12-tone equal temperament.
+    pub fn equal_temperament() -> Self {
+        Self {
+            description: format!("12-tone equal temperament"),
+            cents: (1..=12).map(|degree| degree as f64 * 100.0).collect(),
+            source: None,
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut lines = source.lines().filter(|line| !line.starts_with('!'));
+
+        let description = match lines.next() {
+            Some(line) => line.trim().to_string(),
+            None => return Err(format!("missing description")),
+        };
+
+        let count = match lines.next().and_then(first_token) {
+            Some(token) => token
+                .parse::<usize>()
+                .map_err(|_| format!("bad note count {:?}", token))?,
+            None => return Err(format!("missing note count")),
+        };
+        if count == 0 {
+            return Err(format!("scale has no notes"));
+        }
+
+        let mut cents = Vec::with_capacity(count);
+        for token in lines.filter_map(first_token).take(count) {
+            cents.push(parse_pitch(token)?);
+        }
+        if cents.len() < count {
+            return Err(format!("expected {} notes, found {}", count, cents.len()));
+        }
+
+        Ok(Self {
+            description,
+            cents,
+            source: Some(source.to_string()),
+        })
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_ref().map(|source| source.as_str())
+    }
+
+    // Number of notes per period.
+    pub fn size(&self) -> i32 {
+        self.cents.len() as i32
+    }
+
+    // Cents above the root for any scale degree, including negative ones and ones past the
+    // period.
+    pub fn cents(&self, degree: i32) -> f64 {
+        let period = self.cents[self.cents.len() - 1];
+        let periods = degree.div_euclid(self.size());
+        let step = degree.rem_euclid(self.size());
+
+        let within_period = if step == 0 {
+            0.0
+        } else {
+            self.cents[step as usize - 1]
+        };
+        periods as f64 * period + within_period
+    }
+}
+
+fn first_token(line: &str) -> Option<&str> {
+    line.split_whitespace().next()
+}
+
+fn parse_pitch(token: &str) -> Result<f64, String> {
+    if token.contains('.') {
+        return token
+            .parse::<f64>()
+            .map_err(|_| format!("bad cents value {:?}", token));
+    }
+
+    let (numerator, denominator) = match token.find('/') {
+        Some(slash) => (&token[..slash], &token[slash + 1..]),
+        None => (token, "1"),
+    };
+    let numerator = numerator.parse::<u64>().map_err(|_| format!("bad ratio {:?}", token))?;
+    let denominator = denominator.parse::<u64>().map_err(|_| format!("bad ratio {:?}", token))?;
+    if numerator == 0 || denominator == 0 {
+        return Err(format!("bad ratio {:?}", token));
+    }
+
+    Ok(1200.0 * (numerator as f64 / denominator as f64).log2())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn twelve_tone_equal_temperament() {
+        let source = "12-TET\n12\n100.0\n200.\n300.0\n400.0\n500.0\n600.0\n700.0\n800.0\n900.0\n1000.0\n1100.0\n2/1\n";
+        let scale = Scale::parse(source).unwrap();
+        let reference = Scale::equal_temperament();
+        assert_eq!(scale.size(), 12);
+        for degree in -24..24 {
+            assert_close(scale.cents(degree), reference.cents(degree));
+        }
+    }
+
+    #[test]
+    fn just_intonation_with_ratios_cents_and_comments() {
+        let source = "! ji.scl\n!\nJust major, with a tempered seventh\n 7\n!\n 9/8\n 5/4 the major third\n 4/3\n 3/2\n 5/3\n 1088.268715\n 2\n";
+        let scale = Scale::parse(source).unwrap();
+        assert_eq!(scale.description(), "Just major, with a tempered seventh");
+        assert_eq!(scale.size(), 7);
+        assert_close(scale.cents(0), 0.0);
+        assert_close(scale.cents(2), 1200.0 * (5.0f64 / 4.0).log2());
+        assert_close(scale.cents(6), 1088.268715);
+        assert_close(scale.cents(7), 1200.0);
+        assert_close(scale.cents(-1), 1088.268715 - 1200.0);
+    }
+
+    #[test]
+    fn period_other_than_an_octave() {
+        // Bohlen-Pierce: 13 equal steps of a tritave.
+        let mut source = String::from("Bohlen-Pierce\n13\n");
+        for step in 1..13 {
+            source.push_str(&format!("{:.6}\n", step as f64 * 1200.0 * 3.0f64.log2() / 13.0));
+        }
+        source.push_str("3/1\n");
+        let scale = Scale::parse(&source).unwrap();
+        let tritave = 1200.0 * 3.0f64.log2();
+        assert_close(scale.cents(13), tritave);
+        assert_close(scale.cents(26), 2.0 * tritave);
+    }
+
+    #[test]
+    fn malformed_scales_are_rejected() {
+        assert!(Scale::parse("").is_err());
+        assert!(Scale::parse("! just a comment\n").is_err());
+        assert!(Scale::parse("No count\n").is_err());
+        assert!(Scale::parse("Bad count\nseven\n").is_err());
+        assert!(Scale::parse("Empty\n0\n").is_err());
+        assert!(Scale::parse("Short\n3\n9/8\n5/4\n").is_err());
+        assert!(Scale::parse("Bad ratio\n1\n3/0\n").is_err());
+        assert!(Scale::parse("Zero ratio\n1\n0\n").is_err());
+        assert!(Scale::parse("Bad cents\n1\n1.2.3\n").is_err());
+        assert!(Scale::parse("Junk\n1\nfoo\n").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::midi_learn::MidiLearn;
+use crate::mod_matrix::ModMatrix;
+use crate::sample_map::SampleMap;
+use crate::sequencer::Sequence;
+use crate::sysex::MAX_DUMP_LENGTH;
+use crate::tuning::{Tuning, TuningChange};
+use crate::wavetable::Wavetable;
+
+// Things the editor and the audio thread need to tell each other that aren't host-automatable
+// parameters (those live in `Parameters`).
+pub struct UiState {
+    pub midi_learn: MidiLearn,
+    pub mod_matrix: ModMatrix,
+    pub sequence: Sequence,
+    panic_requested: AtomicBool,
+    sysex_dump: Mutex<Option<Vec<u8>>>,
+    // Allocated up front, so the audio thread can copy incoming dumps in without allocating.
+    received_sysex_dump: Mutex<Vec<u8>>,
+    sysex_dump_received: AtomicBool,
+    clipping: AtomicBool,
+    // The tuning, wavetable and samples in use, for patch dumps (the audio thread's own are off
+    // limits). Whatever hands the audio thread new ones keeps these up to date.
+    tuning: Mutex<Tuning>,
+    wavetable: Mutex<Option<Arc<Wavetable>>>,
+    sample_map: Mutex<Option<Arc<SampleMap>>>,
+    tuning_change: Mutex<Option<Tuning>>,
+    wavetable_change: Mutex<Option<Arc<Wavetable>>>,
+    sample_map_change: Mutex<Option<Arc<SampleMap>>>,
+}
+
+impl UiState {
+    pub fn new() -> Self {
+        Self {
+            midi_learn: MidiLearn::new(),
+            mod_matrix: ModMatrix::new(),
+            sequence: Sequence::new(),
+            panic_requested: AtomicBool::new(false),
+            sysex_dump: Mutex::new(None),
+            received_sysex_dump: Mutex::new(Vec::with_capacity(MAX_DUMP_LENGTH)),
+            sysex_dump_received: AtomicBool::new(false),
+            clipping: AtomicBool::new(false),
+            tuning: Mutex::new(Tuning::new()),
+            wavetable: Mutex::new(None),
+            sample_map: Mutex::new(None),
+            tuning_change: Mutex::new(None),
+            wavetable_change: Mutex::new(None),
+            sample_map_change: Mutex::new(None),
+        }
+    }
+
+    pub fn request_panic(&self) {
+        self.panic_requested.store(true, Ordering::Relaxed);
+    }
+
+    // Returns true once per request, so the audio thread only handles each panic once.
+    pub fn take_panic_request(&self) -> bool {
+        self.panic_requested.swap(false, Ordering::Relaxed)
+    }
+
+    // A patch dump the editor has built, to send out. The audio thread does the sending, since
+    // MIDI can only go to the host from `process()`.
+    pub fn request_sysex_dump(&self, message: Vec<u8>) {
+        *self.sysex_dump.lock().unwrap() = Some(message);
+    }
+
+    pub fn take_sysex_dump(&self) -> Option<Vec<u8>> {
+        self.sysex_dump.try_lock().ok()?.take()
+    }
+
+    // A patch dump that came in, for `DumpLoader` to pick up. Never blocks or allocates, so the
+    // audio thread can call it; returns false if the dump was too big or the loader had the
+    // buffer, and it's been dropped.
+    pub fn receive_sysex_dump(&self, message: &[u8]) -> bool {
+        if message.len() > MAX_DUMP_LENGTH {
+            return false;
+        }
+        let mut buffer = match self.received_sysex_dump.try_lock() {
+            Ok(buffer) => buffer,
+            Err(_) => return false,
+        };
+        buffer.clear();
+        buffer.extend_from_slice(message);
+        self.sysex_dump_received.store(true, Ordering::Release);
+        true
+    }
+
+    pub fn take_received_sysex_dump(&self) -> Option<Vec<u8>> {
+        if !self.sysex_dump_received.swap(false, Ordering::Acquire) {
+            return None;
+        }
+        Some(self.received_sysex_dump.lock().unwrap().clone())
+    }
+
+    // For the editor's clip light. The audio thread keeps it lit for a while after each clip, so
+    // the editor doesn't miss short ones between redraws.
+    pub fn set_clipping(&self, clipping: bool) {
+        self.clipping.store(clipping, Ordering::Relaxed);
+    }
+
+    pub fn is_clipping(&self) -> bool {
+        self.clipping.load(Ordering::Relaxed)
+    }
+
+    // Tuning files are parsed on the editor side, then the whole tuning is handed over.
+    pub fn request_tuning_change(&self, change: TuningChange) {
+        let mut tuning = self.tuning.lock().unwrap();
+        tuning.apply(change);
+        *self.tuning_change.lock().unwrap() = Some(tuning.clone());
+    }
+
+    // For a patch dump's tuning, which replaces the lot.
+    pub fn request_tuning(&self, tuning: Tuning) {
+        let mut current = self.tuning.lock().unwrap();
+        *self.tuning_change.lock().unwrap() = Some(tuning.clone());
+        *current = tuning;
+    }
+
+    // Never blocks: if the editor happens to hold the lock, we'll get it next block.
+    pub fn take_tuning_change(&self) -> Option<Tuning> {
+        self.tuning_change.try_lock().ok()?.take()
+    }
+
+    // Same for wavetables, which are read and mip-mapped on the editor side too.
+    pub fn request_wavetable_change(&self, wavetable: Wavetable) {
+        let wavetable = Arc::new(wavetable);
+        *self.wavetable_change.lock().unwrap() = Some(wavetable.clone());
+        *self.wavetable.lock().unwrap() = Some(wavetable);
+    }
+
+    pub fn take_wavetable_change(&self) -> Option<Arc<Wavetable>> {
+        self.wavetable_change.try_lock().ok()?.take()
+    }
+
+    // And for the sampler's samples.
+    pub fn request_sample_map_change(&self, map: SampleMap) {
+        let map = Arc::new(map);
+        *self.sample_map_change.lock().unwrap() = Some(map.clone());
+        *self.sample_map.lock().unwrap() = Some(map);
+    }
+
+    pub fn take_sample_map_change(&self) -> Option<Arc<SampleMap>> {
+        self.sample_map_change.try_lock().ok()?.take()
+    }
+
+    // For when the audio thread's been given new ones directly, like when the host loads a state.
+    pub fn set_files(&self, tuning: Tuning, wavetable: Option<Arc<Wavetable>>, sample_map: Option<Arc<SampleMap>>) {
+        *self.tuning.lock().unwrap() = tuning;
+        *self.wavetable.lock().unwrap() = wavetable;
+        *self.sample_map.lock().unwrap() = sample_map;
+    }
+
+    pub fn tuning(&self) -> Tuning {
+        self.tuning.lock().unwrap().clone()
+    }
+
+    pub fn wavetable(&self) -> Option<Arc<Wavetable>> {
+        self.wavetable.lock().unwrap().clone()
+    }
+
+    pub fn sample_map(&self) -> Option<Arc<SampleMap>> {
+        self.sample_map.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,168 @@
+use log::*;
+
+mod fft;
+
+use crate::audio_file;
+use crate::chunk::{ByteReader, ChunkWriter};
+
+// A wavetable loaded from a WAV (or AIFF) file: one or more single-cycle frames that the wavetable
+// oscillator scans through. Each frame is resampled to TABLE_SIZE and then band-limited into a
+// mip-map, one level per octave, so high notes don't alias.
+
+pub const TABLE_SIZE: usize = 2048;
+pub const MAX_FRAMES: usize = 256;
+// Level 0 has every harmonic the table can hold (TABLE_SIZE / 2), and each level after that has
+// half as many, down to just the fundamental.
+const NUM_LEVELS: usize = 11;
+// Files at least this long without a "clm " chunk are taken to be frames of TABLE_SIZE. Anything
+// shorter is a single cycle.
+const MIN_MULTI_FRAME_LENGTH: usize = TABLE_SIZE * 2;
+
+pub struct Wavetable {
+    name: String,
+    // The whole file, which is what goes in the plugin state.
+    source: Vec<u8>,
+    num_frames: usize,
+    // TABLE_SIZE samples for each level of each frame, frame by frame.
+    levels: Vec<f32>,
+}
+
+impl Wavetable {
+    pub fn from_file(name: &str, data: Vec<u8>) -> Result<Self, String> {
+        let file = audio_file::read(&data)?;
+        // Only the first channel of a stereo file.
+        let samples = &file.channels[0];
+        let frame_size = match file.frame_size {
+            Some(frame_size) => frame_size,
+            None if samples.len() >= MIN_MULTI_FRAME_LENGTH && samples.len() % TABLE_SIZE == 0 => TABLE_SIZE,
+            None => samples.len(),
+        };
+        let mut num_frames = (samples.len() / frame_size).max(1);
+        if num_frames > MAX_FRAMES {
+            warn!("Wavetable {:?} has {} frames, only using the first {}", name, num_frames, MAX_FRAMES);
+            num_frames = MAX_FRAMES;
+        }
+
+        let mut levels = Vec::with_capacity(num_frames * NUM_LEVELS * TABLE_SIZE);
+        for frame in samples.chunks(frame_size).take(num_frames) {
+            build_levels(&resample(frame), &mut levels);
+        }
+        normalize(&mut levels);
+
+        info!("Loaded wavetable {:?}: {} frame(s) of {} samples", name, num_frames, frame_size);
+        Ok(Self {
+            name: name.to_string(),
+            source: data,
+            num_frames,
+            levels,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // `phase` is 0.0 - 1.0 through the cycle, and `position` 0.0 - 1.0 through the frames. The
+    // level is picked so nothing in it goes past Nyquist at `frequency`.
+    pub fn sample(&self, phase: f64, position: f64, frequency: f64, sample_rate: f32) -> f64 {
+        let level = level_for(frequency, sample_rate);
+
+        let position = position.max(0.0).min(1.0) * (self.num_frames - 1) as f64;
+        let frame = (position as usize).min(self.num_frames - 1);
+        let next_frame = (frame + 1).min(self.num_frames - 1);
+        let blend = position - frame as f64;
+
+        let a = self.lookup(frame, level, phase);
+        let b = self.lookup(next_frame, level, phase);
+        a + (b - a) * blend
+    }
+
+    fn lookup(&self, frame: usize, level: usize, phase: f64) -> f64 {
+        let table = &self.levels[(frame * NUM_LEVELS + level) * TABLE_SIZE..][..TABLE_SIZE];
+        let index = phase.fract().abs() * TABLE_SIZE as f64;
+        let i = index as usize % TABLE_SIZE;
+        let a = table[i] as f64;
+        let b = table[(i + 1) % TABLE_SIZE] as f64;
+        a + (b - a) * index.fract()
+    }
+
+    // The file itself goes in the chunk (like the tuning files), so a project still has its
+    // wavetable on a machine that doesn't have the file.
+    pub fn write_chunk(&self, chunk: &mut ChunkWriter) {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(self.name.len() as u32).to_le_bytes());
+        payload.extend_from_slice(self.name.as_bytes());
+        payload.extend_from_slice(&(self.source.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&self.source);
+        chunk.section(b"WTBL", &payload);
+    }
+
+    pub fn read_chunk(payload: &[u8]) -> Option<Self> {
+        let mut reader = ByteReader::new(payload);
+        let length = reader.u32()? as usize;
+        let name = String::from_utf8_lossy(reader.bytes(length)?).into_owned();
+        let length = reader.u32()? as usize;
+        let data = reader.bytes(length)?.to_vec();
+        match Self::from_file(&name, data) {
+            Ok(wavetable) => Some(wavetable),
+            Err(error) => {
+                warn!("Ignoring saved wavetable {:?}: {}", name, error);
+                None
+            }
+        }
+    }
+}
+
+fn level_for(frequency: f64, sample_rate: f32) -> usize {
+    let harmonics = sample_rate as f64 / 2.0 / frequency.max(1.0);
+    let full = (TABLE_SIZE / 2) as f64;
+    if harmonics >= full {
+        return 0;
+    }
+    ((full / harmonics.max(1.0)).log2().ceil() as usize).min(NUM_LEVELS - 1)
+}
+
+// Stretches (or squashes) one cycle to TABLE_SIZE samples. Anything this puts above the table's
+// harmonics is taken out again by the band-limiting.
+fn resample(frame: &[f32]) -> Vec<f64> {
+    (0..TABLE_SIZE)
+        .map(|i| {
+            let index = i as f64 * frame.len() as f64 / TABLE_SIZE as f64;
+            let j = index as usize;
+            let a = frame[j] as f64;
+            let b = frame[(j + 1) % frame.len()] as f64;
+            a + (b - a) * index.fract()
+        })
+        .collect()
+}
+
+fn build_levels(frame: &[f64], levels: &mut Vec<f32>) {
+    let mut spectrum_real = frame.to_vec();
+    let mut spectrum_imaginary = vec![0.0; TABLE_SIZE];
+    fft::fft(&mut spectrum_real, &mut spectrum_imaginary, false);
+
+    let mut real = vec![0.0; TABLE_SIZE];
+    let mut imaginary = vec![0.0; TABLE_SIZE];
+    for level in 0..NUM_LEVELS {
+        let harmonics = (TABLE_SIZE / 2) >> level;
+        // Keep harmonics 1 to `harmonics`, positive and negative, and drop any DC.
+        for bin in 0..TABLE_SIZE {
+            let harmonic = bin.min(TABLE_SIZE - bin);
+            let keep = harmonic >= 1 && harmonic <= harmonics;
+            real[bin] = if keep { spectrum_real[bin] } else { 0.0 };
+            imaginary[bin] = if keep { spectrum_imaginary[bin] } else { 0.0 };
+        }
+        fft::fft(&mut real, &mut imaginary, true);
+        levels.extend(real.iter().map(|value| (value / TABLE_SIZE as f64) as f32));
+    }
+}
+
+// Scales the whole table, rather than each frame, so quiet frames stay quiet.
+fn normalize(levels: &mut [f32]) {
+    let peak = levels.iter().fold(0.0f32, |peak, value| peak.max(value.abs()));
+    if peak > 0.0 {
+        for value in levels.iter_mut() {
+            *value /= peak;
+        }
+    }
+}